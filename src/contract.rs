@@ -9,17 +9,30 @@ mod state;
 #[path = "unit_tests/contract.rs"]
 mod tests;
 
-use std::mem;
+use std::{collections::BTreeMap, mem};
 
 use linera_sdk::{
     abi::WithContractAbi,
+    linera_base_types::{
+        Account, AccountOwner, Amount, ApplicationId, ApplicationPermissions, ChainId,
+        ChainOwnership, CryptoHash, TimeDelta, Timestamp,
+    },
     views::{RootView, View},
     Contract, ContractRuntime,
 };
 
-use depin_demo::Operation;
+use depin_demo::{
+    merkle::ContributionLeaf, AggregateConsumerAbi, AggregationEvent, AggregationMode, ChainSetup,
+    DepinDemoParameters, FinalizedAggregate, LocalAggregate, Message, MetricValue, NodeRole,
+    Operation, OperationResponse, OutOfRangePolicy, Reading, ReplicatedFlush, Resolution,
+    StreamUpdate, TreeConfig, FLUSHES_STREAM,
+};
 
-use self::state::DepinDemoState;
+use self::state::{
+    ArchivedEpoch, ChildRecord, DepinDemoState, Device, FlushLoad, FlushRecord, QuarantinedReading,
+    RewardPayout, RewardSettlement, Rollup, ShardReport, UnacknowledgedFlush, ValueSnapshot,
+    ValueUpdate,
+};
 
 pub struct DepinDemoContract {
     state: DepinDemoState,
@@ -33,10 +46,10 @@ impl WithContractAbi for DepinDemoContract {
 }
 
 impl Contract for DepinDemoContract {
-    type Message = u64;
-    type Parameters = ();
-    type InstantiationArgument = ();
-    type EventValue = ();
+    type Message = Message;
+    type Parameters = DepinDemoParameters;
+    type InstantiationArgument = ChainSetup;
+    type EventValue = AggregationEvent;
 
     async fn load(runtime: ContractRuntime<Self>) -> Self {
         let state = DepinDemoState::load(runtime.root_view_storage_context())
@@ -45,37 +58,2485 @@ impl Contract for DepinDemoContract {
         DepinDemoContract { state, runtime }
     }
 
-    async fn instantiate(&mut self, _argument: Self::InstantiationArgument) {
+    async fn instantiate(&mut self, setup: Self::InstantiationArgument) {
         // validate that the application parameters were configured correctly.
-        self.runtime.application_parameters();
+        let parameters = self.runtime.application_parameters();
+
+        check_tree_config(&parameters.tree_config());
+        assert!(
+            parameters.aggregation_mode != AggregationMode::Sharded
+                || !parameters.reducer_chains.is_empty(),
+            "Sharded aggregation requires at least one reducer chain"
+        );
+
+        self.initialize(setup).await;
     }
 
     async fn execute_operation(&mut self, operation: Self::Operation) -> Self::Response {
+        match self.runtime.authenticated_caller_id() {
+            Some(caller) => self.check_application_call(caller, &operation).await,
+            None => assert!(
+                self.runtime.authenticated_signer().is_some(),
+                "Operations must be signed by a chain owner or called by an authenticated \
+                application"
+            ),
+        }
+
+        self.check_role_for_operation(&operation);
+
+        if *self.state.retired.get() {
+            assert!(
+                matches!(
+                    operation,
+                    Operation::WithdrawStakeFrom { .. } | Operation::ReadAggregate
+                ),
+                "Chain has been retired and can't execute {operation:?}"
+            );
+        }
+
         match operation {
             Operation::ConnectToParent { parent } => {
-                self.state.parent.set(Some(parent));
+                assert!(
+                    self.state.parent.get().is_none(),
+                    "Chain is already connected to a parent chain, it must be reparented instead"
+                );
+
+                let stake = self.transfer_stake(parent);
+                self.connect_to_parent(parent, stake);
+            }
+            Operation::Submit { metric, value } => {
+                let timestamp = self.runtime.system_time();
+
+                self.submit(metric, value, timestamp).await;
+                self.flush_if_due().await;
             }
-            Operation::Submit { value } => {
-                self.state.value.set(self.state.value.get() + value);
+            Operation::SubmitBatch { readings } => {
+                self.check_batch(&readings);
+
+                for reading in readings {
+                    self.submit(reading.metric, reading.value, reading.timestamp)
+                        .await;
+                }
+
+                self.flush_if_due().await;
             }
             Operation::Flush => {
+                self.fail_over_if_unacknowledged().await;
+
+                let parent =
+                    match self.runtime.application_parameters().aggregation_mode {
+                        AggregationMode::Messages => {
+                            assert!(
+                                !*self.state.unstaking.get(),
+                                "Chain is unstaking and can't flush to its parent chain"
+                            );
+
+                            Some(self.state.parent.get().expect(
+                                "Can't flush if the chain is not connected to a parent chain",
+                            ))
+                        }
+                        AggregationMode::Streams | AggregationMode::Sharded => None,
+                    };
+
+                self.flush(parent).await;
+            }
+            Operation::RegisterDevice {
+                owner,
+                reward_account,
+            } => {
+                let contributions = self
+                    .state
+                    .devices
+                    .get(&owner)
+                    .await
+                    .expect("Failed to load registered device")
+                    .map_or(0, |device| device.contributions);
+
+                self.state
+                    .devices
+                    .insert(
+                        &owner,
+                        Device {
+                            reward_account,
+                            contributions,
+                        },
+                    )
+                    .expect("Failed to register device");
+            }
+            Operation::DistributeRewards { amount } => {
+                assert!(
+                    self.state.parent.get().is_none(),
+                    "Only the root chain can start distributing rewards"
+                );
+                let epoch = self.state.reward_epoch.get() + 1;
+
+                self.distribute_rewards(epoch, amount).await;
+            }
+            Operation::Unstake => {
                 let parent = self
                     .state
                     .parent
                     .get()
-                    .expect("Can't flush if the chain is not connected to a parent chain");
-                let value = mem::take(self.state.value.get_mut());
+                    .expect("Can't unstake if the chain is not connected to a parent chain");
+
+                self.leave_parent(parent, Message::Unstake).await;
+                self.state.unstaking.set(true);
+            }
+            Operation::WithdrawStake => {
+                let parent = self.state.parent.get().expect(
+                    "Can't withdraw the stake if the chain is not connected to a parent chain",
+                );
+                assert!(
+                    *self.state.unstaking.get(),
+                    "Chain must unstake before withdrawing its stake"
+                );
+
+                // The parent chain forgets this chain once it returns the stake.
+                self.runtime.send_message(parent, Message::WithdrawStake);
+                self.disconnect();
+            }
+            Operation::Reparent { parent } => {
+                let old_parent = self
+                    .state
+                    .parent
+                    .get()
+                    .expect("Can't reparent if the chain is not connected to a parent chain");
+
+                assert_ne!(
+                    old_parent, parent,
+                    "Chain is already connected to parent chain {parent}"
+                );
+
+                self.leave_parent(old_parent, Message::Deregister).await;
+                let stake = self.transfer_stake(parent);
+                self.connect_to_parent(parent, stake);
+            }
+            Operation::WithdrawStakeFrom { parent } => {
+                self.runtime.send_message(parent, Message::WithdrawStake);
+            }
+            Operation::Disconnect => {
+                let parent = self
+                    .state
+                    .parent
+                    .get()
+                    .expect("Can't disconnect if the chain is not connected to a parent chain");
+
+                self.leave_parent(parent, Message::Deregister).await;
+                self.disconnect();
+            }
+            Operation::Join { root, aggregator } => {
+                assert!(
+                    self.state.parent.get().is_none(),
+                    "Chain is already connected to a parent chain, it must be reparented instead"
+                );
+                assert_eq!(
+                    self.runtime.application_parameters().aggregation_mode,
+                    AggregationMode::Messages,
+                    "Chains publish their flushes to event streams instead of joining a tree"
+                );
+
+                // The stake can't be transferred from this chain's balance while executing the
+                // root chain's reply, so the root chain forwards it to the assigned parent chain.
+                let stake = self.transfer_stake(root);
+
+                self.state.pending_join.set(Some(root));
+                self.runtime
+                    .send_message(root, Message::Join { aggregator, stake });
+            }
+            Operation::Rebalance { target, balance } => {
+                assert_ne!(
+                    target,
+                    Some(self.runtime.chain_id()),
+                    "Child chains can't be moved to the chain they are leaving"
+                );
+                assert!(
+                    target.is_none() || balance.is_zero(),
+                    "Only a newly opened aggregator chain can be funded with a balance"
+                );
+
+                let count = self.excess_children().await;
+                let target = match target {
+                    Some(target) => target,
+                    None => {
+                        let parent = match *self.state.parent.get() {
+                            Some(parent) => parent,
+                            None => self.runtime.chain_id(),
+                        };
+                        let stake = self.transfer_stake(parent);
+
+                        self.open_aggregator_chain(parent, balance, stake)
+                    }
+                };
+
+                self.move_children(count, target).await;
+            }
+            Operation::OpenEdgeChain { owner, balance } => {
+                assert_eq!(
+                    self.runtime.application_parameters().aggregation_mode,
+                    AggregationMode::Messages,
+                    "Chains publish their flushes to event streams instead of connecting to a \
+                    parent chain"
+                );
+                let stake = self.runtime.application_parameters().registration_stake;
+                assert!(
+                    balance >= stake,
+                    "Edge chains must be funded with at least the registration stake"
+                );
+
+                // The stake is paid from the balance directly by this chain.
+                let chain_id = self.runtime.chain_id();
+                let stake = self.transfer_stake(chain_id);
+                let edge_chain = self.open_child_chain(
+                    ChainOwnership::single(owner),
+                    balance.saturating_sub(stake),
+                    chain_id,
+                    NodeRole::Edge,
+                    stake,
+                );
+
+                self.state
+                    .opened_chains
+                    .insert(&edge_chain, owner)
+                    .expect("Failed to update opened chains");
+            }
+            Operation::UpdateConfig { config } => {
+                assert!(
+                    self.state.parent.get().is_none(),
+                    "Only the root chain can update the configuration of the tree"
+                );
+                check_tree_config(&config);
+
+                let version = self.state.config_version.get() + 1;
+
+                self.apply_config(version, config).await;
+            }
+            Operation::SetRole { role } => self.set_role(role).await,
+            Operation::Initialize { setup } => {
+                assert!(
+                    !*self.state.initialized.get(),
+                    "Application is already initialized on this chain"
+                );
+
+                self.initialize(setup).await;
+            }
+            Operation::SetBackupParents { parents } => {
+                assert_eq!(
+                    self.runtime.application_parameters().aggregation_mode,
+                    AggregationMode::Messages,
+                    "Chains publish their flushes to event streams instead of sending them to \
+                    parent chains"
+                );
+                let chain_id = self.runtime.chain_id();
+                let parent = *self.state.parent.get();
+
+                for (index, backup) in parents.iter().enumerate() {
+                    assert!(
+                        *backup != chain_id && Some(*backup) != parent,
+                        "Chain {backup} can't be a backup parent chain of this chain"
+                    );
+                    assert!(
+                        !parents[..index].contains(backup),
+                        "Backup parent chain {backup} is listed more than once"
+                    );
+                }
+
+                let stake = self.runtime.application_parameters().registration_stake;
+                let reserved_stake = *self.state.reserved_stake.get();
+                let missing_stake = stake.saturating_sub(reserved_stake);
+
+                if !parents.is_empty() && !missing_stake.is_zero() {
+                    let application_account = AccountOwner::from(self.runtime.application_id());
+                    self.runtime.transfer(
+                        AccountOwner::CHAIN,
+                        Account::new(chain_id, application_account),
+                        missing_stake,
+                    );
+                    self.state.reserved_stake.set(stake);
+                } else if parents.is_empty() && !reserved_stake.is_zero() {
+                    // Without backup parent chains, the reserve is returned to the chain's balance.
+                    let application_account = AccountOwner::from(self.runtime.application_id());
+                    self.runtime.transfer(
+                        application_account,
+                        Account::chain(chain_id),
+                        reserved_stake,
+                    );
+                    self.state.reserved_stake.set(Amount::ZERO);
+                }
+
+                self.state.backup_parents.set(parents);
+            }
+            Operation::ReportShard => {
+                assert!(
+                    self.runtime
+                        .application_parameters()
+                        .reducer_chains
+                        .contains(&self.runtime.chain_id()),
+                    "Only reducer chains can report the values of their shard"
+                );
+
+                let values = self
+                    .state
+                    .reduced_metric_values
+                    .index_values()
+                    .await
+                    .expect("Failed to load reduced metric values")
+                    .into_iter()
+                    .map(|(_, value)| value)
+                    .collect();
+                let combiner = self.runtime.application_creator_chain_id();
+
+                self.runtime
+                    .send_message(combiner, Message::ReportShard { values });
+            }
+            Operation::Retire => {
+                assert_eq!(
+                    self.state
+                        .children
+                        .count()
+                        .await
+                        .expect("Failed to load child chain registry"),
+                    0,
+                    "Chains with child chains can't retire, the child chains must move to another \
+                    parent chain first"
+                );
+
+                if let Some(parent) = *self.state.parent.get() {
+                    self.leave_parent(parent, Message::Retire).await;
+                    self.disconnect();
+                } else if self.runtime.application_parameters().aggregation_mode
+                    != AggregationMode::Messages
+                    && (*self.state.value.get() != 0 || *self.state.contributions.get() != 0)
+                {
+                    self.flush(None).await;
+                }
+
+                self.state.retired.set(true);
+            }
+            Operation::Slash { child, amount } => {
+                let is_child = self
+                    .state
+                    .children
+                    .contains_key(&child)
+                    .await
+                    .expect("Failed to load child chain registry");
+                let mut record = if is_child {
+                    self.child_record(child).await
+                } else {
+                    // Chains that moved to another parent chain can still be slashed until they
+                    // withdraw their stake.
+                    self.state
+                        .former_children
+                        .get(&child)
+                        .await
+                        .expect("Failed to load former child chains")
+                        .unwrap_or_else(|| {
+                            panic!("Chain {child} is not registered as a child chain")
+                        })
+                };
+
+                record.stake = record
+                    .stake
+                    .try_sub(amount)
+                    .expect("Can't slash more than the child chain's stake");
+                record.slashed.saturating_add_assign(amount);
+                self.release_held_stake(amount);
 
-                self.runtime.send_message(parent, value);
+                if is_child {
+                    self.state
+                        .children
+                        .insert(&child, record)
+                        .expect("Failed to update child chain registry");
+                } else {
+                    self.state
+                        .former_children
+                        .insert(&child, record)
+                        .expect("Failed to update former child chains");
+                }
+            }
+            Operation::CloseEpoch => {
+                assert!(
+                    self.state.parent.get().is_none(),
+                    "Only the root chain can close epochs"
+                );
+                let epoch = *self.state.epoch.get();
+                let archive = ArchivedEpoch {
+                    value: mem::take(self.state.value.get_mut()),
+                    contributions: mem::take(self.state.contributions.get_mut()),
+                    closed_at: self.runtime.system_time(),
+                    late_flushes: 0,
+                };
+                self.record_value_update();
+                self.emit(AggregationEvent::EpochClosed {
+                    epoch,
+                    value: archive.value,
+                    contributions: archive.contributions,
+                });
+                self.deliver_aggregate(epoch, &archive);
+
+                self.state
+                    .archived_epochs
+                    .insert(&epoch, archive)
+                    .expect("Failed to archive epoch");
+                self.state.epoch.set(epoch + 1);
+                self.broadcast_to_children(Message::NewEpoch { epoch: epoch + 1 })
+                    .await;
+            }
+            Operation::SubscribeToChild { child } => {
+                assert_eq!(
+                    self.runtime.application_parameters().aggregation_mode,
+                    AggregationMode::Streams,
+                    "Child chains can only be subscribed to if they publish their flushes to \
+                    event streams"
+                );
+
+                if !self
+                    .state
+                    .stream_subscriptions
+                    .contains_key(&child)
+                    .await
+                    .expect("Failed to load stream subscriptions")
+                {
+                    self.state
+                        .stream_subscriptions
+                        .insert(&child, 0)
+                        .expect("Failed to subscribe to child chain");
+                }
+            }
+            Operation::UnsubscribeFromChild { child } => {
+                self.state
+                    .stream_subscriptions
+                    .remove(&child)
+                    .expect("Failed to unsubscribe from child chain");
+            }
+            Operation::ProcessStreams { updates } => {
+                self.process_streams(updates).await;
+                self.flush_if_due().await;
+            }
+            Operation::ReadAggregate => {
+                return OperationResponse::Aggregate(LocalAggregate {
+                    epoch: *self.state.epoch.get(),
+                    value: *self.state.value.get(),
+                    contributions: *self.state.contributions.get(),
+                });
+            }
+            Operation::AuthorizeApplication {
+                application_id,
+                permissions,
+            } => {
+                self.state
+                    .authorized_applications
+                    .insert(&application_id, permissions)
+                    .expect("Failed to authorize application");
+            }
+            Operation::RevokeApplication { application_id } => {
+                self.state
+                    .authorized_applications
+                    .remove(&application_id)
+                    .expect("Failed to revoke application");
+            }
+            Operation::SetConsumer { consumer } => {
+                assert!(
+                    self.state.parent.get().is_none(),
+                    "Only the root chain finalizes aggregates for a consumer"
+                );
+
+                self.state.consumer.set(consumer);
             }
         }
+
+        OperationResponse::Ok
     }
 
-    async fn execute_message(&mut self, child_value: Self::Message) {
-        self.state.value.set(self.state.value.get() + child_value);
+    async fn execute_message(&mut self, message: Self::Message) {
+        let sender = self
+            .runtime
+            .message_id()
+            .expect("Incoming messages should have an ID")
+            .chain_id;
+
+        self.check_role_for_message(&message);
+
+        if *self.state.retired.get() {
+            assert!(
+                !matches!(message, Message::Register { .. } | Message::Flush { .. }),
+                "Chain has been retired and can't aggregate the values of child chains"
+            );
+        }
+
+        match message {
+            Message::Flush {
+                epoch,
+                value,
+                contributions,
+                commitment,
+                replicas,
+            } => {
+                assert!(
+                    !self
+                        .state
+                        .retired_children
+                        .contains_key(&sender)
+                        .await
+                        .expect("Failed to load retired child chains"),
+                    "Child chain {sender} has retired"
+                );
+                self.check_child_stake(sender).await;
+                self.record_child_flush();
+
+                if let Some(replica) = replicas.iter().find(|replica| replica.child == sender) {
+                    self.runtime.send_message(
+                        sender,
+                        Message::AcknowledgeFlush {
+                            sequence: replica.sequence,
+                        },
+                    );
+                }
+
+                let contributions = self
+                    .merge_flush(
+                        epoch,
+                        ContributionLeaf {
+                            child: sender,
+                            value,
+                            contributions,
+                            commitment,
+                        },
+                        replicas,
+                    )
+                    .await;
+
+                *self
+                    .state
+                    .child_contributions
+                    .get_mut_or_default(&sender)
+                    .await
+                    .expect("Failed to load child contributions") += contributions;
+
+                self.flush_if_due().await;
+            }
+            Message::AcknowledgeFlush { sequence } => {
+                let acknowledged = self
+                    .state
+                    .unacknowledged_flushes
+                    .get(&sequence)
+                    .await
+                    .expect("Failed to load unacknowledged flushes")
+                    .is_some_and(|flush| flush.parent == sender);
+
+                if acknowledged {
+                    self.state
+                        .unacknowledged_flushes
+                        .remove(&sequence)
+                        .expect("Failed to update unacknowledged flushes");
+                }
+            }
+            Message::Reward { epoch, amount } => {
+                // A former parent chain may have paid the rewards before it learned that this
+                // chain left, so they are returned to it.
+                if self.is_former_parent(sender).await {
+                    let application_account = AccountOwner::from(self.runtime.application_id());
+                    self.runtime.transfer(
+                        application_account,
+                        Account::new(sender, application_account),
+                        amount,
+                    );
+                    return;
+                }
+
+                assert_eq!(
+                    *self.state.parent.get(),
+                    Some(sender),
+                    "Rewards can only be received from the parent chain"
+                );
+
+                self.distribute_rewards(epoch, amount).await;
+            }
+            Message::Register { stake } => {
+                let record = self
+                    .state
+                    .children
+                    .get_mut_or_default(&sender)
+                    .await
+                    .expect("Failed to load child chain registry");
+
+                record
+                    .stake
+                    .try_add_assign(stake)
+                    .expect("Child chain stake overflow");
+                record.unstaking_since = None;
+                self.state
+                    .held_stake
+                    .get_mut()
+                    .try_add_assign(stake)
+                    .expect("Held stake overflow");
+                self.state
+                    .joining_stakes
+                    .remove(&sender)
+                    .expect("Failed to update joining chain stakes");
+
+                let epoch = *self.state.epoch.get();
+                if epoch > 0 {
+                    self.runtime
+                        .send_message(sender, Message::NewEpoch { epoch });
+                }
+
+                let version = *self.state.config_version.get();
+                if let Some(config) = self.state.tree_config.get().clone() {
+                    self.runtime
+                        .send_message(sender, Message::UpdateConfig { version, config });
+                }
+            }
+            Message::Unstake => {
+                let mut record = self.child_record(sender).await;
+
+                record.unstaking_since = Some(self.runtime.system_time());
+
+                self.state
+                    .children
+                    .insert(&sender, record)
+                    .expect("Failed to update child chain registry");
+            }
+            Message::WithdrawStake => {
+                // A chain that left and connected again withdraws the stake it left behind first,
+                // without disconnecting.
+                let former_record = self
+                    .state
+                    .former_children
+                    .get(&sender)
+                    .await
+                    .expect("Failed to load former child chains");
+                let is_former_child = former_record.is_some();
+                let record = match former_record {
+                    Some(record) => record,
+                    None => self.child_record(sender).await,
+                };
+                let unstaking_since = record
+                    .unstaking_since
+                    .expect("Child chain must unstake before withdrawing its stake");
+                let cooldown = self.runtime.application_parameters().unstake_cooldown;
+
+                assert!(
+                    self.runtime.system_time() >= unstaking_since.saturating_add(cooldown),
+                    "Child chain can't withdraw its stake before the unstaking cooldown elapses"
+                );
+
+                if !record.stake.is_zero() {
+                    let application_account = AccountOwner::from(self.runtime.application_id());
+                    self.runtime.transfer(
+                        application_account,
+                        Account::chain(sender),
+                        record.stake,
+                    );
+                    self.release_held_stake(record.stake);
+                }
+
+                if is_former_child {
+                    self.state
+                        .former_children
+                        .remove(&sender)
+                        .expect("Failed to update former child chains");
+                } else {
+                    self.state
+                        .children
+                        .remove(&sender)
+                        .expect("Failed to update child chain registry");
+                    self.state
+                        .moving_children
+                        .remove(&sender)
+                        .expect("Failed to update moving child chains");
+                }
+            }
+            Message::Deregister => self.deregister_child(sender, false).await,
+            Message::Moved => self.deregister_child(sender, true).await,
+            Message::Retire => {
+                self.deregister_child(sender, false).await;
+
+                self.state
+                    .retired_children
+                    .insert(&sender, self.runtime.system_time())
+                    .expect("Failed to update retired child chains");
+            }
+            Message::Join { aggregator, stake } => {
+                assert!(
+                    self.state.parent.get().is_none(),
+                    "Only the root chain assigns parent chains to joining chains"
+                );
+
+                let parent = self.assign_parent(sender, aggregator, stake).await;
+
+                if !stake.is_zero() {
+                    self.keep_joining_stake(sender, parent, stake);
+                }
+
+                self.runtime
+                    .send_message(sender, Message::AssignParent { parent, stake });
+            }
+            Message::AssignParent { parent, stake } => {
+                assert_eq!(
+                    *self.state.pending_join.get(),
+                    Some(sender),
+                    "Parent chains can only be assigned by the root chain that was asked to"
+                );
+
+                self.state.pending_join.set(None);
+                self.connect_to_parent(parent, stake);
+            }
+            Message::MoveToParent { parent, stake } => {
+                if self.is_former_parent(sender).await {
+                    return;
+                }
+
+                assert_eq!(
+                    *self.state.parent.get(),
+                    Some(sender),
+                    "Only the parent chain can move its child chains"
+                );
+
+                self.leave_parent(sender, Message::Moved).await;
+                self.connect_to_parent(parent, stake);
+            }
+            Message::ConnectOpenedChain {
+                parent,
+                role,
+                open_chain_message,
+                stake,
+            } => {
+                assert!(
+                    open_chain_message.chain_id == sender
+                        && ChainId::child(open_chain_message) == self.runtime.chain_id(),
+                    "Only the chain that opened this chain can connect it to a parent chain"
+                );
+                assert!(
+                    self.state.parent.get().is_none(),
+                    "Chain is already connected to a parent chain"
+                );
+
+                self.state.role.set(Some(role));
+                self.connect_to_parent(parent, stake);
+            }
+            Message::UpdateConfig { version, config } => {
+                if self.is_former_parent(sender).await {
+                    return;
+                }
+
+                assert_eq!(
+                    *self.state.parent.get(),
+                    Some(sender),
+                    "Configuration updates can only be received from the parent chain"
+                );
+
+                if version > *self.state.config_version.get() {
+                    self.apply_config(version, config).await;
+                }
+            }
+            Message::ReduceMetrics { values } => {
+                let chain_id = self.runtime.chain_id();
+                let parameters = self.runtime.application_parameters();
+
+                for metric_value in values {
+                    assert_eq!(
+                        parameters.reducer_chain(&metric_value.metric),
+                        Some(chain_id),
+                        "Metric {:?} is not reduced by this chain",
+                        metric_value.metric
+                    );
+
+                    self.state
+                        .value
+                        .set(self.state.value.get() + metric_value.value);
+                    *self.state.contributions.get_mut() += metric_value.contributions;
+
+                    let mut reduced = self
+                        .state
+                        .reduced_metric_values
+                        .get(&metric_value.metric)
+                        .await
+                        .expect("Failed to load reduced metric values")
+                        .unwrap_or_else(|| MetricValue {
+                            metric: metric_value.metric.clone(),
+                            value: 0,
+                            contributions: 0,
+                        });
+
+                    reduced.value += metric_value.value;
+                    reduced.contributions += metric_value.contributions;
+                    self.state
+                        .reduced_metric_values
+                        .insert(&metric_value.metric, reduced)
+                        .expect("Failed to update reduced metric values");
+                }
+
+                self.record_value_update();
+            }
+            Message::ReportShard { values } => {
+                assert_eq!(
+                    self.runtime.application_creator_chain_id(),
+                    self.runtime.chain_id(),
+                    "Shards are only combined on the chain that created the application"
+                );
+                assert!(
+                    self.runtime
+                        .application_parameters()
+                        .reducer_chains
+                        .contains(&sender),
+                    "Only reducer chains can report the values of their shard"
+                );
+
+                let reported_at = self.runtime.system_time();
+
+                self.state
+                    .shard_reports
+                    .insert(
+                        &sender,
+                        ShardReport {
+                            values,
+                            reported_at,
+                        },
+                    )
+                    .expect("Failed to update shard reports");
+            }
+            Message::NewEpoch { epoch } => {
+                // The parent chain may have started the epoch before it learned that this chain
+                // retired or left it.
+                if self.is_former_parent(sender).await {
+                    return;
+                }
+
+                assert_eq!(
+                    *self.state.parent.get(),
+                    Some(sender),
+                    "Epochs can only be started by the parent chain"
+                );
+
+                if epoch <= *self.state.epoch.get() {
+                    return;
+                }
+
+                if (*self.state.value.get() != 0 || *self.state.contributions.get() != 0)
+                    && !*self.state.unstaking.get()
+                {
+                    self.flush(Some(sender)).await;
+                }
+
+                self.state.epoch.set(epoch);
+                self.broadcast_to_children(Message::NewEpoch { epoch })
+                    .await;
+            }
+            Message::DeliverAggregate {
+                consumer,
+                mut aggregate,
+            } => {
+                aggregate.root_chain = sender;
+
+                self.runtime.call_application(
+                    true,
+                    consumer.with_abi::<AggregateConsumerAbi>(),
+                    &aggregate,
+                );
+            }
+        }
     }
 
     async fn store(mut self) {
         self.state.save().await.expect("Failed to save state");
     }
 }
+
+impl DepinDemoContract {
+    /// Sets up this chain with its `setup`, connecting it to its parent chain and giving it its
+    /// role.
+    async fn initialize(&mut self, setup: ChainSetup) {
+        self.state.initialized.set(true);
+        self.state.flush_policy.set(setup.flush_policy);
+
+        if let Some(parent) = setup.parent {
+            assert!(
+                self.state.parent.get().is_none(),
+                "Chain is already connected to a parent chain, it must be reparented instead"
+            );
+
+            let stake = self.transfer_stake(parent);
+            self.connect_to_parent(parent, stake);
+        }
+
+        if let Some(role) = setup.role {
+            self.set_role(role).await;
+        }
+    }
+
+    /// Sets the `role` of this chain, checking that it is consistent with the chain's position in
+    /// the tree.
+    async fn set_role(&mut self, role: NodeRole) {
+        match role {
+            NodeRole::Edge => assert!(
+                self.state
+                    .children
+                    .count()
+                    .await
+                    .expect("Failed to load child chain registry")
+                    == 0,
+                "Chains with child chains can't be edge chains"
+            ),
+            NodeRole::Aggregator => {}
+            NodeRole::Root => assert!(
+                self.state.parent.get().is_none(),
+                "Chains connected to a parent chain can't be root chains"
+            ),
+        }
+
+        self.state.role.set(Some(role));
+    }
+
+    /// Flushes the pending values if there are as many contributions pending as the chain's flush
+    /// policy allows.
+    ///
+    /// Root chains never flush, and chains that send their flushes to a parent chain only flush if
+    /// they are connected to one and are not unstaking.
+    async fn flush_if_due(&mut self) {
+        let max_pending_contributions = self.state.flush_policy.get().max_pending_contributions;
+
+        if max_pending_contributions == 0
+            || *self.state.contributions.get() < max_pending_contributions
+            || *self.state.role.get() == Some(NodeRole::Root)
+        {
+            return;
+        }
+
+        match self.runtime.application_parameters().aggregation_mode {
+            AggregationMode::Messages => {
+                if *self.state.unstaking.get() {
+                    return;
+                }
+
+                self.fail_over_if_unacknowledged().await;
+
+                if let Some(parent) = *self.state.parent.get() {
+                    self.flush(Some(parent)).await;
+                }
+            }
+            AggregationMode::Streams | AggregationMode::Sharded => self.flush(None).await,
+        }
+    }
+
+    /// Fails over to the next backup parent chain if the parent chain has not acknowledged as many
+    /// flushes as the application parameters allow, sending them again to the new parent chain.
+    ///
+    /// The former parent chain becomes the last backup parent chain, in case it comes back. The
+    /// registration stake for the new parent chain is paid from the stake reserved when the backup
+    /// parent chains were set, since this may run while executing a message. Without it, the chain
+    /// keeps waiting for its parent chain.
+    async fn fail_over_if_unacknowledged(&mut self) {
+        let max_unacknowledged_flushes = self
+            .runtime
+            .application_parameters()
+            .max_unacknowledged_flushes;
+        let Some(parent) = *self.state.parent.get() else {
+            return;
+        };
+
+        if max_unacknowledged_flushes == 0 || self.state.backup_parents.get().is_empty() {
+            return;
+        }
+
+        let sequences = self
+            .state
+            .unacknowledged_flushes
+            .indices()
+            .await
+            .expect("Failed to load unacknowledged flushes");
+
+        if sequences.len() < max_unacknowledged_flushes as usize {
+            return;
+        }
+
+        let stake = self.runtime.application_parameters().registration_stake;
+        let reserved_stake = *self.state.reserved_stake.get();
+
+        if !stake.is_zero() {
+            let application_account = AccountOwner::from(self.runtime.application_id());
+
+            if reserved_stake < stake || self.runtime.owner_balance(application_account) < stake {
+                return;
+            }
+        }
+
+        let backup_parents = self.state.backup_parents.get_mut();
+        let backup = backup_parents.remove(0);
+        backup_parents.push(parent);
+
+        self.runtime.send_message(parent, Message::Deregister);
+
+        if !stake.is_zero() {
+            let application_account = AccountOwner::from(self.runtime.application_id());
+            self.runtime.transfer(
+                application_account,
+                Account::new(backup, application_account),
+                stake,
+            );
+            self.state
+                .reserved_stake
+                .set(reserved_stake.saturating_sub(stake));
+        }
+
+        self.connect_to_parent(backup, stake);
+
+        for sequence in sequences {
+            let flush = self
+                .state
+                .unacknowledged_flushes
+                .get_mut(&sequence)
+                .await
+                .expect("Failed to load unacknowledged flushes")
+                .expect("Unacknowledged flush should be tracked");
+
+            flush.parent = backup;
+            self.runtime.send_message(
+                backup,
+                Message::Flush {
+                    epoch: flush.epoch,
+                    value: flush.value,
+                    contributions: flush.contributions,
+                    commitment: flush.commitment,
+                    replicas: flush.replicas.clone(),
+                },
+            );
+        }
+    }
+
+    /// Connects this chain to a `parent` chain, registering with it with the `stake` already
+    /// transferred to the application's account on the `parent` chain.
+    fn connect_to_parent(&mut self, parent: ChainId, stake: Amount) {
+        assert_eq!(
+            self.runtime.application_parameters().aggregation_mode,
+            AggregationMode::Messages,
+            "Chains publish their flushes to event streams instead of connecting to a parent chain"
+        );
+
+        self.forget_parent();
+        self.state
+            .former_parents
+            .remove(&parent)
+            .expect("Failed to update former parent chains");
+        self.state.parent.set(Some(parent));
+        self.state.unstaking.set(false);
+        self.runtime
+            .send_message(parent, Message::Register { stake });
+    }
+
+    /// Forgets the parent chain this chain left.
+    fn disconnect(&mut self) {
+        self.forget_parent();
+        self.state.parent.set(None);
+        self.state.unstaking.set(false);
+    }
+
+    /// Adds the current parent chain, if any, to the former parent chains.
+    fn forget_parent(&mut self) {
+        if let Some(parent) = *self.state.parent.get() {
+            self.state
+                .former_parents
+                .insert(&parent)
+                .expect("Failed to update former parent chains");
+        }
+    }
+
+    /// Checks if the `sender` chain is a parent chain this chain left.
+    async fn is_former_parent(&self, sender: ChainId) -> bool {
+        *self.state.parent.get() != Some(sender)
+            && self
+                .state
+                .former_parents
+                .contains(&sender)
+                .await
+                .expect("Failed to load former parent chains")
+    }
+
+    /// Transfers the registration stake from this chain's balance to the application's account on
+    /// the `recipient` chain, and returns the transferred stake.
+    ///
+    /// Only blocks signed by the owners of the chain can transfer from its balance, so this can't
+    /// be done while executing a message.
+    fn transfer_stake(&mut self, recipient: ChainId) -> Amount {
+        let stake = self.runtime.application_parameters().registration_stake;
+
+        if !stake.is_zero() {
+            let application_account = AccountOwner::from(self.runtime.application_id());
+            self.runtime.transfer(
+                AccountOwner::CHAIN,
+                Account::new(recipient, application_account),
+                stake,
+            );
+        }
+
+        stake
+    }
+
+    /// Checks if the `caller` application is allowed to execute the `operation`.
+    ///
+    /// Other applications can only submit readings and read the local aggregate, and only if they
+    /// were authorized to by an operation in a block of this chain.
+    async fn check_application_call(&mut self, caller: ApplicationId, operation: &Operation) {
+        let permissions = self
+            .state
+            .authorized_applications
+            .get(&caller)
+            .await
+            .expect("Failed to load authorized applications")
+            .unwrap_or_default();
+
+        let allowed = match operation {
+            Operation::Submit { .. } | Operation::SubmitBatch { .. } => permissions.submit,
+            Operation::ReadAggregate => permissions.read_aggregate,
+            _ => false,
+        };
+
+        assert!(
+            allowed,
+            "Application {caller} is not authorized to execute {operation:?}"
+        );
+    }
+
+    /// Checks if this chain's role allows it to execute the `operation`.
+    ///
+    /// Only edge chains accept submitted values and devices, root chains never flush or connect to
+    /// a parent chain, edge chains don't manage child chains, only aggregator chains join a tree as
+    /// aggregators, and only root chains perform the operations that affect the whole tree. Chains
+    /// without a role can execute any operation.
+    fn check_role_for_operation(&mut self, operation: &Operation) {
+        let Some(role) = *self.state.role.get() else {
+            return;
+        };
+
+        let allowed = match operation {
+            Operation::Submit { .. }
+            | Operation::SubmitBatch { .. }
+            | Operation::RegisterDevice { .. } => role == NodeRole::Edge,
+            Operation::Join {
+                aggregator: true, ..
+            } => role == NodeRole::Aggregator,
+            Operation::ConnectToParent { .. }
+            | Operation::Flush
+            | Operation::Reparent { .. }
+            | Operation::Disconnect
+            | Operation::Join { .. }
+            | Operation::SetBackupParents { .. } => role != NodeRole::Root,
+            Operation::Slash { .. }
+            | Operation::SubscribeToChild { .. }
+            | Operation::UnsubscribeFromChild { .. }
+            | Operation::ProcessStreams { .. }
+            | Operation::Rebalance { .. }
+            | Operation::OpenEdgeChain { .. }
+            | Operation::ReportShard => role != NodeRole::Edge,
+            Operation::DistributeRewards { .. }
+            | Operation::CloseEpoch
+            | Operation::SetConsumer { .. }
+            | Operation::UpdateConfig { .. } => role == NodeRole::Root,
+            Operation::Unstake
+            | Operation::WithdrawStake
+            | Operation::ReadAggregate
+            | Operation::AuthorizeApplication { .. }
+            | Operation::RevokeApplication { .. }
+            | Operation::WithdrawStakeFrom { .. }
+            | Operation::Retire
+            | Operation::SetRole { .. }
+            | Operation::Initialize { .. } => true,
+        };
+
+        assert!(allowed, "{role:?} chains can't execute {operation:?}");
+    }
+
+    /// Checks if this chain's role allows it to receive the `message`.
+    ///
+    /// Edge chains don't accept child chains, only root chains assign parent chains to joining
+    /// chains, and root chains can't be connected to a parent chain.
+    fn check_role_for_message(&mut self, message: &Message) {
+        let Some(role) = *self.state.role.get() else {
+            return;
+        };
+
+        let allowed = match message {
+            Message::Register { .. } | Message::Flush { .. } | Message::ReduceMetrics { .. } => {
+                role != NodeRole::Edge
+            }
+            Message::Join { .. } => role == NodeRole::Root,
+            Message::AssignParent { .. }
+            | Message::MoveToParent { .. }
+            | Message::ConnectOpenedChain { .. } => role != NodeRole::Root,
+            Message::AcknowledgeFlush { .. }
+            | Message::Reward { .. }
+            | Message::Unstake
+            | Message::WithdrawStake
+            | Message::Deregister
+            | Message::Moved
+            | Message::Retire
+            | Message::UpdateConfig { .. }
+            | Message::ReportShard { .. }
+            | Message::NewEpoch { .. }
+            | Message::DeliverAggregate { .. } => true,
+        };
+
+        assert!(allowed, "{role:?} chains can't receive {message:?}");
+    }
+
+    /// Assigns a parent chain to a `child` chain joining the root chain's tree.
+    ///
+    /// Only the root chain and the chains that joined as aggregator chains are assigned child
+    /// chains, in the order they joined, each one until it has `fan_out` child chains, so that the
+    /// tree is filled level by level. Instead of giving the last free place in the tree to a chain
+    /// that doesn't join as an aggregator chain, a new aggregator chain is opened in it and the
+    /// chain is assigned to it. If the root chain can't pay for the new aggregator chain, besides
+    /// the `stake` of the joining chain that it still has to forward, the chain takes the last
+    /// place, and chains joining a full tree are assigned to the root chain. A chain that joins
+    /// again is assigned the same parent chain.
+    async fn assign_parent(&mut self, child: ChainId, aggregator: bool, stake: Amount) -> ChainId {
+        if let Some(parent) = self
+            .state
+            .assigned_parents
+            .get(&child)
+            .await
+            .expect("Failed to load assigned parent chains")
+        {
+            return parent;
+        }
+
+        let fan_out = self.runtime.application_parameters().fan_out;
+        let root = self.runtime.chain_id();
+        let mut parent = root;
+
+        if fan_out > 0 {
+            let candidates = self.state.joined_aggregators.count() as u64 + 1;
+            let mut index = *self.state.next_assigned_parent.get();
+            let mut is_last_place = false;
+
+            while index < candidates {
+                let candidate = match index.checked_sub(1) {
+                    None => root,
+                    Some(aggregator_index) => self
+                        .state
+                        .joined_aggregators
+                        .get(aggregator_index as usize)
+                        .await
+                        .expect("Failed to load joined aggregator chains")
+                        .expect("Joined aggregator chain index should be valid"),
+                };
+                let assigned_children = self
+                    .state
+                    .assigned_children
+                    .get(&candidate)
+                    .await
+                    .expect("Failed to load assigned child chain counts")
+                    .unwrap_or_default();
+
+                if assigned_children < fan_out {
+                    parent = candidate;
+                    is_last_place = index + 1 == candidates && assigned_children + 1 == fan_out;
+                    break;
+                }
+
+                index += 1;
+            }
+
+            self.state.next_assigned_parent.set(index);
+
+            if is_last_place && !aggregator {
+                if let Some(new_aggregator) = self.open_joined_aggregator_chain(parent, stake).await
+                {
+                    self.record_assigned_parent(new_aggregator, parent, true)
+                        .await;
+                    parent = new_aggregator;
+                }
+            }
+        }
+
+        self.record_assigned_parent(child, parent, aggregator).await;
+
+        parent
+    }
+
+    /// Forwards the `stake` of a `child` chain joining the root chain's tree to its `parent` chain,
+    /// or keeps it until the child chain registers if the root chain is its parent chain.
+    fn keep_joining_stake(&mut self, child: ChainId, parent: ChainId, stake: Amount) {
+        if parent != self.runtime.chain_id() {
+            let application_account = AccountOwner::from(self.runtime.application_id());
+            self.runtime.transfer(
+                application_account,
+                Account::new(parent, application_account),
+                stake,
+            );
+        } else {
+            self.state
+                .joining_stakes
+                .insert(&child, stake)
+                .expect("Failed to update joining chain stakes");
+        }
+    }
+
+    /// Records that the root chain assigned the `parent` chain to a `child` chain in its tree,
+    /// which accepts child chains of its own if it is an `aggregator` chain.
+    async fn record_assigned_parent(&mut self, child: ChainId, parent: ChainId, aggregator: bool) {
+        *self
+            .state
+            .assigned_children
+            .get_mut_or_default(&parent)
+            .await
+            .expect("Failed to load assigned child chain counts") += 1;
+        self.state.joined_chains.push(child);
+        self.state
+            .assigned_parents
+            .insert(&child, parent)
+            .expect("Failed to update assigned parent chains");
+
+        if aggregator {
+            self.state.joined_aggregators.push(child);
+        }
+    }
+
+    /// Opens a new aggregator chain for the root chain's tree, with the configured
+    /// `aggregator_balance`, and asks it to connect to the `parent` chain.
+    ///
+    /// This may happen while executing a message, so the registration stake of the new chain is
+    /// paid from the unlocked balance of the application's account, besides the
+    /// `forwarded_stake` of the joining chain. Returns `None` without opening a chain if the
+    /// balances don't cover it.
+    async fn open_joined_aggregator_chain(
+        &mut self,
+        parent: ChainId,
+        forwarded_stake: Amount,
+    ) -> Option<ChainId> {
+        let parameters = self.runtime.application_parameters();
+        let stake = parameters.registration_stake;
+        let balance = parameters.aggregator_balance;
+
+        if !balance.is_zero() && self.runtime.chain_balance() < balance {
+            return None;
+        }
+
+        if !stake.is_zero() {
+            let required_balance = stake
+                .try_add(forwarded_stake)
+                .expect("Registration stake overflow");
+
+            if self.unlocked_balance().await < required_balance {
+                return None;
+            }
+        }
+
+        let chain_id = self.open_aggregator_chain(parent, balance, stake);
+
+        if !stake.is_zero() {
+            self.keep_joining_stake(chain_id, parent, stake);
+        }
+
+        Some(chain_id)
+    }
+
+    /// Returns the flushes received from child chains during the current load window, which
+    /// starts now if the last one ended.
+    fn current_flush_load(&mut self) -> FlushLoad {
+        let load_window = self.runtime.application_parameters().load_window;
+        let now = self.runtime.system_time();
+        let load = self.state.flush_load.get();
+
+        if now >= load.window_start.saturating_add(load_window) {
+            FlushLoad {
+                window_start: now,
+                flushes: 0,
+            }
+        } else {
+            load.clone()
+        }
+    }
+
+    /// Counts a flush received from a child chain in the current load window.
+    fn record_child_flush(&mut self) {
+        let mut load = self.current_flush_load();
+
+        load.flushes += 1;
+
+        self.state.flush_load.set(load);
+    }
+
+    /// Returns how many child chains must be moved to another aggregator chain to bring this chain
+    /// back within its fan-in budget.
+    ///
+    /// If the chain has too many child chains, the ones exceeding the budget are moved. If it only
+    /// receives too many flushes, half of its child chains are moved. Child chains that are
+    /// already moving are not counted.
+    async fn excess_children(&mut self) -> usize {
+        let parameters = self.runtime.application_parameters();
+        let children = self
+            .state
+            .children
+            .count()
+            .await
+            .expect("Failed to load child chain registry")
+            .saturating_sub(
+                self.state
+                    .moving_children
+                    .count()
+                    .await
+                    .expect("Failed to load moving child chains"),
+            );
+        let fan_in_budget = parameters.fan_in_budget as usize;
+
+        if fan_in_budget > 0 && children > fan_in_budget {
+            return children - fan_in_budget;
+        }
+
+        let flushes = self.current_flush_load().flushes;
+
+        assert!(
+            parameters.flush_budget > 0 && flushes > parameters.flush_budget && children > 0,
+            "Aggregator chain is within its fan-in budget"
+        );
+
+        children.div_ceil(2)
+    }
+
+    /// Opens a new aggregator chain with the same owners as this chain and the `balance` taken
+    /// from this chain, and asks it to connect to the `parent` chain with the `stake` already paid
+    /// to it.
+    fn open_aggregator_chain(
+        &mut self,
+        parent: ChainId,
+        balance: Amount,
+        stake: Amount,
+    ) -> ChainId {
+        let ownership = self.runtime.chain_ownership();
+
+        self.open_child_chain(ownership, balance, parent, NodeRole::Aggregator, stake)
+    }
+
+    /// Opens a new chain with the `ownership` and the `balance` taken from this chain, and asks it
+    /// to take the `role` and connect to the `parent` chain.
+    ///
+    /// This chain pays the registration `stake` of the new chain to the `parent` chain before
+    /// opening it, because the new chain connects while executing a message and can't pay it from
+    /// its own balance.
+    fn open_child_chain(
+        &mut self,
+        ownership: ChainOwnership,
+        balance: Amount,
+        parent: ChainId,
+        role: NodeRole,
+        stake: Amount,
+    ) -> ChainId {
+        let (open_chain_message, chain_id) =
+            self.runtime
+                .open_chain(ownership, ApplicationPermissions::default(), balance);
+
+        self.runtime.send_message(
+            chain_id,
+            Message::ConnectOpenedChain {
+                parent,
+                role,
+                open_chain_message,
+                stake,
+            },
+        );
+
+        chain_id
+    }
+
+    /// Asks `count` of the child chains that are not already moving to move to the `target`
+    /// chain.
+    ///
+    /// The child chains flush their pending values to this chain before leaving it, and their
+    /// stake is forwarded to the `target` chain when they leave, since they can't pay it again
+    /// while executing the request to move.
+    async fn move_children(&mut self, count: usize, target: ChainId) {
+        let children = self
+            .state
+            .children
+            .indices()
+            .await
+            .expect("Failed to load child chain registry");
+        let mut moved = 0;
+
+        for child in children {
+            if moved == count {
+                break;
+            }
+
+            if child == target
+                || self
+                    .state
+                    .moving_children
+                    .contains_key(&child)
+                    .await
+                    .expect("Failed to load moving child chains")
+            {
+                continue;
+            }
+
+            let stake = self.child_record(child).await.stake;
+
+            self.state
+                .moving_children
+                .insert(&child, target)
+                .expect("Failed to update moving child chains");
+            self.runtime.send_message(
+                child,
+                Message::MoveToParent {
+                    parent: target,
+                    stake,
+                },
+            );
+            moved += 1;
+        }
+    }
+
+    /// Flushes the values pending since the last flush to the `parent` chain that this chain is
+    /// leaving, and then sends it the `notification` that it left.
+    ///
+    /// Chains that are unstaking keep their pending values, since the parent chain would reject
+    /// them.
+    async fn leave_parent(&mut self, parent: ChainId, notification: Message) {
+        if (*self.state.value.get() != 0 || *self.state.contributions.get() != 0)
+            && !*self.state.unstaking.get()
+        {
+            self.flush(Some(parent)).await;
+        }
+
+        self.runtime.send_message(parent, notification);
+    }
+
+    /// Removes a `child` chain that left this chain from the registry.
+    ///
+    /// The stake of a child chain that `moved` where it was asked to is forwarded to its new
+    /// parent chain, otherwise it is added to the stake kept with the former child chains until it
+    /// is withdrawn, restarting the unstaking cooldown. The contributions it made since the last
+    /// reward distribution are forfeited.
+    async fn deregister_child(&mut self, child: ChainId, moved: bool) {
+        let record = self.child_record(child).await;
+        let new_parent = if moved {
+            let new_parent = self
+                .state
+                .moving_children
+                .get(&child)
+                .await
+                .expect("Failed to load moving child chains")
+                .unwrap_or_else(|| panic!("Chain {child} was not asked to move"));
+            Some(new_parent)
+        } else {
+            None
+        };
+
+        self.state
+            .children
+            .remove(&child)
+            .expect("Failed to update child chain registry");
+        self.state
+            .child_contributions
+            .remove(&child)
+            .expect("Failed to update child contributions");
+        self.state
+            .moving_children
+            .remove(&child)
+            .expect("Failed to update moving child chains");
+
+        if record.stake.is_zero() {
+            return;
+        }
+
+        if let Some(new_parent) = new_parent {
+            let application_account = AccountOwner::from(self.runtime.application_id());
+            self.runtime.transfer(
+                application_account,
+                Account::new(new_parent, application_account),
+                record.stake,
+            );
+            self.release_held_stake(record.stake);
+        } else {
+            let former_record = self
+                .state
+                .former_children
+                .get_mut_or_default(&child)
+                .await
+                .expect("Failed to load former child chains");
+
+            former_record
+                .stake
+                .try_add_assign(record.stake)
+                .expect("Former child chain stake overflow");
+            former_record.slashed.saturating_add_assign(record.slashed);
+            former_record.unstaking_since = Some(self.runtime.system_time());
+        }
+    }
+
+    /// Sends the values aggregated since the last flush to the `parent` chain, tagged with the
+    /// current epoch and the root of its contribution tree.
+    ///
+    /// The flush is always published to the flushes stream, which is the only way it is delivered
+    /// if there is no `parent` chain. Chains that shard the aggregation also send the values of
+    /// each metric to the reducer chain it is assigned to.
+    ///
+    /// Chains with backup parent chains also replicate the flush, tracking it until the parent
+    /// chain acknowledges it.
+    async fn flush(&mut self, parent: Option<ChainId>) {
+        let epoch = *self.state.epoch.get();
+        let value = mem::take(self.state.value.get_mut());
+        let contributions = mem::take(self.state.contributions.get_mut());
+        let commitment = self.contribution_root(epoch).await;
+        let mut replicas = mem::take(self.state.pending_replicas.get_mut());
+        self.record_value_update();
+
+        if self.runtime.application_parameters().aggregation_mode == AggregationMode::Sharded {
+            self.send_metric_values_to_reducers().await;
+        }
+
+        if let Some(parent) = parent {
+            if !self.state.backup_parents.get().is_empty() {
+                let sequence = *self.state.next_flush_sequence.get();
+                self.state.next_flush_sequence.set(sequence + 1);
+
+                replicas.push(ReplicatedFlush {
+                    child: self.runtime.chain_id(),
+                    sequence,
+                    value: value.saturating_sub(replicas.iter().map(|replica| replica.value).sum()),
+                    contributions: contributions
+                        .saturating_sub(replicas.iter().map(|replica| replica.contributions).sum()),
+                });
+                self.state
+                    .unacknowledged_flushes
+                    .insert(
+                        &sequence,
+                        UnacknowledgedFlush {
+                            parent,
+                            epoch,
+                            value,
+                            contributions,
+                            commitment,
+                            replicas: replicas.clone(),
+                        },
+                    )
+                    .expect("Failed to track unacknowledged flush");
+            }
+
+            self.runtime.send_message(
+                parent,
+                Message::Flush {
+                    epoch,
+                    value,
+                    contributions,
+                    commitment,
+                    replicas,
+                },
+            );
+        }
+        self.emit(AggregationEvent::FlushSent {
+            parent,
+            epoch,
+            value,
+            contributions,
+            commitment,
+        });
+        self.state.last_flush.set(Some(FlushRecord {
+            epoch,
+            value,
+            contributions,
+            commitment,
+            block_height: self.runtime.block_height(),
+            timestamp: self.runtime.system_time(),
+        }));
+    }
+
+    /// Sends the values of each metric submitted since the last flush to the reducer chain the
+    /// metric is assigned to, with one message for each reducer chain.
+    async fn send_metric_values_to_reducers(&mut self) {
+        let parameters = self.runtime.application_parameters();
+        let mut values_by_reducer = BTreeMap::<ChainId, Vec<MetricValue>>::new();
+
+        for (metric, value) in self
+            .state
+            .pending_metric_values
+            .index_values()
+            .await
+            .expect("Failed to load pending metric values")
+        {
+            let reducer = parameters
+                .reducer_chain(&metric)
+                .expect("Sharded aggregation requires at least one reducer chain");
+
+            values_by_reducer.entry(reducer).or_default().push(value);
+        }
+
+        self.state.pending_metric_values.clear();
+
+        for (reducer, values) in values_by_reducer {
+            self.runtime
+                .send_message(reducer, Message::ReduceMetrics { values });
+        }
+    }
+
+    /// Merges the values flushed by a child chain during an `epoch`, committing to them in the
+    /// epoch's contribution tree.
+    ///
+    /// The `replicas` included in the flush that this chain already merged are discounted, and the
+    /// others are forwarded with the next flush. Returns the number of contributions merged.
+    async fn merge_flush(
+        &mut self,
+        epoch: u64,
+        leaf: ContributionLeaf,
+        replicas: Vec<ReplicatedFlush>,
+    ) -> u64 {
+        let ContributionLeaf {
+            child,
+            value,
+            contributions,
+            ..
+        } = leaf;
+
+        self.commit_contribution(epoch, leaf).await;
+
+        let (value, contributions, replicas) = self
+            .discount_merged_replicas(value, contributions, replicas)
+            .await;
+
+        let current_epoch = *self.state.epoch.get();
+        assert!(
+            epoch <= current_epoch,
+            "Child chain {child} flushed values for epoch {epoch}, which has not started"
+        );
+
+        self.emit(AggregationEvent::ChildMerged {
+            child,
+            epoch,
+            value,
+            contributions,
+        });
+
+        if epoch < current_epoch {
+            self.merge_late_flush(epoch, value, contributions, replicas)
+                .await;
+        } else {
+            self.state.value.set(self.state.value.get() + value);
+            *self.state.contributions.get_mut() += contributions;
+
+            if self.state.parent.get().is_some() {
+                self.state.pending_replicas.get_mut().extend(replicas);
+            }
+
+            self.record_value_update();
+            self.record_history(child).await;
+            self.update_rollups(value).await;
+        }
+
+        contributions
+    }
+
+    /// Discounts the `replicas` that this chain already merged from the `value` and
+    /// `contributions` of a flush that includes them.
+    ///
+    /// Replicas more than `max_unacknowledged_flushes` below the highest sequence number merged
+    /// from the same chain are assumed to have been merged already.
+    ///
+    /// Returns the remaining value and contributions, and the replicas merged for the first time.
+    async fn discount_merged_replicas(
+        &mut self,
+        mut value: u64,
+        mut contributions: u64,
+        replicas: Vec<ReplicatedFlush>,
+    ) -> (u64, u64, Vec<ReplicatedFlush>) {
+        let window = u64::from(
+            self.runtime
+                .application_parameters()
+                .max_unacknowledged_flushes,
+        );
+        let mut new_replicas = Vec::new();
+
+        for replica in replicas {
+            let merged = self
+                .state
+                .merged_replicas
+                .get_mut_or_default(&replica.child)
+                .await
+                .expect("Failed to load merged replicated flushes");
+
+            if replica.sequence.saturating_add(window) < merged.highest
+                || merged.sequences.contains(&replica.sequence)
+            {
+                value = value.saturating_sub(replica.value);
+                contributions = contributions.saturating_sub(replica.contributions);
+            } else {
+                merged.highest = merged.highest.max(replica.sequence);
+                merged.sequences.insert(replica.sequence);
+                merged.sequences = merged
+                    .sequences
+                    .split_off(&merged.highest.saturating_sub(window));
+                new_replicas.push(replica);
+            }
+        }
+
+        (value, contributions, new_replicas)
+    }
+
+    /// Merges the flushes that subscribed child chains published to their event streams, up to
+    /// the `next_index` of each update.
+    ///
+    /// Child chains publishing to streams don't know which chains merge their values, so they
+    /// can't follow their epochs. Their flushes are merged into the current epoch instead.
+    async fn process_streams(&mut self, updates: Vec<StreamUpdate>) {
+        for StreamUpdate {
+            chain_id: child,
+            next_index,
+        } in updates
+        {
+            let first_index = self
+                .state
+                .stream_subscriptions
+                .get(&child)
+                .await
+                .expect("Failed to load stream subscriptions")
+                .unwrap_or_else(|| panic!("Chain is not subscribed to child chain {child}"));
+
+            for index in first_index..next_index {
+                let event = self.runtime.read_event(child, FLUSHES_STREAM.into(), index);
+                let AggregationEvent::FlushSent {
+                    value,
+                    contributions,
+                    commitment,
+                    ..
+                } = event
+                else {
+                    panic!("Unexpected event {event:?} in the flushes stream of chain {child}");
+                };
+                let epoch = *self.state.epoch.get();
+
+                self.merge_flush(
+                    epoch,
+                    ContributionLeaf {
+                        child,
+                        value,
+                        contributions,
+                        commitment,
+                    },
+                    Vec::new(),
+                )
+                .await;
+            }
+
+            if next_index > first_index {
+                self.state
+                    .stream_subscriptions
+                    .insert(&child, next_index)
+                    .expect("Failed to update stream subscription");
+            }
+        }
+    }
+
+    /// Adds a flush received from a child chain to the contribution tree of its `epoch`.
+    async fn commit_contribution(&mut self, epoch: u64, leaf: ContributionLeaf) {
+        self.state
+            .contribution_trees
+            .get_mut_or_default(&epoch)
+            .await
+            .expect("Failed to load contribution tree")
+            .push(&leaf);
+        self.state
+            .contribution_leaves
+            .load_entry_mut(&epoch)
+            .await
+            .expect("Failed to load contribution tree")
+            .push(leaf);
+    }
+
+    /// Returns the root of the contribution tree of an `epoch`.
+    async fn contribution_root(&self, epoch: u64) -> CryptoHash {
+        self.state
+            .contribution_trees
+            .get(&epoch)
+            .await
+            .expect("Failed to load contribution tree")
+            .unwrap_or_default()
+            .root()
+    }
+
+    /// Merges values flushed by a child chain for an `epoch` that has already been closed.
+    ///
+    /// The root chain adds them to the epoch's archived aggregate, while other chains forward them
+    /// to their parent chain with the updated root of the epoch's contribution tree and the
+    /// `replicas` they include.
+    async fn merge_late_flush(
+        &mut self,
+        epoch: u64,
+        value: u64,
+        contributions: u64,
+        replicas: Vec<ReplicatedFlush>,
+    ) {
+        if let Some(parent) = *self.state.parent.get() {
+            let commitment = self.contribution_root(epoch).await;
+
+            self.runtime.send_message(
+                parent,
+                Message::Flush {
+                    epoch,
+                    value,
+                    contributions,
+                    commitment,
+                    replicas,
+                },
+            );
+            return;
+        }
+
+        let archive = self
+            .state
+            .archived_epochs
+            .get_mut(&epoch)
+            .await
+            .expect("Failed to load archived epochs")
+            .unwrap_or_else(|| panic!("Epoch {epoch} has not been archived"));
+
+        archive.value += value;
+        archive.contributions += contributions;
+        archive.late_flushes += 1;
+    }
+
+    /// Delivers the final aggregate of a closed `epoch` from its `archive` to the configured
+    /// consumer application, if there is one.
+    ///
+    /// Consumers on this chain are called directly, while consumers on other chains are called by
+    /// this application on their chain after it receives the aggregate in a message.
+    fn deliver_aggregate(&mut self, epoch: u64, archive: &ArchivedEpoch) {
+        let Some(consumer) = *self.state.consumer.get() else {
+            return;
+        };
+        let aggregate = FinalizedAggregate {
+            root_chain: self.runtime.chain_id(),
+            epoch,
+            value: archive.value,
+            contributions: archive.contributions,
+            closed_at: archive.closed_at,
+        };
+
+        if consumer.chain_id == self.runtime.chain_id() {
+            self.runtime.call_application(
+                true,
+                consumer.application_id.with_abi::<AggregateConsumerAbi>(),
+                &aggregate,
+            );
+        } else {
+            self.runtime.send_message(
+                consumer.chain_id,
+                Message::DeliverAggregate {
+                    consumer: consumer.application_id,
+                    aggregate,
+                },
+            );
+        }
+    }
+
+    /// Sends a `message` to all the registered child chains, except the ones that are unstaking.
+    async fn broadcast_to_children(&mut self, message: Message) {
+        let children = self
+            .state
+            .children
+            .index_values()
+            .await
+            .expect("Failed to read child chain registry");
+
+        for (child, record) in children {
+            if record.unstaking_since.is_none() {
+                self.runtime.send_message(child, message.clone());
+            }
+        }
+    }
+
+    /// Applies a new `version` of the tree `config` and forwards it to the child chains.
+    async fn apply_config(&mut self, version: u64, config: TreeConfig) {
+        self.state.config_version.set(version);
+        self.state.tree_config.set(Some(config.clone()));
+
+        self.broadcast_to_children(Message::UpdateConfig { version, config })
+            .await;
+    }
+
+    /// Returns the tree configuration applied by this chain, or the one set by the application
+    /// parameters if the root chain didn't broadcast one.
+    fn tree_config(&mut self) -> TreeConfig {
+        match self.state.tree_config.get() {
+            Some(config) => config.clone(),
+            None => self.runtime.application_parameters().tree_config(),
+        }
+    }
+
+    /// Records that the value changed in the current block, so that clients can follow it.
+    ///
+    /// Also publishes an event for every configured threshold that the value rose to or above.
+    fn record_value_update(&mut self) {
+        let value = *self.state.value.get();
+        let previous_value = self
+            .state
+            .last_value_update
+            .get()
+            .as_ref()
+            .map_or(0, |update| update.value);
+
+        if value > previous_value {
+            let thresholds = self.runtime.application_parameters().value_thresholds;
+
+            for &threshold in thresholds.range(previous_value + 1..=value) {
+                self.emit(AggregationEvent::ThresholdCrossed { threshold, value });
+            }
+        }
+
+        self.state.last_value_update.set(Some(ValueUpdate {
+            value,
+            block_height: self.runtime.block_height(),
+            timestamp: self.runtime.system_time(),
+        }));
+    }
+
+    /// Publishes an `event` to its stream.
+    fn emit(&mut self, event: AggregationEvent) {
+        self.runtime.emit(event.stream_name(), &event);
+    }
+
+    /// Counts an accepted value towards the contributions of this chain and of the registered
+    /// device that signed the block, if there is one.
+    async fn record_contribution(&mut self) {
+        *self.state.contributions.get_mut() += 1;
+
+        if let Some(signer) = self.runtime.authenticated_signer() {
+            if let Some(device) = self
+                .state
+                .devices
+                .get_mut(&signer)
+                .await
+                .expect("Failed to load registered device")
+            {
+                device.contributions += 1;
+            }
+        }
+    }
+
+    /// Aggregates a `value` measured at the `timestamp`, if it is inside the allowed range of its
+    /// `metric`.
+    async fn submit(&mut self, metric: Option<String>, value: u64, timestamp: Timestamp) {
+        if self.check_value_range(metric.clone(), value, timestamp) {
+            self.state.value.set(self.state.value.get() + value);
+            self.record_value_update();
+            self.record_contribution().await;
+
+            if self.runtime.application_parameters().aggregation_mode == AggregationMode::Sharded {
+                self.record_metric_value(metric.unwrap_or_default(), value)
+                    .await;
+            }
+        }
+    }
+
+    /// Records a `value` submitted for a `metric`, to be sent to the metric's reducer chain with
+    /// the next flush.
+    async fn record_metric_value(&mut self, metric: String, value: u64) {
+        let mut pending = self
+            .state
+            .pending_metric_values
+            .get(&metric)
+            .await
+            .expect("Failed to load pending metric values")
+            .unwrap_or_else(|| MetricValue {
+                metric: metric.clone(),
+                value: 0,
+                contributions: 0,
+            });
+
+        pending.value += value;
+        pending.contributions += 1;
+        self.state
+            .pending_metric_values
+            .insert(&metric, pending)
+            .expect("Failed to update pending metric values");
+    }
+
+    /// Checks that none of the `readings` of a batch was measured in the future.
+    ///
+    /// The values are checked against their allowed ranges as each reading is submitted, where
+    /// rejecting one of them fails the whole block, so the batch is never partially aggregated.
+    fn check_batch(&mut self, readings: &[Reading]) {
+        let now = self.runtime.system_time();
+
+        for (index, reading) in readings.iter().enumerate() {
+            assert!(
+                reading.timestamp <= now,
+                "Reading {index} of the batch has a timestamp in the future"
+            );
+        }
+    }
+
+    /// Checks if a submitted `value` is inside the allowed range configured for its `metric`.
+    ///
+    /// Returns `true` if the value should be aggregated. Out-of-range values either cause the
+    /// block to be rejected or are recorded in the quarantine log with the `timestamp` at which
+    /// they were measured, depending on the configured [`OutOfRangePolicy`].
+    fn check_value_range(
+        &mut self,
+        metric: Option<String>,
+        value: u64,
+        timestamp: Timestamp,
+    ) -> bool {
+        let Some(metric) = metric else {
+            return true;
+        };
+        let config = self.tree_config();
+        let Some(allowed_range) = config.value_range(&metric) else {
+            return true;
+        };
+
+        if allowed_range.contains(value) {
+            return true;
+        }
+
+        match config.out_of_range_policy {
+            OutOfRangePolicy::Reject => panic!(
+                "Value {value} is outside of the allowed range for metric {metric:?} \
+                ({}..={})",
+                allowed_range.min, allowed_range.max
+            ),
+            OutOfRangePolicy::Quarantine => {
+                self.state.quarantine.push(QuarantinedReading {
+                    metric,
+                    value,
+                    allowed_range,
+                    block_height: self.runtime.block_height(),
+                    timestamp,
+                });
+                false
+            }
+        }
+    }
+
+    /// Records a snapshot of the current value in the history, after merging values from a `child`
+    /// chain.
+    ///
+    /// The snapshot is skipped if the previous one was taken less than the configured history
+    /// interval ago.
+    async fn record_history(&mut self, child: ChainId) {
+        let timestamp = self.runtime.system_time();
+        let interval = self.runtime.application_parameters().history_interval;
+        let snapshot_count = self.state.history.count();
+
+        if interval > TimeDelta::ZERO && snapshot_count > 0 {
+            let last_snapshot = self
+                .state
+                .history
+                .get(snapshot_count - 1)
+                .await
+                .expect("Failed to load value history")
+                .expect("Snapshots before the history count should exist");
+
+            if timestamp < last_snapshot.timestamp.saturating_add(interval) {
+                return;
+            }
+        }
+
+        self.state.history.push(ValueSnapshot {
+            timestamp,
+            block_height: self.runtime.block_height(),
+            value: *self.state.value.get(),
+            child,
+        });
+    }
+
+    /// Adds a merge of `merged_value` from a child chain to the rollups of every [`Resolution`].
+    ///
+    /// Rollups whose time buckets ended before the configured retention are pruned.
+    async fn update_rollups(&mut self, merged_value: u64) {
+        let timestamp = self.runtime.system_time();
+        let value = *self.state.value.get();
+        let retention = self.runtime.application_parameters().rollup_retention;
+
+        for resolution in Resolution::ALL {
+            let start = resolution.bucket_start(timestamp);
+            let open_rollup = self
+                .state
+                .open_rollups
+                .get(&resolution)
+                .await
+                .expect("Failed to load rollups");
+
+            let rollup = match open_rollup {
+                Some(mut rollup) if rollup.start == start => {
+                    rollup.merge(merged_value, value);
+                    rollup
+                }
+                previous_rollup => {
+                    if let Some(previous_rollup) = previous_rollup {
+                        self.state
+                            .closed_rollups
+                            .load_entry_mut(&resolution)
+                            .await
+                            .expect("Failed to load rollups")
+                            .push_back(previous_rollup);
+                    }
+                    Rollup::new(start, merged_value, value)
+                }
+            };
+
+            self.state
+                .open_rollups
+                .insert(&resolution, rollup)
+                .expect("Failed to update rollups");
+
+            if let Some(&retention) = retention.get(&resolution) {
+                self.prune_rollups(resolution, timestamp.saturating_sub(retention))
+                    .await;
+            }
+        }
+    }
+
+    /// Removes the closed rollups with the `resolution` whose time buckets ended before the
+    /// `cutoff` time.
+    async fn prune_rollups(&mut self, resolution: Resolution, cutoff: Timestamp) {
+        let rollups = self
+            .state
+            .closed_rollups
+            .load_entry_mut(&resolution)
+            .await
+            .expect("Failed to load rollups");
+
+        while let Some(rollup) = rollups.front().await.expect("Failed to load rollups") {
+            if rollup.start.saturating_add(resolution.duration()) > cutoff {
+                break;
+            }
+
+            rollups.delete_front();
+        }
+    }
+
+    /// Returns the registration of a `child` chain, failing if it is not registered.
+    async fn child_record(&self, child: ChainId) -> ChildRecord {
+        self.state
+            .children
+            .get(&child)
+            .await
+            .expect("Failed to load child chain registry")
+            .unwrap_or_else(|| panic!("Chain {child} is not registered as a child chain"))
+    }
+
+    /// Returns the balance of the application's account on this chain that is not held for child
+    /// chains or joining chains, or reserved to fail over.
+    async fn unlocked_balance(&mut self) -> Amount {
+        let application_account = AccountOwner::from(self.runtime.application_id());
+        let mut locked_stake = self
+            .state
+            .held_stake
+            .get()
+            .try_add(*self.state.reserved_stake.get())
+            .expect("Locked stake overflow");
+
+        for (_, stake) in self
+            .state
+            .joining_stakes
+            .index_values()
+            .await
+            .expect("Failed to load joining chain stakes")
+        {
+            locked_stake
+                .try_add_assign(stake)
+                .expect("Locked stake overflow");
+        }
+
+        self.runtime
+            .owner_balance(application_account)
+            .saturating_sub(locked_stake)
+    }
+
+    /// Stops counting an `amount` of stake that left the application's account, or that was slashed,
+    /// as held for child chains.
+    fn release_held_stake(&mut self, amount: Amount) {
+        let held_stake = self.state.held_stake.get_mut();
+
+        *held_stake = held_stake.saturating_sub(amount);
+    }
+
+    /// Checks if a `child` chain has staked enough to have its values aggregated.
+    async fn check_child_stake(&mut self, child: ChainId) {
+        let required_stake = self.runtime.application_parameters().registration_stake;
+
+        if required_stake.is_zero() {
+            return;
+        }
+
+        let record = self
+            .state
+            .children
+            .get(&child)
+            .await
+            .expect("Failed to load child chain registry");
+
+        assert!(
+            record.is_some_and(|record| {
+                record.unstaking_since.is_none() && record.stake >= required_stake
+            }),
+            "Child chain {child} has not staked the required amount"
+        );
+    }
+
+    /// Distributes the rewards of an `epoch` between the child chains and the registered devices,
+    /// proportionally to the values they contributed since the last distribution.
+    ///
+    /// The `amount` must be available in this application's account on the current chain, besides
+    /// the stake held for child chains and the stake reserved to fail over. Any remainder left from
+    /// rounding stays in the account.
+    async fn distribute_rewards(&mut self, epoch: u64, amount: Amount) {
+        let application_account = AccountOwner::from(self.runtime.application_id());
+
+        assert!(
+            amount <= self.unlocked_balance().await,
+            "Rewards can't be paid from the stake held in the application's account"
+        );
+
+        let children = self
+            .state
+            .child_contributions
+            .index_values()
+            .await
+            .expect("Failed to read child contributions");
+        let devices = self
+            .state
+            .devices
+            .index_values()
+            .await
+            .expect("Failed to read registered devices");
+
+        let total_contributions = children
+            .iter()
+            .map(|(_, contributions)| *contributions)
+            .chain(devices.iter().map(|(_, device)| device.contributions))
+            .sum::<u64>();
+        let mut payouts = Vec::new();
+
+        for (child, contributions) in children {
+            let payout = self.pay_reward(
+                Account::new(child, application_account),
+                contributions,
+                total_contributions,
+                amount,
+            );
+
+            if !payout.amount.is_zero() {
+                self.runtime.send_message(
+                    child,
+                    Message::Reward {
+                        epoch,
+                        amount: payout.amount,
+                    },
+                );
+            }
+
+            payouts.push(payout);
+        }
+
+        for (owner, mut device) in devices {
+            if device.contributions == 0 {
+                continue;
+            }
+
+            payouts.push(self.pay_reward(
+                device.reward_account,
+                device.contributions,
+                total_contributions,
+                amount,
+            ));
+
+            device.contributions = 0;
+            self.state
+                .devices
+                .insert(&owner, device)
+                .expect("Failed to reset device contributions");
+        }
+
+        self.state.child_contributions.clear();
+        self.state.reward_epoch.set(epoch);
+        self.state.reward_settlements.push(RewardSettlement {
+            epoch,
+            amount,
+            contributions: total_contributions,
+            payouts,
+        });
+    }
+
+    /// Transfers to the `recipient` its share of the reward `amount`, based on its
+    /// `contributions`.
+    fn pay_reward(
+        &mut self,
+        recipient: Account,
+        contributions: u64,
+        total_contributions: u64,
+        amount: Amount,
+    ) -> RewardPayout {
+        let share = proportional_share(amount, contributions, total_contributions);
+
+        if !share.is_zero() {
+            let application_account = AccountOwner::from(self.runtime.application_id());
+            self.runtime.transfer(application_account, recipient, share);
+        }
+
+        RewardPayout {
+            recipient,
+            contributions,
+            amount: share,
+        }
+    }
+}
+
+impl Rollup {
+    /// Creates a rollup for the time bucket starting at `start`, with its first merge.
+    fn new(start: Timestamp, merged_value: u64, value: u64) -> Self {
+        Rollup {
+            start,
+            merges: 1,
+            merged_value,
+            min_value: value,
+            max_value: value,
+            last_value: value,
+        }
+    }
+
+    /// Adds a merge of `merged_value` that resulted in the chain's `value` to this rollup.
+    fn merge(&mut self, merged_value: u64, value: u64) {
+        self.merges += 1;
+        self.merged_value = self.merged_value.saturating_add(merged_value);
+        self.min_value = self.min_value.min(value);
+        self.max_value = self.max_value.max(value);
+        self.last_value = value;
+    }
+}
+
+/// Checks that the value ranges of a tree `config` aren't empty, and that each metric has at most
+/// one of them.
+fn check_tree_config(config: &TreeConfig) {
+    for (index, range) in config.value_ranges.iter().enumerate() {
+        assert!(
+            range.min <= range.max,
+            "The value range of metric {:?} has a minimum above its maximum",
+            range.metric
+        );
+        assert!(
+            config.value_ranges[..index]
+                .iter()
+                .all(|other| other.metric != range.metric),
+            "Metric {:?} has more than one value range",
+            range.metric
+        );
+    }
+}
+
+/// Calculates the share of an `amount` that corresponds to `contributions` out of
+/// `total_contributions`, rounding down.
+fn proportional_share(amount: Amount, contributions: u64, total_contributions: u64) -> Amount {
+    if contributions == 0 {
+        return Amount::ZERO;
+    }
+
+    let amount = u128::from(amount);
+    let contributions = u128::from(contributions);
+    let total_contributions = u128::from(total_contributions);
+
+    // Split the calculation to avoid overflowing when multiplying large amounts.
+    let share = amount / total_contributions * contributions
+        + amount % total_contributions * contributions / total_contributions;
+
+    Amount::from_attos(share)
+}
@@ -1,9 +1,16 @@
 // Copyright (c) Zefchain Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use async_graphql::{Request, Response};
+pub mod merkle;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use async_graphql::{Enum, InputObject, Request, Response, SimpleObject};
 use linera_sdk::{
-    linera_base_types::ChainId,
+    linera_base_types::{
+        Account, AccountOwner, Amount, ApplicationId, ChainId, CryptoHash, MessageId, StreamName,
+        TimeDelta, Timestamp,
+    },
     abi::{ContractAbi, ServiceAbi},
     graphql::GraphQLMutationRoot,
 };
@@ -13,7 +20,7 @@ pub struct DepinDemoAbi;
 
 impl ContractAbi for DepinDemoAbi {
     type Operation = Operation;
-    type Response = ();
+    type Response = OperationResponse;
 }
 
 impl ServiceAbi for DepinDemoAbi {
@@ -21,9 +28,501 @@ impl ServiceAbi for DepinDemoAbi {
     type QueryResponse = Response;
 }
 
+/// The ABI that applications receiving the aggregates finalized by the root chain must implement.
+pub struct AggregateConsumerAbi;
+
+impl ContractAbi for AggregateConsumerAbi {
+    type Operation = FinalizedAggregate;
+    type Response = ();
+}
+
 #[derive(Debug, Deserialize, Serialize, GraphQLMutationRoot)]
 pub enum Operation {
     ConnectToParent { parent: ChainId },
-    Submit { value: u64 },
+    Submit { metric: Option<String>, value: u64 },
+    SubmitBatch { readings: Vec<Reading> },
     Flush,
+    RegisterDevice { owner: AccountOwner, reward_account: Account },
+    DistributeRewards { amount: Amount },
+    Unstake,
+    WithdrawStake,
+    Slash { child: ChainId, amount: Amount },
+    CloseEpoch,
+    SubscribeToChild { child: ChainId },
+    UnsubscribeFromChild { child: ChainId },
+    ProcessStreams { updates: Vec<StreamUpdate> },
+    ReadAggregate,
+    AuthorizeApplication { application_id: ApplicationId, permissions: ApplicationPermissions },
+    RevokeApplication { application_id: ApplicationId },
+    SetConsumer { consumer: Option<AggregateConsumer> },
+    Reparent { parent: ChainId },
+    WithdrawStakeFrom { parent: ChainId },
+    Disconnect,
+    Retire,
+    Join { root: ChainId, aggregator: bool },
+    Rebalance { target: Option<ChainId>, balance: Amount },
+    OpenEdgeChain { owner: AccountOwner, balance: Amount },
+    UpdateConfig { config: TreeConfig },
+    SetRole { role: NodeRole },
+    Initialize { setup: ChainSetup },
+    SetBackupParents { parents: Vec<ChainId> },
+    ReportShard,
+}
+
+/// The result of an [`Operation`], returned to the applications that call this one.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum OperationResponse {
+    /// The operation was executed and has no result.
+    Ok,
+    /// The aggregate read by an [`Operation::ReadAggregate`].
+    Aggregate(LocalAggregate),
+}
+
+/// The values aggregated by a chain that have not been flushed yet.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct LocalAggregate {
+    pub epoch: u64,
+    pub value: u64,
+    pub contributions: u64,
+}
+
+/// What another application on the same chain is allowed to do by calling this application.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, InputObject, PartialEq, Serialize, SimpleObject,
+)]
+#[graphql(input_name = "ApplicationPermissionsInput")]
+pub struct ApplicationPermissions {
+    /// Whether the application can submit readings.
+    pub submit: bool,
+    /// Whether the application can read the local aggregate.
+    pub read_aggregate: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Message {
+    /// The values aggregated by a child chain since its last flush, during an `epoch`.
+    Flush {
+        epoch: u64,
+        value: u64,
+        contributions: u64,
+        /// The root of the child chain's contribution tree for the epoch.
+        commitment: CryptoHash,
+        /// The flushes from chains with backup parent chains that are included in this one.
+        replicas: Vec<ReplicatedFlush>,
+    },
+    /// A parent chain's acknowledgement that it received the flush with the `sequence` number
+    /// from a chain with backup parent chains.
+    AcknowledgeFlush { sequence: u64 },
+    /// A child chain's share of the rewards of an epoch, transferred to its application account.
+    Reward { epoch: u64, amount: Amount },
+    /// A request to register the sending chain as a child, with the stake it transferred.
+    Register { stake: Amount },
+    /// A request from a child chain to start the cooldown before its stake can be withdrawn.
+    Unstake,
+    /// A request from a child chain to have its stake returned after the cooldown.
+    WithdrawStake,
+    /// A notification from a child chain that it moved to another parent chain or disconnected.
+    Deregister,
+    /// A notification from a child chain that it moved to the parent chain it was asked to move
+    /// to with [`Message::MoveToParent`].
+    Moved,
+    /// A notification from a child chain that it was decommissioned.
+    Retire,
+    /// A request to the root chain to assign a parent chain to the sending chain, which also
+    /// accepts child chains of its own if it joins as an `aggregator`, with the `stake` it
+    /// transferred to the root chain for its parent chain.
+    Join { aggregator: bool, stake: Amount },
+    /// The root chain's reply to a [`Message::Join`] request, with the assigned `parent` chain
+    /// and the `stake` forwarded to it.
+    AssignParent { parent: ChainId, stake: Amount },
+    /// A request from the parent chain to move to another `parent` chain, to relieve it of some of
+    /// its child chains, with the `stake` it forwards to the new parent chain once the child chain
+    /// leaves.
+    MoveToParent { parent: ChainId, stake: Amount },
+    /// A request to a chain opened by the sending chain to take a `role` and connect to a
+    /// `parent` chain, with the ID of the message that opened it and the `stake` the sending chain
+    /// paid to the parent chain for it.
+    ConnectOpenedChain {
+        parent: ChainId,
+        role: NodeRole,
+        open_chain_message: MessageId,
+        stake: Amount,
+    },
+    /// A new `version` of the configuration of the tree, broadcast by the root chain.
+    UpdateConfig { version: u64, config: TreeConfig },
+    /// The values of the metrics assigned to the receiving reducer chain, flushed by a chain that
+    /// aggregates through [`AggregationMode::Sharded`].
+    ReduceMetrics { values: Vec<MetricValue> },
+    /// The values of all the metrics reduced by the sending reducer chain, to be combined with
+    /// the other shards on the chain that created the application.
+    ReportShard { values: Vec<MetricValue> },
+    /// A notification from the parent chain that a new accounting epoch has started.
+    NewEpoch { epoch: u64 },
+    /// An aggregate finalized by the root chain, to be delivered to a `consumer` application on
+    /// the receiving chain.
+    DeliverAggregate {
+        consumer: ApplicationId,
+        aggregate: FinalizedAggregate,
+    },
+}
+
+/// A flush from a chain with backup parent chains, which may reach a common ancestor chain through
+/// more than one parent chain after the chain fails over.
+///
+/// Chains remember the replicated flushes they merge, and discount the ones that reach them again.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, SimpleObject)]
+pub struct ReplicatedFlush {
+    /// The chain that sent the flush.
+    pub child: ChainId,
+    /// The number of the flush among the ones sent by the chain.
+    pub sequence: u64,
+    /// The value and contributions of the flush, excluding the replicated flushes it includes.
+    pub value: u64,
+    pub contributions: u64,
+}
+
+/// The values aggregated for a metric.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, SimpleObject)]
+pub struct MetricValue {
+    /// The metric, which is empty for values submitted without one.
+    pub metric: String,
+    pub value: u64,
+    pub contributions: u64,
+}
+
+/// An application that receives the aggregate of every epoch closed by the root chain.
+#[derive(
+    Clone, Copy, Debug, Deserialize, Eq, InputObject, PartialEq, Serialize, SimpleObject,
+)]
+#[graphql(input_name = "AggregateConsumerInput")]
+pub struct AggregateConsumer {
+    pub application_id: ApplicationId,
+    /// The chain on which the application receives the aggregates.
+    pub chain_id: ChainId,
+}
+
+/// The final aggregate of an epoch closed by the root chain.
+///
+/// Values flushed late for a closed epoch are added to its archive, but not delivered again.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FinalizedAggregate {
+    /// The root chain that closed the epoch.
+    pub root_chain: ChainId,
+    pub epoch: u64,
+    pub value: u64,
+    pub contributions: u64,
+    pub closed_at: Timestamp,
+}
+
+/// A value measured by a device, submitted as part of a batch.
+#[derive(Clone, Debug, Deserialize, Eq, InputObject, PartialEq, Serialize)]
+pub struct Reading {
+    /// The metric the value measures, if any.
+    pub metric: Option<String>,
+    pub value: u64,
+    /// When the value was measured.
+    pub timestamp: Timestamp,
+}
+
+/// The name of the stream with the [`AggregationEvent::FlushSent`] events.
+pub const FLUSHES_STREAM: &str = "flushes";
+/// The name of the stream with the [`AggregationEvent::ChildMerged`] events.
+pub const MERGES_STREAM: &str = "merges";
+/// The name of the stream with the [`AggregationEvent::EpochClosed`] events.
+pub const EPOCHS_STREAM: &str = "epochs";
+/// The name of the stream with the [`AggregationEvent::ThresholdCrossed`] events.
+pub const THRESHOLDS_STREAM: &str = "thresholds";
+
+/// The events published by a chain, so that other applications and indexers can follow the
+/// aggregation without querying each chain's service.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum AggregationEvent {
+    /// The chain flushed its aggregated values, either to its parent chain or only to this
+    /// stream if the application aggregates through [`AggregationMode::Streams`].
+    FlushSent {
+        parent: Option<ChainId>,
+        epoch: u64,
+        value: u64,
+        contributions: u64,
+        commitment: CryptoHash,
+    },
+    /// The chain merged the values flushed by a child chain.
+    ChildMerged {
+        child: ChainId,
+        epoch: u64,
+        value: u64,
+        contributions: u64,
+    },
+    /// The root chain closed an epoch with its final aggregate.
+    EpochClosed {
+        epoch: u64,
+        value: u64,
+        contributions: u64,
+    },
+    /// The chain's aggregated value rose to or above a configured threshold.
+    ThresholdCrossed { threshold: u64, value: u64 },
+}
+
+impl AggregationEvent {
+    /// Returns the name of the stream the event is published to.
+    pub fn stream_name(&self) -> StreamName {
+        let name = match self {
+            AggregationEvent::FlushSent { .. } => FLUSHES_STREAM,
+            AggregationEvent::ChildMerged { .. } => MERGES_STREAM,
+            AggregationEvent::EpochClosed { .. } => EPOCHS_STREAM,
+            AggregationEvent::ThresholdCrossed { .. } => THRESHOLDS_STREAM,
+        };
+
+        StreamName::from(name)
+    }
+}
+
+/// A notification that a child chain published new events to its flushes stream.
+#[derive(Clone, Debug, Deserialize, Eq, InputObject, PartialEq, Serialize)]
+pub struct StreamUpdate {
+    pub chain_id: ChainId,
+    /// The index after the last event published by the child chain.
+    pub next_index: u32,
+}
+
+/// The position of a chain in the aggregation tree, which restricts what it can do.
+#[derive(Clone, Copy, Debug, Deserialize, Enum, Eq, PartialEq, Serialize)]
+pub enum NodeRole {
+    /// A chain where devices submit values, which it flushes to its parent chain.
+    Edge,
+    /// A chain that merges the values flushed by its child chains and flushes them to its parent
+    /// chain.
+    Aggregator,
+    /// The chain at the top of the tree, which merges the values of its child chains but never
+    /// flushes them.
+    Root,
+}
+
+/// How a chain is set up, either when the application is created on it or by the first operation
+/// executed on it.
+#[derive(Clone, Debug, Default, Deserialize, Eq, InputObject, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ChainSetup {
+    /// The parent chain to connect to, if any.
+    pub parent: Option<ChainId>,
+    /// The role of the chain in the tree, if any.
+    pub role: Option<NodeRole>,
+    /// When the chain flushes its values without a `Flush` operation.
+    #[graphql(default)]
+    pub flush_policy: FlushPolicy,
+}
+
+/// When a chain flushes its aggregated values without a `Flush` operation.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, InputObject, PartialEq, Serialize, SimpleObject,
+)]
+#[graphql(input_name = "FlushPolicyInput")]
+#[serde(default)]
+pub struct FlushPolicy {
+    /// The number of contributions after which the pending values are flushed automatically.
+    ///
+    /// If zero, values are only flushed by `Flush` operations.
+    pub max_pending_contributions: u64,
+}
+
+/// How child chains deliver their flushed values to the chains that aggregate them.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Enum, Eq, PartialEq, Serialize)]
+pub enum AggregationMode {
+    /// Child chains send their flushes in messages to the parent chain they connected to.
+    #[default]
+    Messages,
+    /// Child chains publish their flushes to their event stream, and any number of chains can
+    /// subscribe to them and merge them.
+    Streams,
+    /// Chains send the values of each metric to the reducer chain the metric is assigned to,
+    /// which reports the values of all its metrics to the chain that created the application.
+    Sharded,
+}
+
+/// The parameters shared by the application on all chains.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DepinDemoParameters {
+    /// The allowed range of values for each metric, none of which can have a minimum above its
+    /// maximum.
+    ///
+    /// Values submitted for metrics without a configured range are always accepted.
+    pub value_ranges: BTreeMap<String, ValueRange>,
+    /// What to do with values submitted outside of their metric's allowed range.
+    pub out_of_range_policy: OutOfRangePolicy,
+    /// The amount of native tokens a child chain must stake with its parent chain when it
+    /// connects to it.
+    pub registration_stake: Amount,
+    /// How long a child chain must wait after unstaking before it can withdraw its stake.
+    pub unstake_cooldown: TimeDelta,
+    /// The minimum time between two snapshots in a chain's value history.
+    ///
+    /// If zero, a snapshot is recorded every time values from a child chain are merged.
+    pub history_interval: TimeDelta,
+    /// How long the rollups of each [`Resolution`] are kept after their time bucket ends.
+    ///
+    /// Rollups of resolutions without a configured retention are kept forever.
+    pub rollup_retention: BTreeMap<Resolution, TimeDelta>,
+    /// The values that trigger an [`AggregationEvent::ThresholdCrossed`] event when a chain's
+    /// aggregated value rises to or above them.
+    pub value_thresholds: BTreeSet<u64>,
+    /// How child chains deliver their flushed values.
+    pub aggregation_mode: AggregationMode,
+    /// The maximum number of chains the root chain assigns to each parent chain when they join.
+    ///
+    /// If zero, all joining chains are assigned to the root chain.
+    pub fan_out: u32,
+    /// The balance the root chain gives to each aggregator chain it opens for joining chains, taken
+    /// from its own balance.
+    pub aggregator_balance: Amount,
+    /// The maximum number of child chains an aggregator chain should have before some of them are
+    /// moved to another aggregator chain.
+    ///
+    /// If zero, the number of child chains is not limited.
+    pub fan_in_budget: u32,
+    /// The maximum number of flushes an aggregator chain should receive from its child chains
+    /// during each `load_window`, before some of them are moved to another aggregator chain.
+    ///
+    /// If zero, the rate of flushes is not limited.
+    pub flush_budget: u64,
+    /// The length of the windows during which the flushes received from child chains are counted.
+    pub load_window: TimeDelta,
+    /// The number of flushes a chain with backup parent chains sends without them being
+    /// acknowledged before it fails over to its next backup parent chain.
+    ///
+    /// If zero, chains never fail over.
+    pub max_unacknowledged_flushes: u32,
+    /// The chains that reduce the values of the metrics assigned to them, if the application
+    /// aggregates through [`AggregationMode::Sharded`], in which case there must be at least one.
+    pub reducer_chains: Vec<ChainId>,
+}
+
+impl DepinDemoParameters {
+    /// Returns the configuration of the tree set by these parameters, which applies until the
+    /// root chain broadcasts a new one.
+    pub fn tree_config(&self) -> TreeConfig {
+        TreeConfig {
+            value_ranges: self
+                .value_ranges
+                .iter()
+                .map(|(metric, range)| MetricRange {
+                    metric: metric.clone(),
+                    min: range.min,
+                    max: range.max,
+                })
+                .collect(),
+            out_of_range_policy: self.out_of_range_policy,
+        }
+    }
+
+    /// Returns the reducer chain that the `metric` is assigned to, if any are configured.
+    ///
+    /// Metrics are assigned by the 64-bit FNV-1a hash of their name, so that every chain and
+    /// client assigns them to the same reducer chain.
+    pub fn reducer_chain(&self, metric: &str) -> Option<ChainId> {
+        if self.reducer_chains.is_empty() {
+            return None;
+        }
+
+        let hash = metric
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        let index = hash % self.reducer_chains.len() as u64;
+
+        Some(self.reducer_chains[index as usize])
+    }
+}
+
+/// The validation rules that the root chain can update on all the chains of its tree, replacing
+/// the ones in the application parameters.
+#[derive(
+    Clone, Debug, Default, Deserialize, Eq, InputObject, PartialEq, Serialize, SimpleObject,
+)]
+#[graphql(input_name = "TreeConfigInput")]
+pub struct TreeConfig {
+    /// The allowed range of values for each metric.
+    ///
+    /// Values submitted for metrics without a configured range are always accepted.
+    pub value_ranges: Vec<MetricRange>,
+    /// What to do with values submitted outside of their metric's allowed range.
+    pub out_of_range_policy: OutOfRangePolicy,
+}
+
+impl TreeConfig {
+    /// Returns the allowed range of values for a `metric`, if one is configured.
+    pub fn value_range(&self, metric: &str) -> Option<ValueRange> {
+        self.value_ranges
+            .iter()
+            .find(|range| range.metric == metric)
+            .map(|range| ValueRange {
+                min: range.min,
+                max: range.max,
+            })
+    }
+}
+
+/// The allowed range of values for a metric.
+#[derive(Clone, Debug, Deserialize, Eq, InputObject, PartialEq, Serialize, SimpleObject)]
+#[graphql(input_name = "MetricRangeInput")]
+pub struct MetricRange {
+    pub metric: String,
+    pub min: u64,
+    pub max: u64,
+}
+
+/// An inclusive range of values that are acceptable for a metric.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, SimpleObject)]
+pub struct ValueRange {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl ValueRange {
+    /// Checks if the `value` is inside this range.
+    pub fn contains(&self, value: u64) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+}
+
+/// How to handle submitted values that are outside of their metric's allowed range.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Enum, Eq, PartialEq, Serialize)]
+pub enum OutOfRangePolicy {
+    /// Reject the block that contains the value.
+    #[default]
+    Reject,
+    /// Accept the block, but record the value in the quarantine log instead of aggregating it.
+    Quarantine,
+}
+
+/// The sizes of the time buckets in which merged values are rolled up.
+#[derive(
+    Clone, Copy, Debug, Deserialize, Enum, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize,
+)]
+pub enum Resolution {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    /// All the resolutions at which merged values are rolled up.
+    pub const ALL: [Resolution; 3] = [Resolution::Minute, Resolution::Hour, Resolution::Day];
+
+    /// Returns the length of a time bucket with this resolution.
+    pub fn duration(self) -> TimeDelta {
+        match self {
+            Resolution::Minute => TimeDelta::from_secs(60),
+            Resolution::Hour => TimeDelta::from_secs(60 * 60),
+            Resolution::Day => TimeDelta::from_secs(24 * 60 * 60),
+        }
+    }
+
+    /// Returns the start of the time bucket with this resolution that contains the `timestamp`.
+    pub fn bucket_start(self, timestamp: Timestamp) -> Timestamp {
+        let micros = timestamp.micros();
+
+        Timestamp::from(micros - micros % self.duration().as_micros())
+    }
 }
@@ -0,0 +1,166 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Merkle commitments over the flushes a chain receives from its child chains during an epoch.
+//!
+//! Each epoch's flushes are the leaves of a fixed-depth binary Merkle tree, padded with empty
+//! subtrees. Chains only keep the nodes needed to append new leaves and to compute the root, which
+//! they publish with every flush to their parent chain.
+
+#[cfg(test)]
+#[path = "unit_tests/merkle.rs"]
+mod tests;
+
+use async_graphql::SimpleObject;
+use linera_sdk::linera_base_types::{BcsHashable, ChainId, CryptoHash};
+use serde::{Deserialize, Serialize};
+
+/// The depth of the contribution trees, which limits them to `2^32` leaves.
+pub const TREE_DEPTH: usize = 32;
+
+/// A flush received from a child chain, committed to as a leaf of a contribution tree.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, SimpleObject)]
+pub struct ContributionLeaf {
+    pub child: ChainId,
+    pub value: u64,
+    pub contributions: u64,
+    /// The root of the child chain's own contribution tree when it flushed.
+    pub commitment: CryptoHash,
+}
+
+impl BcsHashable<'_> for ContributionLeaf {}
+
+/// A proof that a leaf is included in a contribution tree.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, SimpleObject)]
+pub struct ContributionProof {
+    pub leaf: ContributionLeaf,
+    /// The position of the leaf in the tree.
+    pub index: u64,
+    /// The sibling nodes on the path from the leaf to the root, starting from the leaf.
+    pub siblings: Vec<CryptoHash>,
+}
+
+impl ContributionProof {
+    /// Creates the proof that the leaf at `index` is included in the tree with the `leaves`.
+    ///
+    /// Returns [`None`] if there is no leaf at `index`.
+    pub fn new(leaves: &[ContributionLeaf], index: usize) -> Option<Self> {
+        let leaf = leaves.get(index)?.clone();
+        let mut level = leaves.iter().map(CryptoHash::new).collect::<Vec<_>>();
+        let mut position = index;
+        let mut empty_node = empty_leaf();
+        let mut siblings = Vec::with_capacity(TREE_DEPTH);
+
+        for _ in 0..TREE_DEPTH {
+            siblings.push(level.get(position ^ 1).copied().unwrap_or(empty_node));
+            level = level
+                .chunks(2)
+                .map(|pair| hash_pair(pair[0], pair.get(1).copied().unwrap_or(empty_node)))
+                .collect();
+            position /= 2;
+            empty_node = hash_pair(empty_node, empty_node);
+        }
+
+        Some(ContributionProof {
+            leaf,
+            index: index as u64,
+            siblings,
+        })
+    }
+}
+
+/// Checks if a `proof` shows that its leaf is included in the contribution tree with the `root`.
+pub fn verify_contribution_proof(proof: &ContributionProof, root: CryptoHash) -> bool {
+    if proof.siblings.len() != TREE_DEPTH || proof.index >> TREE_DEPTH != 0 {
+        return false;
+    }
+
+    let mut node = CryptoHash::new(&proof.leaf);
+
+    for (height, &sibling) in proof.siblings.iter().enumerate() {
+        node = if (proof.index >> height) & 1 == 0 {
+            hash_pair(node, sibling)
+        } else {
+            hash_pair(sibling, node)
+        };
+    }
+
+    node == root
+}
+
+/// An append-only contribution tree, which only stores the nodes needed to add leaves and to
+/// compute its root.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ContributionTree {
+    leaf_count: u64,
+    /// The last left node completed at each height of the tree.
+    branch: Vec<CryptoHash>,
+}
+
+impl ContributionTree {
+    /// Returns the number of leaves in the tree.
+    pub fn leaf_count(&self) -> u64 {
+        self.leaf_count
+    }
+
+    /// Appends a `leaf` to the tree.
+    pub fn push(&mut self, leaf: &ContributionLeaf) {
+        assert!(
+            self.leaf_count >> TREE_DEPTH == 0,
+            "Contribution tree is full"
+        );
+
+        let mut node = CryptoHash::new(leaf);
+        let mut size = self.leaf_count + 1;
+        self.leaf_count = size;
+
+        for height in 0..TREE_DEPTH {
+            if size & 1 == 1 {
+                if height == self.branch.len() {
+                    self.branch.push(node);
+                } else {
+                    self.branch[height] = node;
+                }
+                return;
+            }
+
+            node = hash_pair(self.branch[height], node);
+            size /= 2;
+        }
+    }
+
+    /// Computes the root hash of the tree.
+    pub fn root(&self) -> CryptoHash {
+        let mut node = empty_leaf();
+        let mut empty_node = empty_leaf();
+        let mut size = self.leaf_count;
+
+        for height in 0..TREE_DEPTH {
+            node = if size & 1 == 1 {
+                hash_pair(self.branch[height], node)
+            } else {
+                hash_pair(node, empty_node)
+            };
+            empty_node = hash_pair(empty_node, empty_node);
+            size /= 2;
+        }
+
+        node
+    }
+}
+
+/// The pair of child nodes hashed to obtain their parent node in a contribution tree.
+#[derive(Deserialize, Serialize)]
+struct NodePair(CryptoHash, CryptoHash);
+
+impl BcsHashable<'_> for NodePair {}
+
+/// Returns the hash of the parent node of the `left` and `right` nodes.
+fn hash_pair(left: CryptoHash, right: CryptoHash) -> CryptoHash {
+    CryptoHash::new(&NodePair(left, right))
+}
+
+/// Returns the node used in place of missing leaves.
+fn empty_leaf() -> CryptoHash {
+    CryptoHash::from([0; 4])
+}
@@ -9,19 +9,32 @@ mod state;
 #[path = "unit_tests/service.rs"]
 mod tests;
 
-use std::sync::Arc;
+use std::{collections::BTreeMap, fmt, sync::Arc};
 
-use async_graphql::{EmptySubscription, Schema};
+use async_graphql::{
+    EmptySubscription, ErrorExtensions, InputObject, InputValueError, InputValueResult, Scalar,
+    ScalarType, Schema, SimpleObject, Value,
+};
 use linera_sdk::{
-    linera_base_types::ChainId,
     abi::WithServiceAbi,
-    views::View,
+    linera_base_types::{
+        Account, AccountOwner, Amount, ApplicationId, BlockHeight, ChainId, CryptoHash, TimeDelta,
+        Timestamp,
+    },
+    views::{View, ViewError},
     Service, ServiceRuntime,
 };
 
-use depin_demo::Operation;
+use depin_demo::{
+    merkle::ContributionProof, AggregateConsumer, AggregationMode, ApplicationPermissions,
+    ChainSetup, DepinDemoParameters, FlushPolicy, MetricRange, MetricValue, NodeRole, Operation,
+    OutOfRangePolicy, Reading, Resolution, StreamUpdate, TreeConfig,
+};
 
-use self::state::DepinDemoState;
+use self::state::{
+    ArchivedEpoch, DepinDemoState, FlushLoad, FlushRecord, QuarantinedReading, RewardSettlement,
+    Rollup, ShardReport, UnacknowledgedFlush, ValueSnapshot, ValueUpdate,
+};
 
 pub struct DepinDemoService {
     state: Arc<DepinDemoState>,
@@ -35,7 +48,7 @@ impl WithServiceAbi for DepinDemoService {
 }
 
 impl Service for DepinDemoService {
-    type Parameters = ();
+    type Parameters = DepinDemoParameters;
 
     async fn new(runtime: ServiceRuntime<Self>) -> Self {
         DepinDemoService {
@@ -50,19 +63,750 @@ impl Service for DepinDemoService {
 
     async fn handle_query(&self, query: Self::Query) -> Self::QueryResponse {
         Schema::build(
-            self.state.clone(),
+            QueryRoot {
+                state: self.state.clone(),
+                runtime: Arc::clone(&self.runtime),
+            },
             MutationRoot {
+                state: self.state.clone(),
                 runtime: Arc::clone(&self.runtime),
             },
             EmptySubscription,
         )
-            .finish()
-            .execute(query)
-            .await
+        .finish()
+        .execute(query)
+        .await
+    }
+}
+
+struct QueryRoot {
+    state: Arc<DepinDemoState>,
+    runtime: Arc<ServiceRuntime<DepinDemoService>>,
+}
+
+#[async_graphql::Object]
+impl QueryRoot {
+    /// The value aggregated by this chain.
+    async fn value(&self) -> u64 {
+        *self.state.value.get()
+    }
+
+    /// The last update of the value, if it happened in a block after the `after` block height.
+    ///
+    /// Clients can follow the value by listening to the node service's notifications of new
+    /// blocks on this chain, and querying for an update after the last block height they saw.
+    async fn value_update(&self, after: Option<BlockHeight>) -> Option<ValueUpdate> {
+        self.state
+            .last_value_update
+            .get()
+            .clone()
+            .filter(|update| after.is_none_or(|after| update.block_height > after))
+    }
+
+    /// The parent chain this chain flushes its values to, if it is connected to one.
+    async fn parent(&self) -> Option<ChainId> {
+        *self.state.parent.get()
+    }
+
+    /// Whether this chain is the root of the aggregation tree.
+    ///
+    /// A chain with a role is the root if it has the root role. Chains that send their flushes to
+    /// parent chains are otherwise the root if they aren't connected to one, but chains that don't
+    /// need the root role to be reported as the root.
+    async fn is_root(&self) -> bool {
+        if let Some(role) = *self.state.role.get() {
+            return role == NodeRole::Root;
+        }
+
+        match self.runtime.application_parameters().aggregation_mode {
+            AggregationMode::Messages => self.state.parent.get().is_none(),
+            AggregationMode::Streams | AggregationMode::Sharded => false,
+        }
+    }
+
+    /// The role of this chain in the tree, if one was set.
+    async fn role(&self) -> Option<NodeRole> {
+        *self.state.role.get()
+    }
+
+    /// Whether this chain was set up, when the application was created on it or by an
+    /// `initialize` mutation.
+    async fn is_initialized(&self) -> bool {
+        *self.state.initialized.get()
+    }
+
+    /// When this chain flushes its values without a `flush` mutation.
+    async fn flush_policy(&self) -> FlushPolicy {
+        *self.state.flush_policy.get()
+    }
+
+    /// Whether this chain was decommissioned and refuses further submissions.
+    async fn is_retired(&self) -> bool {
+        *self.state.retired.get()
+    }
+
+    /// The current accounting epoch.
+    async fn epoch(&self) -> u64 {
+        *self.state.epoch.get()
+    }
+
+    /// The values aggregated since the last flush to the parent chain.
+    async fn pending_since_last_flush(&self) -> PendingValues {
+        PendingValues {
+            value: *self.state.value.get(),
+            contributions: *self.state.contributions.get(),
+        }
+    }
+
+    /// The last flush sent to the parent chain.
+    async fn last_flush(&self) -> Option<FlushRecord> {
+        self.state.last_flush.get().clone()
+    }
+
+    /// The child chains registered with this chain.
+    async fn children(&self) -> async_graphql::Result<Vec<ChildChain>> {
+        let mut children = Vec::new();
+
+        for (chain_id, record) in self.state.children.index_values().await? {
+            children.push(ChildChain {
+                chain_id,
+                stake: record.stake,
+                slashed: record.slashed,
+                unstaking_since: record.unstaking_since,
+                contributions: self
+                    .state
+                    .child_contributions
+                    .get(&chain_id)
+                    .await?
+                    .unwrap_or_default(),
+            });
+        }
+
+        Ok(children)
+    }
+
+    /// The child chains that moved to another parent chain, until they withdraw their stake.
+    async fn former_children(&self) -> async_graphql::Result<Vec<FormerChildChain>> {
+        Ok(self
+            .state
+            .former_children
+            .index_values()
+            .await?
+            .into_iter()
+            .map(|(chain_id, record)| FormerChildChain {
+                chain_id,
+                stake: record.stake,
+                slashed: record.slashed,
+                unstaking_since: record.unstaking_since,
+            })
+            .collect())
+    }
+
+    /// The chains that joined the root chain's tree, in the order they joined, with the parent
+    /// chains they were assigned.
+    async fn joined_chains(&self) -> async_graphql::Result<Vec<JoinedChain>> {
+        let mut chains = Vec::new();
+
+        for chain_id in self.state.joined_chains.read(..).await? {
+            chains.push(JoinedChain {
+                chain_id,
+                parent: self
+                    .state
+                    .assigned_parents
+                    .get(&chain_id)
+                    .await?
+                    .expect("Joined chains should have an assigned parent chain"),
+            });
+        }
+
+        Ok(chains)
+    }
+
+    /// The child chains asked to move to another parent chain that haven't left yet, with the
+    /// parent chain they are moving to.
+    async fn moving_children(&self) -> async_graphql::Result<Vec<MovingChildChain>> {
+        Ok(self
+            .state
+            .moving_children
+            .index_values()
+            .await?
+            .into_iter()
+            .map(|(chain_id, parent)| MovingChildChain { chain_id, parent })
+            .collect())
+    }
+
+    /// The flushes received from child chains since the start of the last load window in which
+    /// one was received.
+    async fn flush_load(&self) -> FlushLoad {
+        self.state.flush_load.get().clone()
+    }
+
+    /// Whether this chain asked its parent chain to return its stake, after which it stops flushing
+    /// to it.
+    async fn unstaking(&self) -> bool {
+        *self.state.unstaking.get()
+    }
+
+    /// The chains this chain fails over to, in order, if its parent chain stops acknowledging its
+    /// flushes.
+    async fn backup_parents(&self) -> Vec<ChainId> {
+        self.state.backup_parents.get().clone()
+    }
+
+    /// The flushes sent to a parent chain that it has not acknowledged yet.
+    async fn unacknowledged_flushes(&self) -> async_graphql::Result<Vec<SentFlush>> {
+        Ok(self
+            .state
+            .unacknowledged_flushes
+            .index_values()
+            .await?
+            .into_iter()
+            .map(|(sequence, flush)| SentFlush { sequence, flush })
+            .collect())
+    }
+
+    /// The edge chains opened by this chain for new devices.
+    async fn opened_chains(&self) -> async_graphql::Result<Vec<OpenedChain>> {
+        Ok(self
+            .state
+            .opened_chains
+            .index_values()
+            .await?
+            .into_iter()
+            .map(|(chain_id, owner)| OpenedChain { chain_id, owner })
+            .collect())
+    }
+
+    /// The child chains that were decommissioned.
+    async fn retired_children(&self) -> async_graphql::Result<Vec<RetiredChildChain>> {
+        Ok(self
+            .state
+            .retired_children
+            .index_values()
+            .await?
+            .into_iter()
+            .map(|(chain_id, retired_at)| RetiredChildChain {
+                chain_id,
+                retired_at,
+            })
+            .collect())
+    }
+
+    /// The child chains whose flushes streams this chain merges, with the index of the next event
+    /// to merge from each of them.
+    async fn stream_subscriptions(&self) -> async_graphql::Result<Vec<StreamSubscription>> {
+        Ok(self
+            .state
+            .stream_subscriptions
+            .index_values()
+            .await?
+            .into_iter()
+            .map(|(chain_id, next_index)| StreamSubscription {
+                chain_id,
+                next_index,
+            })
+            .collect())
+    }
+
+    /// The application that receives the aggregate of every epoch closed by the root chain.
+    async fn consumer(&self) -> Option<AggregateConsumer> {
+        *self.state.consumer.get()
+    }
+
+    /// The applications on this chain that are allowed to call this application.
+    async fn authorized_applications(&self) -> async_graphql::Result<Vec<AuthorizedApplication>> {
+        Ok(self
+            .state
+            .authorized_applications
+            .index_values()
+            .await?
+            .into_iter()
+            .map(|(application_id, permissions)| AuthorizedApplication {
+                application_id,
+                permissions,
+            })
+            .collect())
+    }
+
+    /// The devices registered on this chain.
+    async fn devices(&self) -> async_graphql::Result<Vec<RegisteredDevice>> {
+        Ok(self
+            .state
+            .devices
+            .index_values()
+            .await?
+            .into_iter()
+            .map(|(owner, device)| RegisteredDevice {
+                owner,
+                reward_account: device.reward_account,
+                contributions: device.contributions,
+            })
+            .collect())
+    }
+
+    /// The snapshots of the value taken from the `start` time (inclusive) until the `end` time
+    /// (exclusive), skipping the first `offset` snapshots and returning at most `limit` snapshots.
+    async fn history(
+        &self,
+        start: Option<Timestamp>,
+        end: Option<Timestamp>,
+        #[graphql(default)] offset: usize,
+        #[graphql(default = 100)] limit: usize,
+    ) -> async_graphql::Result<Vec<ValueSnapshot>> {
+        let first_index = match start {
+            Some(start) => self.history_index_at(start).await?,
+            None => 0,
+        };
+        let end_index = match end {
+            Some(end) => self.history_index_at(end).await?,
+            None => self.state.history.count(),
+        };
+        let page_start = first_index.saturating_add(offset).min(end_index);
+        let page_end = page_start.saturating_add(limit).min(end_index);
+
+        Ok(self.state.history.read(page_start..page_end).await?)
+    }
+
+    /// The rollups with the `resolution` whose time buckets start from the `start` time
+    /// (inclusive) until the `end` time (exclusive).
+    async fn rollups(
+        &self,
+        resolution: Resolution,
+        start: Option<Timestamp>,
+        end: Option<Timestamp>,
+    ) -> async_graphql::Result<Vec<Rollup>> {
+        let mut rollups = match self
+            .state
+            .closed_rollups
+            .try_load_entry(&resolution)
+            .await?
+        {
+            Some(closed_rollups) => closed_rollups.elements().await?,
+            None => Vec::new(),
+        };
+        rollups.extend(self.state.open_rollups.get(&resolution).await?);
+
+        Ok(rollups
+            .into_iter()
+            .filter(|rollup| start.is_none_or(|start| rollup.start >= start))
+            .filter(|rollup| end.is_none_or(|end| rollup.start < end))
+            .collect())
+    }
+
+    /// The out-of-range values that were quarantined, skipping the first `offset` values and
+    /// returning at most `limit` values.
+    async fn quarantine(
+        &self,
+        #[graphql(default)] offset: usize,
+        #[graphql(default = 100)] limit: usize,
+    ) -> async_graphql::Result<Vec<QuarantinedReading>> {
+        let end = offset.saturating_add(limit);
+
+        Ok(self.state.quarantine.read(offset..end).await?)
+    }
+
+    /// The epoch of the last reward distribution.
+    async fn reward_epoch(&self) -> u64 {
+        *self.state.reward_epoch.get()
+    }
+
+    /// The records of the reward distributions, skipping the first `offset` records and returning
+    /// at most `limit` records.
+    async fn reward_settlements(
+        &self,
+        #[graphql(default)] offset: usize,
+        #[graphql(default = 100)] limit: usize,
+    ) -> async_graphql::Result<Vec<RewardSettlement>> {
+        let end = offset.saturating_add(limit);
+
+        Ok(self.state.reward_settlements.read(offset..end).await?)
+    }
+
+    /// The final aggregate of a closed `epoch`, on the root chain.
+    async fn archived_epoch(&self, epoch: u64) -> async_graphql::Result<Option<ArchivedEpoch>> {
+        Ok(self.state.archived_epochs.get(&epoch).await?)
+    }
+
+    /// The root of the contribution tree of an `epoch`.
+    async fn contribution_root(&self, epoch: u64) -> async_graphql::Result<CryptoHash> {
+        Ok(self
+            .state
+            .contribution_trees
+            .get(&epoch)
+            .await?
+            .unwrap_or_default()
+            .root())
+    }
+
+    /// The proof that the flush number `sequence` received from the `child` chain during an
+    /// `epoch` is included in the epoch's contribution tree.
+    async fn contribution_proof(
+        &self,
+        epoch: u64,
+        child: ChainId,
+        sequence: usize,
+    ) -> async_graphql::Result<Option<ContributionProof>> {
+        let Some(leaves) = self
+            .state
+            .contribution_leaves
+            .try_load_entry(&epoch)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let leaves = leaves.read(..).await?;
+        let index = leaves
+            .iter()
+            .enumerate()
+            .filter(|(_, leaf)| leaf.child == child)
+            .nth(sequence)
+            .map(|(index, _)| index);
+
+        Ok(index.and_then(|index| ContributionProof::new(&leaves, index)))
+    }
+
+    /// The version of the tree configuration applied by this chain, which is zero until the root
+    /// chain broadcasts one.
+    async fn config_version(&self) -> u64 {
+        *self.state.config_version.get()
+    }
+
+    /// The tree configuration applied by this chain, which is set by the application parameters
+    /// until the root chain broadcasts one.
+    async fn tree_config(&self) -> TreeConfig {
+        match self.state.tree_config.get() {
+            Some(config) => config.clone(),
+            None => self.runtime.application_parameters().tree_config(),
+        }
+    }
+
+    /// The values of each metric submitted since the last flush, when sharding the aggregation.
+    async fn pending_metric_values(&self) -> async_graphql::Result<Vec<MetricValue>> {
+        Ok(self
+            .state
+            .pending_metric_values
+            .index_values()
+            .await?
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect())
+    }
+
+    /// The values of each metric reduced by this chain, if it is a reducer chain.
+    async fn reduced_metric_values(&self) -> async_graphql::Result<Vec<MetricValue>> {
+        Ok(self
+            .state
+            .reduced_metric_values
+            .index_values()
+            .await?
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect())
+    }
+
+    /// The reducer chain that the `metric` is assigned to, which can be queried for its value
+    /// directly.
+    async fn reducer_chain(&self, metric: String) -> Option<ChainId> {
+        self.runtime.application_parameters().reducer_chain(&metric)
+    }
+
+    /// The last report received from each reducer chain, on the chain that created the
+    /// application.
+    async fn shard_reports(&self) -> async_graphql::Result<Vec<ReportedShard>> {
+        Ok(self
+            .state
+            .shard_reports
+            .index_values()
+            .await?
+            .into_iter()
+            .map(|(reducer, report)| ReportedShard { reducer, report })
+            .collect())
+    }
+
+    /// The values of each metric in the last reports of all the reducer chains, on the chain that
+    /// created the application.
+    async fn combined_metric_values(&self) -> async_graphql::Result<Vec<MetricValue>> {
+        Ok(self.combine_shard_reports().await?)
+    }
+
+    /// The value of a `metric` in the last reports of the reducer chains, on the chain that
+    /// created the application.
+    async fn combined_metric_value(
+        &self,
+        metric: String,
+    ) -> async_graphql::Result<Option<MetricValue>> {
+        Ok(self
+            .combine_shard_reports()
+            .await?
+            .into_iter()
+            .find(|value| value.metric == metric))
+    }
+
+    /// The application parameters shared by all chains.
+    async fn config(&self) -> Config {
+        Config(self.runtime.application_parameters())
+    }
+}
+
+impl QueryRoot {
+    /// Returns the index of the first snapshot in the history taken at or after the `timestamp`.
+    ///
+    /// Snapshots are recorded with the timestamps of the blocks that merged the values, so they are
+    /// sorted and can be binary searched.
+    async fn history_index_at(&self, timestamp: Timestamp) -> Result<usize, ViewError> {
+        let mut low = 0;
+        let mut high = self.state.history.count();
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let snapshot = self
+                .state
+                .history
+                .get(middle)
+                .await?
+                .expect("Snapshots before the history count should exist");
+
+            if snapshot.timestamp < timestamp {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        Ok(low)
+    }
+
+    /// Combines the values of each metric in the last reports of all the reducer chains, sorted by
+    /// metric.
+    ///
+    /// Each metric is reported by a single reducer chain, unless the reducer chains were changed,
+    /// in which case the values reported by each of them are added.
+    async fn combine_shard_reports(&self) -> Result<Vec<MetricValue>, ViewError> {
+        let mut combined = BTreeMap::<String, MetricValue>::new();
+
+        for (_, report) in self.state.shard_reports.index_values().await? {
+            for value in report.values {
+                let entry = combined
+                    .entry(value.metric.clone())
+                    .or_insert_with(|| MetricValue {
+                        metric: value.metric.clone(),
+                        value: 0,
+                        contributions: 0,
+                    });
+
+                entry.value += value.value;
+                entry.contributions += value.contributions;
+            }
+        }
+
+        Ok(combined.into_values().collect())
+    }
+}
+
+/// The values aggregated by a chain that have not been flushed yet.
+#[derive(SimpleObject)]
+struct PendingValues {
+    value: u64,
+    contributions: u64,
+}
+
+/// A child chain registered with this chain.
+#[derive(SimpleObject)]
+struct ChildChain {
+    chain_id: ChainId,
+    /// The amount the child chain has staked and can still withdraw.
+    stake: Amount,
+    /// The total amount slashed from the child chain's stake.
+    slashed: Amount,
+    /// When the child chain requested to unstake, if it did.
+    unstaking_since: Option<Timestamp>,
+    /// The number of values contributed by the child chain since the last reward distribution.
+    contributions: u64,
+}
+
+/// A child chain that moved to another parent chain and can still withdraw its stake.
+#[derive(SimpleObject)]
+struct FormerChildChain {
+    chain_id: ChainId,
+    /// The amount the child chain has staked and can still withdraw.
+    stake: Amount,
+    /// The total amount slashed from the child chain's stake.
+    slashed: Amount,
+    /// When the child chain moved to another parent chain, starting the unstaking cooldown.
+    unstaking_since: Option<Timestamp>,
+}
+
+/// A chain that joined the root chain's tree.
+#[derive(SimpleObject)]
+struct JoinedChain {
+    chain_id: ChainId,
+    /// The parent chain the root chain assigned to the chain.
+    parent: ChainId,
+}
+
+/// A child chain that was asked to move to another parent chain.
+#[derive(SimpleObject)]
+struct MovingChildChain {
+    chain_id: ChainId,
+    /// The parent chain the child chain is moving to.
+    parent: ChainId,
+}
+
+/// A flush sent by a chain with backup parent chains.
+#[derive(SimpleObject)]
+struct SentFlush {
+    /// The number of the flush among the ones sent by the chain.
+    sequence: u64,
+    #[graphql(flatten)]
+    flush: UnacknowledgedFlush,
+}
+
+/// The last report of a reducer chain.
+#[derive(SimpleObject)]
+struct ReportedShard {
+    reducer: ChainId,
+    #[graphql(flatten)]
+    report: ShardReport,
+}
+
+/// An edge chain opened for a new device.
+#[derive(SimpleObject)]
+struct OpenedChain {
+    chain_id: ChainId,
+    /// The owner of the device, who owns the chain.
+    owner: AccountOwner,
+}
+
+/// A child chain that was decommissioned.
+#[derive(SimpleObject)]
+struct RetiredChildChain {
+    chain_id: ChainId,
+    retired_at: Timestamp,
+}
+
+/// A child chain whose flushes stream is merged by this chain.
+#[derive(SimpleObject)]
+struct StreamSubscription {
+    chain_id: ChainId,
+    /// The index of the next event to merge from the child chain's flushes stream.
+    next_index: u32,
+}
+
+/// An application on this chain that is allowed to call this application.
+#[derive(SimpleObject)]
+struct AuthorizedApplication {
+    application_id: ApplicationId,
+    permissions: ApplicationPermissions,
+}
+
+/// A device registered to receive rewards for the values it submits.
+#[derive(SimpleObject)]
+struct RegisteredDevice {
+    owner: AccountOwner,
+    reward_account: Account,
+    /// The number of values submitted by the device since the last reward distribution.
+    contributions: u64,
+}
+
+/// The application parameters.
+struct Config(DepinDemoParameters);
+
+#[async_graphql::Object]
+impl Config {
+    /// The allowed range of values for each metric.
+    async fn value_ranges(&self) -> Vec<MetricRange> {
+        self.0.tree_config().value_ranges
+    }
+
+    /// What to do with values submitted outside of their metric's allowed range.
+    async fn out_of_range_policy(&self) -> OutOfRangePolicy {
+        self.0.out_of_range_policy
+    }
+
+    /// The amount child chains must stake with their parent chain.
+    async fn registration_stake(&self) -> Amount {
+        self.0.registration_stake
+    }
+
+    /// How long child chains must wait after unstaking before withdrawing their stake.
+    async fn unstake_cooldown(&self) -> TimeDelta {
+        self.0.unstake_cooldown
+    }
+
+    /// The minimum time between two snapshots in the value history.
+    async fn history_interval(&self) -> TimeDelta {
+        self.0.history_interval
+    }
+
+    /// How child chains deliver their flushed values.
+    async fn aggregation_mode(&self) -> AggregationMode {
+        self.0.aggregation_mode
+    }
+
+    /// The maximum number of chains assigned to each parent chain when they join the tree.
+    async fn fan_out(&self) -> u32 {
+        self.0.fan_out
+    }
+
+    /// The balance of each aggregator chain the root chain opens for joining chains.
+    async fn aggregator_balance(&self) -> Amount {
+        self.0.aggregator_balance
+    }
+
+    /// The maximum number of child chains an aggregator chain should have.
+    async fn fan_in_budget(&self) -> u32 {
+        self.0.fan_in_budget
+    }
+
+    /// The maximum number of flushes an aggregator chain should receive during each load window.
+    async fn flush_budget(&self) -> u64 {
+        self.0.flush_budget
+    }
+
+    /// The length of the windows during which the flushes received from child chains are counted.
+    async fn load_window(&self) -> TimeDelta {
+        self.0.load_window
+    }
+
+    /// The number of unacknowledged flushes after which a chain fails over to a backup parent
+    /// chain.
+    async fn max_unacknowledged_flushes(&self) -> u32 {
+        self.0.max_unacknowledged_flushes
+    }
+
+    /// The chains that reduce the values of the metrics assigned to them when sharding the
+    /// aggregation.
+    async fn reducer_chains(&self) -> Vec<ChainId> {
+        self.0.reducer_chains.clone()
+    }
+
+    /// The values that trigger an event when a chain's aggregated value rises to or above them.
+    async fn value_thresholds(&self) -> Vec<u64> {
+        self.0.value_thresholds.iter().copied().collect()
+    }
+
+    /// How long the rollups of each resolution are kept after their time bucket ends.
+    async fn rollup_retention(&self) -> Vec<RollupRetention> {
+        self.0
+            .rollup_retention
+            .iter()
+            .map(|(&resolution, &retention)| RollupRetention {
+                resolution,
+                retention,
+            })
+            .collect()
     }
 }
 
+/// How long the rollups of a resolution are kept after their time bucket ends.
+#[derive(SimpleObject)]
+struct RollupRetention {
+    resolution: Resolution,
+    retention: TimeDelta,
+}
+
 struct MutationRoot {
+    state: Arc<DepinDemoState>,
     runtime: Arc<ServiceRuntime<DepinDemoService>>,
 }
 
@@ -70,19 +814,426 @@ struct MutationRoot {
 impl MutationRoot {
     /// Creates an operation to connect this chain to a parent chain.
     async fn connect_to_parent(&self, parent: ChainId) -> bool {
-        self.runtime.schedule_operation(&Operation::ConnectToParent { parent });
+        self.runtime
+            .schedule_operation(&Operation::ConnectToParent { parent });
         true
     }
 
-    /// Creates an operation to submit a value.
-    async fn submit(&self, value: String) -> async_graphql::Result<bool> {
-        self.runtime.schedule_operation(&Operation::Submit { value: value.parse()? });
+    /// Creates an operation to submit a `reading`.
+    ///
+    /// The reading is checked before the operation is created, so that invalid readings are
+    /// refused with an error code instead of causing the block to be rejected.
+    async fn submit(&self, reading: ReadingInput) -> async_graphql::Result<bool> {
+        let value = self.check_reading(&reading).await?;
+
+        self.runtime.schedule_operation(&Operation::Submit {
+            metric: reading.metric,
+            value,
+        });
         Ok(true)
-   }
+    }
+
+    /// Creates a single operation to submit a batch of `readings`, which are either all accepted
+    /// or all rejected.
+    ///
+    /// Errors for invalid readings also report the `index` of the reading in the batch.
+    async fn submit_batch(
+        &self,
+        readings: Vec<TimestampedReadingInput>,
+    ) -> async_graphql::Result<bool> {
+        let mut checked_readings = Vec::with_capacity(readings.len());
+
+        for (index, timestamped) in readings.into_iter().enumerate() {
+            let value = self
+                .check_reading(&timestamped.reading)
+                .await
+                .map_err(|error| {
+                    error.extend_with(|_, extensions| extensions.set("index", index))
+                })?;
+
+            checked_readings.push(Reading {
+                metric: timestamped.reading.metric,
+                value,
+                timestamp: timestamped.timestamp,
+            });
+        }
+
+        self.runtime.schedule_operation(&Operation::SubmitBatch {
+            readings: checked_readings,
+        });
+        Ok(true)
+    }
 
     /// Creates an operation to flush the accumulated values to the parent chain.
     async fn flush(&self) -> bool {
         self.runtime.schedule_operation(&Operation::Flush);
         true
     }
+
+    /// Creates an operation to register a device, so that it is rewarded for the values it
+    /// submits.
+    async fn register_device(&self, owner: AccountOwner, reward_account: Account) -> bool {
+        self.runtime.schedule_operation(&Operation::RegisterDevice {
+            owner,
+            reward_account,
+        });
+        true
+    }
+
+    /// Creates an operation to distribute rewards from the root chain's reward pool.
+    async fn distribute_rewards(&self, amount: Amount) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::DistributeRewards { amount });
+        true
+    }
+
+    /// Creates an operation to start the cooldown before the stake can be withdrawn from the
+    /// parent chain.
+    async fn unstake(&self) -> bool {
+        self.runtime.schedule_operation(&Operation::Unstake);
+        true
+    }
+
+    /// Creates an operation to withdraw the stake from the parent chain after the cooldown.
+    async fn withdraw_stake(&self) -> bool {
+        self.runtime.schedule_operation(&Operation::WithdrawStake);
+        true
+    }
+
+    /// Creates an operation to flush the pending values to the current parent chain and move this
+    /// chain to a new `parent` chain.
+    async fn reparent(&self, parent: ChainId) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::Reparent { parent });
+        true
+    }
+
+    /// Creates an operation to withdraw the stake from a former `parent` chain after the cooldown.
+    async fn withdraw_stake_from(&self, parent: ChainId) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::WithdrawStakeFrom { parent });
+        true
+    }
+
+    /// Creates an operation to ask the `root` chain to assign a parent chain to this chain, which
+    /// then connects to it automatically.
+    ///
+    /// Only chains that join as an `aggregator` are assigned child chains of their own.
+    async fn join(&self, root: ChainId, aggregator: bool) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::Join { root, aggregator });
+        true
+    }
+
+    /// Creates an operation to move the child chains exceeding this chain's fan-in budget to the
+    /// `target` aggregator chain, or to a newly opened one funded with the `balance` if there is no
+    /// `target`.
+    async fn rebalance(
+        &self,
+        target: Option<ChainId>,
+        #[graphql(default)] balance: Amount,
+    ) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::Rebalance { target, balance });
+        true
+    }
+
+    /// Creates an operation to open an edge chain owned by a new device's `owner`, funded with the
+    /// `balance` and connected to this chain as its parent chain.
+    async fn open_edge_chain(&self, owner: AccountOwner, balance: Amount) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::OpenEdgeChain { owner, balance });
+        true
+    }
+
+    /// Creates an operation to broadcast a new version of the tree configuration from the root
+    /// chain to all the chains of its tree.
+    async fn update_config(&self, config: TreeConfig) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::UpdateConfig { config });
+        true
+    }
+
+    /// Creates an operation to set up this chain the first time the application is used on it,
+    /// connecting it to its parent chain, giving it its role and setting its flush policy.
+    async fn initialize(&self, setup: ChainSetup) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::Initialize { setup });
+        true
+    }
+
+    /// Creates an operation to set the `role` of this chain in the tree.
+    async fn set_role(&self, role: NodeRole) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::SetRole { role });
+        true
+    }
+
+    /// Creates an operation to set the chains this chain fails over to, in order, if its parent
+    /// chain stops acknowledging its flushes.
+    async fn set_backup_parents(&self, parents: Vec<ChainId>) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::SetBackupParents { parents });
+        true
+    }
+
+    /// Creates an operation to flush the pending values to the parent chain and disconnect from
+    /// it.
+    async fn disconnect(&self) -> bool {
+        self.runtime.schedule_operation(&Operation::Disconnect);
+        true
+    }
+
+    /// Creates an operation to flush the pending values and decommission this chain, which then
+    /// refuses further submissions.
+    async fn retire(&self) -> bool {
+        self.runtime.schedule_operation(&Operation::Retire);
+        true
+    }
+
+    /// Creates an operation to slash part of a misbehaving child chain's stake.
+    async fn slash(&self, child: ChainId, amount: Amount) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::Slash { child, amount });
+        true
+    }
+
+    /// Creates an operation to close the current epoch on the root chain and start a new one.
+    async fn close_epoch(&self) -> bool {
+        self.runtime.schedule_operation(&Operation::CloseEpoch);
+        true
+    }
+
+    /// Creates an operation to allow another application on this chain to call this application
+    /// with the `permissions`.
+    async fn authorize_application(
+        &self,
+        application_id: ApplicationId,
+        permissions: ApplicationPermissions,
+    ) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::AuthorizeApplication {
+                application_id,
+                permissions,
+            });
+        true
+    }
+
+    /// Creates an operation to stop another application on this chain from calling this
+    /// application.
+    async fn revoke_application(&self, application_id: ApplicationId) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::RevokeApplication { application_id });
+        true
+    }
+
+    /// Creates an operation to configure the application that receives the aggregate of every
+    /// epoch closed by the root chain, or to stop delivering them if there is no `consumer`.
+    async fn set_consumer(&self, consumer: Option<AggregateConsumer>) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::SetConsumer { consumer });
+        true
+    }
+
+    /// Creates an operation to start merging the flushes a child chain publishes to its event
+    /// stream.
+    async fn subscribe_to_child(&self, child: ChainId) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::SubscribeToChild { child });
+        true
+    }
+
+    /// Creates an operation to stop merging the flushes a child chain publishes to its event
+    /// stream.
+    async fn unsubscribe_from_child(&self, child: ChainId) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::UnsubscribeFromChild { child });
+        true
+    }
+
+    /// Creates an operation to merge the flushes that subscribed child chains published to their
+    /// event streams, up to the `nextIndex` of each update.
+    async fn process_streams(&self, updates: Vec<StreamUpdate>) -> bool {
+        self.runtime
+            .schedule_operation(&Operation::ProcessStreams { updates });
+        true
+    }
+
+    /// Creates an operation to report the values of the metrics reduced by this reducer chain to
+    /// the chain that created the application.
+    async fn report_shard(&self) -> bool {
+        self.runtime.schedule_operation(&Operation::ReportShard);
+        true
+    }
+}
+
+impl MutationRoot {
+    /// Checks if a `reading` would be accepted by the contract, returning its value.
+    ///
+    /// Out-of-range values are only refused if the application rejects them instead of
+    /// quarantining them. Metrics are only checked if the application configures value ranges.
+    async fn check_reading(&self, reading: &ReadingInput) -> async_graphql::Result<u64> {
+        let config = match self.state.tree_config.get() {
+            Some(config) => config.clone(),
+            None => self.runtime.application_parameters().tree_config(),
+        };
+        let value = u64::try_from(reading.value.0).map_err(|_| {
+            ReadingError::OutOfRange {
+                metric: reading.metric.clone(),
+                value: reading.value.0,
+                min: 0,
+                max: u64::MAX,
+            }
+            .extend()
+        })?;
+
+        if let Some(metric) = &reading.metric {
+            match config.value_range(metric) {
+                Some(range)
+                    if !range.contains(value)
+                        && config.out_of_range_policy == OutOfRangePolicy::Reject =>
+                {
+                    return Err(ReadingError::OutOfRange {
+                        metric: Some(metric.clone()),
+                        value: reading.value.0,
+                        min: range.min,
+                        max: range.max,
+                    }
+                    .extend());
+                }
+                None if !config.value_ranges.is_empty() => {
+                    return Err(ReadingError::UnknownMetric(metric.clone()).extend());
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(device) = reading.device_id {
+            if !self.state.devices.contains_key(&device).await? {
+                return Err(ReadingError::UnauthorizedDevice(device).extend());
+            }
+        }
+
+        Ok(value)
+    }
+}
+
+/// A value measured by a device.
+#[derive(InputObject)]
+struct ReadingInput {
+    /// The metric the value measures, if any.
+    metric: Option<String>,
+    value: UInt128,
+    /// The registered device expected to sign the block with the reading, if any.
+    ///
+    /// It is only checked by the service, and is not part of the operation.
+    device_id: Option<AccountOwner>,
+}
+
+/// A value measured by a device at a specific time, submitted as part of a batch.
+#[derive(InputObject)]
+struct TimestampedReadingInput {
+    #[graphql(flatten)]
+    reading: ReadingInput,
+    /// When the value was measured.
+    timestamp: Timestamp,
+}
+
+/// An unsigned integer of up to 128 bits.
+///
+/// It can be written as a number, or as a decimal string for values that GraphQL clients can't
+/// represent as numbers without losing precision.
+struct UInt128(u128);
+
+#[Scalar]
+impl ScalarType for UInt128 {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::Number(number) => number
+                .as_u64()
+                .map(|value| UInt128(value.into()))
+                .ok_or_else(|| {
+                    InputValueError::custom(format!("Expected an unsigned integer, found {number}"))
+                }),
+            Value::String(string) => string.parse().map(UInt128).map_err(|_| {
+                InputValueError::custom(format!(
+                    "Expected an unsigned integer of up to 128 bits, found {string:?}"
+                ))
+            }),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.0.to_string())
+    }
+}
+
+/// The reasons why the service refuses to create an operation for a reading.
+///
+/// Only out-of-range values are also rejected by the contract. The other checks are advisory, and
+/// only protect clients that submit readings through this service.
+#[derive(Debug)]
+enum ReadingError {
+    /// The value is outside of the allowed range for its metric, or doesn't fit in 64 bits.
+    OutOfRange {
+        metric: Option<String>,
+        value: u128,
+        min: u64,
+        max: u64,
+    },
+    /// The metric has no configured value range.
+    UnknownMetric(String),
+    /// The device is not registered on this chain.
+    UnauthorizedDevice(AccountOwner),
+}
+
+impl ReadingError {
+    /// The machine-readable code reported in the error's extensions.
+    fn code(&self) -> &'static str {
+        match self {
+            ReadingError::OutOfRange { .. } => "OUT_OF_RANGE",
+            ReadingError::UnknownMetric(_) => "UNKNOWN_METRIC",
+            ReadingError::UnauthorizedDevice(_) => "UNAUTHORIZED_DEVICE",
+        }
+    }
+}
+
+impl fmt::Display for ReadingError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadingError::OutOfRange {
+                metric: Some(metric),
+                value,
+                min,
+                max,
+            } => write!(
+                formatter,
+                "Value {value} is outside of the allowed range for metric {metric:?} \
+                ({min}..={max})"
+            ),
+            ReadingError::OutOfRange {
+                metric: None,
+                value,
+                min,
+                max,
+            } => write!(
+                formatter,
+                "Value {value} is outside of the allowed range ({min}..={max})"
+            ),
+            ReadingError::UnknownMetric(metric) => {
+                write!(formatter, "Metric {metric:?} has no configured value range")
+            }
+            ReadingError::UnauthorizedDevice(device) => {
+                write!(formatter, "Device {device} is not registered on this chain")
+            }
+        }
+    }
+}
+
+impl ErrorExtensions for ReadingError {
+    fn extend(&self) -> async_graphql::Error {
+        async_graphql::Error::new(self.to_string())
+            .extend_with(|_, extensions| extensions.set("code", self.code()))
+    }
 }
@@ -1,19 +1,294 @@
 // Copyright (c) Zefchain Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::collections::BTreeSet;
 #[cfg(test)]
 use std::sync::Arc;
 
+use depin_demo::{
+    merkle::{ContributionLeaf, ContributionTree},
+    AggregateConsumer, ApplicationPermissions, FlushPolicy, MetricValue, NodeRole, ReplicatedFlush,
+    Resolution, TreeConfig, ValueRange,
+};
 use linera_sdk::{
-    linera_base_types::ChainId,
-    views::{linera_views, RegisterView, RootView, ViewStorageContext},
+    linera_base_types::{
+        Account, AccountOwner, Amount, ApplicationId, BlockHeight, ChainId, CryptoHash, Timestamp,
+    },
+    views::{
+        linera_views, CollectionView, LogView, MapView, QueueView, RegisterView, RootView, SetView,
+        ViewStorageContext,
+    },
 };
+use serde::{Deserialize, Serialize};
 
-#[derive(RootView, async_graphql::SimpleObject)]
+#[derive(RootView)]
 #[view(context = "ViewStorageContext")]
 pub struct DepinDemoState {
+    /// The parent chain this chain flushes its values to, if it is connected to one.
     pub parent: RegisterView<Option<ChainId>>,
+    /// The value aggregated since the last flush, or on the root chain since the last epoch was
+    /// closed.
     pub value: RegisterView<u64>,
+    /// The out-of-range values that were set aside instead of being aggregated.
+    pub quarantine: LogView<QuarantinedReading>,
+    /// The number of values aggregated into the `value`.
+    pub contributions: RegisterView<u64>,
+    /// The contributions merged from each child chain since the last reward distribution.
+    pub child_contributions: MapView<ChainId, u64>,
+    /// The devices registered to be rewarded for the values they submit.
+    pub devices: MapView<AccountOwner, Device>,
+    /// The last reward epoch, counting the reward distributions started by the root chain.
+    pub reward_epoch: RegisterView<u64>,
+    /// How this chain split the rewards of each reward epoch.
+    pub reward_settlements: LogView<RewardSettlement>,
+    /// The child chains registered with this chain.
+    pub children: MapView<ChainId, ChildRecord>,
+    /// The snapshots of the value taken after merging flushes from child chains.
+    pub history: LogView<ValueSnapshot>,
+    /// The current accounting epoch, which advances each time the root chain closes one and
+    /// archives its aggregate.
+    ///
+    /// Flushes carry the epoch their values belong to. It is independent of the `reward_epoch`,
+    /// since closing an epoch doesn't distribute rewards, nor the other way around.
+    pub epoch: RegisterView<u64>,
+    /// The final aggregates of the closed epochs, on the root chain.
+    pub archived_epochs: MapView<u64, ArchivedEpoch>,
+    /// The rollups of the time buckets that are still receiving merged values.
+    pub open_rollups: MapView<Resolution, Rollup>,
+    /// The rollups of the time buckets that have ended and have not been pruned yet.
+    pub closed_rollups: CollectionView<Resolution, QueueView<Rollup>>,
+    /// The contribution tree of each epoch, committing to the flushes received from child chains.
+    pub contribution_trees: MapView<u64, ContributionTree>,
+    /// The leaves of the contribution tree of each epoch.
+    pub contribution_leaves: CollectionView<u64, LogView<ContributionLeaf>>,
+    /// The last flush sent to the parent chain or published to the flushes stream.
+    pub last_flush: RegisterView<Option<FlushRecord>>,
+    /// The last change of the value.
+    pub last_value_update: RegisterView<Option<ValueUpdate>>,
+    /// The index of the next event to merge from each subscribed child chain's flushes stream.
+    pub stream_subscriptions: MapView<ChainId, u32>,
+    /// The applications on this chain that are allowed to call this application.
+    pub authorized_applications: MapView<ApplicationId, ApplicationPermissions>,
+    /// The application that receives the aggregate of every epoch closed by the root chain.
+    pub consumer: RegisterView<Option<AggregateConsumer>>,
+    /// The child chains that moved to another parent chain, until they withdraw their stake.
+    pub former_children: MapView<ChainId, ChildRecord>,
+    /// The stake of the child chains and former child chains held in the application's account on
+    /// this chain, which can't be paid out as rewards.
+    pub held_stake: RegisterView<Amount>,
+    /// The stakes kept by the root chain for the joining chains it assigned to itself, until they
+    /// register, which can't be paid out as rewards either.
+    pub joining_stakes: MapView<ChainId, Amount>,
+    /// Whether this chain was decommissioned and refuses further submissions.
+    pub retired: RegisterView<bool>,
+    /// When each of the child chains that were decommissioned retired.
+    pub retired_children: MapView<ChainId, Timestamp>,
+    /// The chains that joined the root chain's tree, in the order they joined.
+    pub joined_chains: LogView<ChainId>,
+    /// The parent chain the root chain assigned to each chain that joined its tree.
+    pub assigned_parents: MapView<ChainId, ChainId>,
+    /// The chains that joined the root chain's tree as aggregator chains, in the order they
+    /// joined.
+    pub joined_aggregators: LogView<ChainId>,
+    /// How many joining chains the root chain assigned to each parent chain.
+    pub assigned_children: MapView<ChainId, u32>,
+    /// The index of the first parent chain that may have spare capacity for joining chains, with
+    /// the root chain first followed by the joined aggregator chains.
+    pub next_assigned_parent: RegisterView<u64>,
+    /// The root chain this chain asked to assign it a parent chain, until it replies.
+    pub pending_join: RegisterView<Option<ChainId>>,
+    /// The flushes received from child chains during the current load window.
+    pub flush_load: RegisterView<FlushLoad>,
+    /// The child chains asked to move to another parent chain, with the parent chain they are
+    /// moving to, until they leave.
+    pub moving_children: MapView<ChainId, ChainId>,
+    /// The edge chains opened by this chain for new devices, with the device owner of each one.
+    pub opened_chains: MapView<ChainId, AccountOwner>,
+    /// The version of the tree configuration applied by this chain, which is zero until the root
+    /// chain broadcasts one.
+    pub config_version: RegisterView<u64>,
+    /// The tree configuration applied by this chain, if the root chain broadcast one.
+    pub tree_config: RegisterView<Option<TreeConfig>>,
+    /// The role of this chain in the tree, if one was set.
+    pub role: RegisterView<Option<NodeRole>>,
+    /// Whether this chain was set up, by the creation of the application or by an `Initialize`
+    /// operation.
+    pub initialized: RegisterView<bool>,
+    /// When this chain flushes its values without a `Flush` operation.
+    pub flush_policy: RegisterView<FlushPolicy>,
+    /// The chains this chain fails over to, in order, if its parent chain stops acknowledging its
+    /// flushes.
+    pub backup_parents: RegisterView<Vec<ChainId>>,
+    /// Whether this chain asked its parent chain to return its stake, after which it stops flushing
+    /// to it.
+    pub unstaking: RegisterView<bool>,
+    /// The parent chains this chain left, whose messages sent before they learned it left are
+    /// ignored.
+    pub former_parents: SetView<ChainId>,
+    /// The registration stake set aside in the application's account on this chain, to register
+    /// with a backup parent chain when failing over.
+    pub reserved_stake: RegisterView<Amount>,
+    /// The sequence number of the next flush sent while this chain has backup parent chains.
+    pub next_flush_sequence: RegisterView<u64>,
+    /// The flushes sent to a parent chain that it has not acknowledged yet, by sequence number.
+    pub unacknowledged_flushes: MapView<u64, UnacknowledgedFlush>,
+    /// The replicated flushes merged by this chain, by sending chain.
+    pub merged_replicas: MapView<ChainId, MergedReplicas>,
+    /// The replicated flushes merged since the last flush, which are forwarded with it.
+    pub pending_replicas: RegisterView<Vec<ReplicatedFlush>>,
+    /// The values of each metric submitted since the last flush, when sharding the aggregation.
+    pub pending_metric_values: MapView<String, MetricValue>,
+    /// The values of each metric reduced by this chain, if it is a reducer chain.
+    pub reduced_metric_values: MapView<String, MetricValue>,
+    /// The last report received from each reducer chain, on the chain that created the
+    /// application.
+    pub shard_reports: MapView<ChainId, ShardReport>,
+}
+
+/// The values of all the metrics reduced by a reducer chain, as last reported by it.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject)]
+pub struct ShardReport {
+    pub values: Vec<MetricValue>,
+    pub reported_at: Timestamp,
+}
+
+/// The replicated flushes from one chain merged by one of its ancestor chains.
+///
+/// Chains only send the flushes their parent chain has not acknowledged again, so only the
+/// sequence numbers up to `max_unacknowledged_flushes` below the highest one are kept. Older
+/// replicated flushes are assumed to have been merged already.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MergedReplicas {
+    /// The highest sequence number merged.
+    pub highest: u64,
+    /// The sequence numbers merged that are still kept.
+    pub sequences: BTreeSet<u64>,
+}
+
+/// A flush sent to a parent chain that has not acknowledged it yet, kept to be sent again to a
+/// backup parent chain.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject)]
+pub struct UnacknowledgedFlush {
+    /// The parent chain the flush was last sent to.
+    pub parent: ChainId,
+    pub epoch: u64,
+    pub value: u64,
+    pub contributions: u64,
+    pub commitment: CryptoHash,
+    pub replicas: Vec<ReplicatedFlush>,
+}
+
+/// The number of flushes received from child chains since the start of a load window.
+#[derive(
+    Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject,
+)]
+pub struct FlushLoad {
+    pub window_start: Timestamp,
+    pub flushes: u64,
+}
+
+/// The values a chain sent to its parent chain in a flush.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject)]
+pub struct FlushRecord {
+    pub epoch: u64,
+    pub value: u64,
+    pub contributions: u64,
+    pub commitment: CryptoHash,
+    pub block_height: BlockHeight,
+    pub timestamp: Timestamp,
+}
+
+/// The value of a chain after it changed in a block.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject)]
+pub struct ValueUpdate {
+    pub value: u64,
+    pub block_height: BlockHeight,
+    pub timestamp: Timestamp,
+}
+
+/// A snapshot of a chain's value right after merging values from one of its child chains.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject)]
+pub struct ValueSnapshot {
+    pub timestamp: Timestamp,
+    pub block_height: BlockHeight,
+    pub value: u64,
+    /// The child chain whose values were merged.
+    pub child: ChainId,
+}
+
+/// The final aggregate of a closed accounting epoch.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject)]
+pub struct ArchivedEpoch {
+    pub value: u64,
+    pub contributions: u64,
+    pub closed_at: Timestamp,
+    /// How many flushes for the epoch were merged after it was closed.
+    pub late_flushes: u64,
+}
+
+/// The summary of the values merged during a time bucket.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject)]
+pub struct Rollup {
+    /// The start of the time bucket.
+    pub start: Timestamp,
+    /// How many times values from child chains were merged.
+    pub merges: u64,
+    /// The sum of the merged values.
+    pub merged_value: u64,
+    /// The lowest value of the chain right after a merge.
+    pub min_value: u64,
+    /// The highest value of the chain right after a merge.
+    pub max_value: u64,
+    /// The value of the chain right after the last merge.
+    pub last_value: u64,
+}
+
+/// A submitted value that was outside of its metric's allowed range.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject)]
+pub struct QuarantinedReading {
+    pub metric: String,
+    pub value: u64,
+    pub allowed_range: ValueRange,
+    pub block_height: BlockHeight,
+    pub timestamp: Timestamp,
+}
+
+/// A device registered to receive rewards for the values it submits.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject)]
+pub struct Device {
+    pub reward_account: Account,
+    /// The number of values submitted by the device since the last reward distribution.
+    pub contributions: u64,
+}
+
+/// The record of how a chain distributed its rewards for an epoch.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject)]
+pub struct RewardSettlement {
+    pub epoch: u64,
+    pub amount: Amount,
+    pub contributions: u64,
+    pub payouts: Vec<RewardPayout>,
+}
+
+/// A share of an epoch's rewards paid to a child chain or to a device.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject)]
+pub struct RewardPayout {
+    pub recipient: Account,
+    pub contributions: u64,
+    pub amount: Amount,
+}
+
+/// The registration of a child chain with its parent chain.
+#[derive(
+    Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize, async_graphql::SimpleObject,
+)]
+pub struct ChildRecord {
+    /// The amount the child chain has staked and can still withdraw.
+    pub stake: Amount,
+    /// The total amount slashed from the child chain's stake.
+    pub slashed: Amount,
+    /// When the child chain requested to unstake, if it did.
+    pub unstaking_since: Option<Timestamp>,
 }
 
 #[cfg(test)]
@@ -1,19 +1,37 @@
 // Copyright (c) Zefchain Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::mem;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    mem,
+    sync::{Arc, Mutex},
+};
 
 use linera_sdk::{
-    linera_base_types::{ChainId, Destination, Resources, SendMessageRequest},
+    bcs,
+    linera_base_types::{
+        Account, AccountOwner, Amount, ApplicationId, BlockHeight, ChainId, ChainOwnership,
+        CryptoHash, Destination, MessageId, Resources, SendMessageRequest, TimeDelta, Timestamp,
+    },
     util::BlockingWait,
     views::View,
     Contract, ContractRuntime,
 };
 use test_strategy::proptest;
 
-use depin_demo::Operation;
+use depin_demo::{
+    merkle::{ContributionLeaf, ContributionTree},
+    AggregateConsumer, AggregationEvent, AggregationMode, ApplicationPermissions, ChainSetup,
+    DepinDemoAbi, DepinDemoParameters, FinalizedAggregate, FlushPolicy, LocalAggregate, Message,
+    MetricRange, MetricValue, NodeRole, Operation, OperationResponse, OutOfRangePolicy, Reading,
+    ReplicatedFlush, Resolution, StreamUpdate, TreeConfig, ValueRange, FLUSHES_STREAM,
+};
 
-use super::{DepinDemoContract, DepinDemoState};
+use super::{
+    proportional_share, ArchivedEpoch, ChildRecord, DepinDemoContract, DepinDemoState, Device,
+    FlushLoad, FlushRecord, QuarantinedReading, RewardPayout, Rollup, ShardReport,
+    UnacknowledgedFlush, ValueSnapshot, ValueUpdate,
+};
 
 /// Test initial state of the application.
 #[test]
@@ -31,6 +49,7 @@ fn submit_operation(values_to_submit: Vec<u32>) {
 
     for &value in &values_to_submit {
         app.execute_operation(Operation::Submit {
+            metric: None,
             value: value.into(),
         })
         .blocking_wait();
@@ -48,11 +67,189 @@ fn submit_operation(values_to_submit: Vec<u32>) {
 fn submit_operation_overflow() {
     let mut app = create_and_instantiate_app();
 
-    app.execute_operation(Operation::Submit { value: u64::MAX })
-        .blocking_wait();
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: u64::MAX,
+    })
+    .blocking_wait();
+
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 1,
+    })
+    .blocking_wait();
+}
+
+/// Test if values inside their metric's configured range are accepted.
+#[proptest]
+fn submit_operation_in_range(#[strategy(10..=20_u64)] value: u64) {
+    let mut app = create_and_instantiate_app_with_parameters(temperature_range_parameters(
+        OutOfRangePolicy::Reject,
+    ));
+
+    app.execute_operation(Operation::Submit {
+        metric: Some("temperature".to_owned()),
+        value,
+    })
+    .blocking_wait();
+
+    assert_eq!(*app.state.value.get(), value);
+    assert_eq!(app.state.quarantine.count(), 0);
+}
+
+/// Test if values for metrics without a configured range are always accepted.
+#[proptest]
+fn submit_operation_without_range(value: u64) {
+    let mut app = create_and_instantiate_app_with_parameters(temperature_range_parameters(
+        OutOfRangePolicy::Reject,
+    ));
+
+    app.execute_operation(Operation::Submit {
+        metric: Some("humidity".to_owned()),
+        value,
+    })
+    .blocking_wait();
+
+    assert_eq!(*app.state.value.get(), value);
+}
+
+/// Test if out-of-range values cause the block to be rejected.
+#[test]
+#[should_panic(expected = "Value 21 is outside of the allowed range for metric \"temperature\"")]
+fn submit_operation_out_of_range_is_rejected() {
+    let mut app = create_and_instantiate_app_with_parameters(temperature_range_parameters(
+        OutOfRangePolicy::Reject,
+    ));
+
+    app.execute_operation(Operation::Submit {
+        metric: Some("temperature".to_owned()),
+        value: 21,
+    })
+    .blocking_wait();
+}
+
+/// Test if out-of-range values are quarantined instead of aggregated when configured to.
+#[test]
+fn submit_operation_out_of_range_is_quarantined() {
+    let mut app = create_and_instantiate_app_with_parameters(temperature_range_parameters(
+        OutOfRangePolicy::Quarantine,
+    ));
+    app.runtime
+        .set_block_height(BlockHeight(7))
+        .set_system_time(Timestamp::from(1_000));
+
+    app.execute_operation(Operation::Submit {
+        metric: Some("temperature".to_owned()),
+        value: 15,
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::Submit {
+        metric: Some("temperature".to_owned()),
+        value: 9,
+    })
+    .blocking_wait();
+
+    assert_eq!(*app.state.value.get(), 15);
+    assert_eq!(
+        app.state.quarantine.read(..).blocking_wait().unwrap(),
+        vec![QuarantinedReading {
+            metric: "temperature".to_owned(),
+            value: 9,
+            allowed_range: ValueRange { min: 10, max: 20 },
+            block_height: BlockHeight(7),
+            timestamp: Timestamp::from(1_000),
+        }]
+    );
+}
+
+/// Test if all the readings of a batch are aggregated in a single operation.
+#[proptest]
+fn submit_batch_operation(values_to_submit: Vec<u32>) {
+    let mut app = create_and_instantiate_app_with_parameters(temperature_range_parameters(
+        OutOfRangePolicy::Reject,
+    ));
+    app.runtime.set_system_time(Timestamp::from(1_000));
+
+    let readings = values_to_submit
+        .iter()
+        .enumerate()
+        .map(|(index, &value)| Reading {
+            metric: None,
+            value: value.into(),
+            timestamp: Timestamp::from(index as u64),
+        })
+        .collect();
 
-    app.execute_operation(Operation::Submit { value: 1 })
+    app.execute_operation(Operation::SubmitBatch { readings })
         .blocking_wait();
+
+    assert_eq!(
+        *app.state.value.get(),
+        values_to_submit.iter().copied().map(u64::from).sum::<u64>()
+    );
+    assert_eq!(
+        *app.state.contributions.get(),
+        values_to_submit.len() as u64
+    );
+}
+
+/// Test if a batch with an out-of-range reading is rejected as a whole.
+#[test]
+#[should_panic(expected = "Value 21 is outside of the allowed range for metric \"temperature\"")]
+fn submit_batch_operation_out_of_range_is_rejected() {
+    let mut app = create_and_instantiate_app_with_parameters(temperature_range_parameters(
+        OutOfRangePolicy::Reject,
+    ));
+
+    app.execute_operation(Operation::SubmitBatch {
+        readings: vec![temperature_reading(15, 0), temperature_reading(21, 0)],
+    })
+    .blocking_wait();
+}
+
+/// Test if a batch with a reading from the future is rejected as a whole.
+#[test]
+#[should_panic(expected = "Reading 0 of the batch has a timestamp in the future")]
+fn submit_batch_operation_from_the_future_is_rejected() {
+    let mut app = create_and_instantiate_app();
+    app.runtime.set_system_time(Timestamp::from(1_000));
+
+    app.execute_operation(Operation::SubmitBatch {
+        readings: vec![temperature_reading(15, 1_001)],
+    })
+    .blocking_wait();
+}
+
+/// Test if out-of-range readings of a batch are quarantined with the time they were measured.
+#[test]
+fn submit_batch_operation_out_of_range_is_quarantined() {
+    let mut app = create_and_instantiate_app_with_parameters(temperature_range_parameters(
+        OutOfRangePolicy::Quarantine,
+    ));
+    app.runtime
+        .set_block_height(BlockHeight(7))
+        .set_system_time(Timestamp::from(1_000));
+
+    app.execute_operation(Operation::SubmitBatch {
+        readings: vec![
+            temperature_reading(15, 100),
+            temperature_reading(9, 200),
+            temperature_reading(20, 300),
+        ],
+    })
+    .blocking_wait();
+
+    assert_eq!(*app.state.value.get(), 35);
+    assert_eq!(
+        app.state.quarantine.read(..).blocking_wait().unwrap(),
+        vec![QuarantinedReading {
+            metric: "temperature".to_owned(),
+            value: 9,
+            allowed_range: ValueRange { min: 10, max: 20 },
+            block_height: BlockHeight(7),
+            timestamp: Timestamp::from(200),
+        }]
+    );
 }
 
 /// Test connecting the application to a parent chain.
@@ -64,122 +261,4429 @@ fn connect_to_parent(parent: ChainId) {
         .blocking_wait();
 
     assert_eq!(*app.state.parent.get(), Some(parent));
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![SendMessageRequest {
+            destination: Destination::Recipient(parent),
+            authenticated: false,
+            is_tracked: false,
+            grant: Resources::default(),
+            message: Message::Register {
+                stake: Amount::ZERO
+            },
+        }]
+    );
 }
 
-/// Test if flushing without a configured parent causes the block to be rejected.
+/// Test if connecting to a parent chain transfers the registration stake to it.
 #[test]
-#[should_panic(expected = "Can't flush if the chain is not connected to a parent chain")]
-fn flush_without_parent() {
+fn connect_to_parent_with_stake() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+    let application_account = fund_application_account(&mut app, Amount::ZERO);
+    let parent = ChainId::root(1);
+
+    app.runtime.set_chain_balance(Amount::from_tokens(15));
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+
+    assert_eq!(
+        *app.runtime.outgoing_transfers(),
+        HashMap::from([(
+            Account::new(parent, application_account),
+            Amount::from_tokens(10)
+        )])
+    );
+    assert_eq!(app.runtime.chain_balance(), Amount::from_tokens(5));
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![SendMessageRequest {
+            destination: Destination::Recipient(parent),
+            authenticated: false,
+            is_tracked: false,
+            grant: Resources::default(),
+            message: Message::Register {
+                stake: Amount::from_tokens(10)
+            },
+        }]
+    );
+}
+
+/// Test if a parent chain registers its child chains with their stakes.
+#[test]
+fn child_chains_are_registered() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+    let child = ChainId::root(1);
+
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::from_tokens(10),
+        },
+    );
+    receive_message(
+        &mut app,
+        child,
+        Message::Flush {
+            epoch: 0,
+            value: 5,
+            contributions: 1,
+            commitment: empty_commitment(),
+            replicas: Vec::new(),
+        },
+    );
+
+    assert_eq!(*app.state.value.get(), 5);
+    assert_eq!(
+        app.state.children.get(&child).blocking_wait().unwrap(),
+        Some(ChildRecord {
+            stake: Amount::from_tokens(10),
+            slashed: Amount::ZERO,
+            unstaking_since: None,
+        })
+    );
+}
+
+/// Test if values flushed by child chains without the required stake cause the block to be
+/// rejected.
+#[test]
+#[should_panic(expected = "has not staked the required amount")]
+fn flush_from_child_without_stake() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+    let child = ChainId::root(1);
+
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::from_tokens(9),
+        },
+    );
+    receive_message(
+        &mut app,
+        child,
+        Message::Flush {
+            epoch: 0,
+            value: 5,
+            contributions: 1,
+            commitment: empty_commitment(),
+            replicas: Vec::new(),
+        },
+    );
+}
+
+/// Test if connecting to a parent chain again is rejected, so that chains are reparented
+/// explicitly.
+#[test]
+#[should_panic(expected = "Chain is already connected to a parent chain")]
+fn connect_to_parent_twice() {
     let mut app = create_and_instantiate_app();
 
-    app.execute_operation(Operation::Flush).blocking_wait();
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(2),
+    })
+    .blocking_wait();
 }
 
-/// Test if flushing values sends messages to the parent chain.
-#[proptest]
-fn flush_sends_messages(parent: ChainId, values_to_submit: Vec<Option<u32>>) {
+/// Test if reparenting flushes the pending values to the old parent chain, deregisters from it
+/// and registers with the new parent chain.
+#[test]
+fn reparent() {
     let mut app = create_and_instantiate_app();
-    let mut accumulated = 0_u64;
+    let old_parent = ChainId::root(1);
+    let new_parent = ChainId::root(2);
 
-    app.execute_operation(Operation::ConnectToParent { parent })
+    app.execute_operation(Operation::ConnectToParent { parent: old_parent })
         .blocking_wait();
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 5,
+    })
+    .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
 
-    for maybe_value in values_to_submit.into_iter().chain(None) {
-        match maybe_value {
-            Some(value) => {
-                app.execute_operation(Operation::Submit {
-                    value: value.into(),
-                })
-                .blocking_wait();
-
-                accumulated += u64::from(value);
-            }
-            None => {
-                app.execute_operation(Operation::Flush).blocking_wait();
+    app.execute_operation(Operation::Reparent { parent: new_parent })
+        .blocking_wait();
 
-                assert_eq!(
-                    mem::take(&mut *app.runtime.created_send_message_requests()),
-                    vec![SendMessageRequest {
-                        destination: Destination::Recipient(parent),
-                        authenticated: false,
-                        is_tracked: false,
-                        grant: Resources::default(),
-                        message: mem::take(&mut accumulated),
-                    }]
-                );
-            }
-        }
-    }
+    assert_eq!(*app.state.parent.get(), Some(new_parent));
+    assert_eq!(*app.state.value.get(), 0);
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![
+            outgoing_message(
+                old_parent,
+                Message::Flush {
+                    epoch: 0,
+                    value: 5,
+                    contributions: 1,
+                    commitment: empty_commitment(),
+                    replicas: Vec::new(),
+                },
+            ),
+            outgoing_message(old_parent, Message::Deregister),
+            outgoing_message(
+                new_parent,
+                Message::Register {
+                    stake: Amount::ZERO,
+                },
+            ),
+        ]
+    );
 }
 
-/// Test that value overflows are avoided by flushing.
-#[proptest]
-fn submit_operation_overflow_is_avoided_by_flushing(parent: ChainId) {
+/// Test if reparenting without pending values doesn't send an empty flush.
+#[test]
+fn reparent_without_pending_values() {
     let mut app = create_and_instantiate_app();
+    let old_parent = ChainId::root(1);
+    let new_parent = ChainId::root(2);
 
-    app.execute_operation(Operation::Submit { value: u64::MAX })
+    app.execute_operation(Operation::ConnectToParent { parent: old_parent })
         .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
 
-    app.execute_operation(Operation::ConnectToParent { parent })
+    app.execute_operation(Operation::Reparent { parent: new_parent })
         .blocking_wait();
-    app.execute_operation(Operation::Flush).blocking_wait();
 
-    app.execute_operation(Operation::Submit { value: 1 })
-        .blocking_wait();
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![
+            outgoing_message(old_parent, Message::Deregister),
+            outgoing_message(
+                new_parent,
+                Message::Register {
+                    stake: Amount::ZERO,
+                },
+            ),
+        ]
+    );
+}
 
-    assert_eq!(*app.state.value.get(), 1);
+/// Test if a chain that is not connected to a parent chain can't be reparented.
+#[test]
+#[should_panic(expected = "Can't reparent if the chain is not connected to a parent chain")]
+fn reparent_without_parent() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::Reparent {
+        parent: ChainId::root(2),
+    })
+    .blocking_wait();
 }
 
-/// Test if flushed values are accumulated.
-#[proptest]
-fn incoming_messages_are_accumulated(incoming_messages: Vec<u32>) {
+/// Test if a child chain without stake is removed when it deregisters.
+#[test]
+fn deregister_child() {
     let mut app = create_and_instantiate_app();
+    let child = ChainId::root(1);
+
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::ZERO,
+        },
+    );
+    receive_flush(&mut app, 5);
+    receive_message(&mut app, child, Message::Deregister);
+
+    assert_eq!(*app.state.value.get(), 5);
+    assert_eq!(
+        app.state.children.get(&child).blocking_wait().unwrap(),
+        None
+    );
+    assert_eq!(
+        app.state
+            .child_contributions
+            .get(&child)
+            .blocking_wait()
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        app.state
+            .former_children
+            .get(&child)
+            .blocking_wait()
+            .unwrap(),
+        None
+    );
+}
+
+/// Test if a child chain that deregisters can withdraw its stake after the unstaking cooldown.
+#[test]
+fn deregistered_child_withdraws_stake() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+    let child = ChainId::root(1);
+
+    fund_application_account(&mut app, Amount::from_tokens(10));
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::from_tokens(10),
+        },
+    );
+
+    app.runtime.set_system_time(Timestamp::from(5_000));
+    receive_message(&mut app, child, Message::Deregister);
+
+    assert_eq!(
+        app.state.children.get(&child).blocking_wait().unwrap(),
+        None
+    );
+    assert_eq!(
+        app.state
+            .former_children
+            .get(&child)
+            .blocking_wait()
+            .unwrap()
+            .and_then(|record| record.unstaking_since),
+        Some(Timestamp::from(5_000))
+    );
+
+    app.runtime.set_system_time(Timestamp::from(6_000));
+    receive_message(&mut app, child, Message::WithdrawStake);
+
+    assert_eq!(
+        *app.runtime.outgoing_transfers(),
+        HashMap::from([(Account::chain(child), Amount::from_tokens(10))])
+    );
+    assert_eq!(
+        app.state
+            .former_children
+            .get(&child)
+            .blocking_wait()
+            .unwrap(),
+        None
+    );
+}
+
+/// Test if a child chain that left and connected again to the same parent chain withdraws only
+/// the stake it left behind, staying registered with its current stake.
+#[test]
+fn reconnected_child_withdraws_former_stake() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+    let child = ChainId::root(1);
+    let stake = Amount::from_tokens(10);
+
+    fund_application_account(&mut app, Amount::from_tokens(20));
+    receive_message(&mut app, child, Message::Register { stake });
+    receive_message(&mut app, child, Message::Deregister);
+    receive_message(&mut app, child, Message::Register { stake });
+
+    app.runtime.set_system_time(Timestamp::from(1_000));
+    receive_message(&mut app, child, Message::WithdrawStake);
+
+    assert_eq!(
+        *app.runtime.outgoing_transfers(),
+        HashMap::from([(Account::chain(child), stake)])
+    );
+    assert_eq!(
+        app.state
+            .former_children
+            .get(&child)
+            .blocking_wait()
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        app.state
+            .children
+            .get(&child)
+            .blocking_wait()
+            .unwrap()
+            .map(|record| record.stake),
+        Some(stake)
+    );
 
-    for &message in &incoming_messages {
-        app.execute_message(message.into()).blocking_wait();
+    receive_flush(&mut app, 5);
+
+    assert_eq!(*app.state.value.get(), 5);
+}
+
+/// Test if the stakes a child chain leaves behind each time it deregisters are added together.
+#[test]
+fn former_child_stakes_are_added() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+    let child = ChainId::root(1);
+    let stake = Amount::from_tokens(10);
+
+    for time in [1_000, 2_000] {
+        receive_message(&mut app, child, Message::Register { stake });
+        app.runtime.set_system_time(Timestamp::from(time));
+        receive_message(&mut app, child, Message::Deregister);
     }
 
     assert_eq!(
-        *app.state.value.get(),
-        incoming_messages.into_iter().map(u64::from).sum::<u64>()
+        app.state
+            .former_children
+            .get(&child)
+            .blocking_wait()
+            .unwrap()
+            .map(|record| (record.stake, record.unstaking_since)),
+        Some((Amount::from_tokens(20), Some(Timestamp::from(2_000))))
     );
 }
 
-/// Test if flushed value overflows cause the block to be rejected.
+/// Test if disconnecting flushes the pending values to the parent chain and deregisters from it.
 #[test]
-#[should_panic(expected = "attempt to add with overflow")]
-fn incoming_messages_overflow() {
+fn disconnect() {
     let mut app = create_and_instantiate_app();
+    let parent = ChainId::root(1);
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 5,
+    })
+    .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
 
-    app.execute_message(u64::MAX).blocking_wait();
-    app.execute_message(1).blocking_wait();
+    app.execute_operation(Operation::Disconnect).blocking_wait();
+
+    assert_eq!(*app.state.parent.get(), None);
+    assert!(!*app.state.retired.get());
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![
+            outgoing_message(
+                parent,
+                Message::Flush {
+                    epoch: 0,
+                    value: 5,
+                    contributions: 1,
+                    commitment: empty_commitment(),
+                    replicas: Vec::new(),
+                },
+            ),
+            outgoing_message(parent, Message::Deregister),
+        ]
+    );
 }
 
-/// Test if flushed value does not overflow if it is flushed further upwards.
-#[proptest]
-fn incoming_messages_overflow_is_avoided_by_a_flush(parent: ChainId) {
+/// Test if retiring flushes the pending values to the parent chain and notifies it.
+#[test]
+fn retire() {
     let mut app = create_and_instantiate_app();
+    let parent = ChainId::root(1);
 
-    app.execute_message(u64::MAX).blocking_wait();
     app.execute_operation(Operation::ConnectToParent { parent })
         .blocking_wait();
-    app.execute_operation(Operation::Flush).blocking_wait();
-    app.execute_message(1).blocking_wait();
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 5,
+    })
+    .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
 
-    assert_eq!(*app.state.value.get(), 1);
+    app.execute_operation(Operation::Retire).blocking_wait();
+
+    assert_eq!(*app.state.parent.get(), None);
+    assert!(*app.state.retired.get());
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![
+            outgoing_message(
+                parent,
+                Message::Flush {
+                    epoch: 0,
+                    value: 5,
+                    contributions: 1,
+                    commitment: empty_commitment(),
+                    replicas: Vec::new(),
+                },
+            ),
+            outgoing_message(parent, Message::Retire),
+        ]
+    );
 }
 
-/// Creates a [`DepinDemoContract`] instance ready to be tested.
-fn create_and_instantiate_app() -> DepinDemoContract {
-    let runtime = ContractRuntime::new().with_application_parameters(());
-    let mut contract = DepinDemoContract {
-        state: DepinDemoState::load(runtime.root_view_storage_context())
-            .blocking_wait()
-            .expect("Failed to read from mock key value store"),
-        runtime,
-    };
+/// Test if a retired chain refuses further submissions.
+#[test]
+#[should_panic(expected = "Chain has been retired and can't execute Submit")]
+fn retired_chain_refuses_submit() {
+    let mut app = create_and_instantiate_app();
 
-    contract.instantiate(()).blocking_wait();
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::Retire).blocking_wait();
 
-    contract
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 5,
+    })
+    .blocking_wait();
+}
+
+/// Test if a retired chain can still withdraw its stake from its former parent chain.
+#[test]
+fn retired_chain_withdraws_stake() {
+    let mut app = create_and_instantiate_app();
+    let parent = ChainId::root(1);
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.execute_operation(Operation::Retire).blocking_wait();
+    app.runtime.created_send_message_requests().clear();
+
+    app.execute_operation(Operation::WithdrawStakeFrom { parent })
+        .blocking_wait();
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(parent, Message::WithdrawStake)]
+    );
+}
+
+/// Test if aggregator chains can't retire while they still have child chains.
+#[test]
+#[should_panic(expected = "Chains with child chains can't retire")]
+fn aggregator_can_not_retire() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    receive_message(
+        &mut app,
+        ChainId::root(2),
+        Message::Register {
+            stake: Amount::ZERO,
+        },
+    );
+
+    app.execute_operation(Operation::Retire).blocking_wait();
+}
+
+/// Test if a retired chain refuses new child chains.
+#[test]
+#[should_panic(expected = "Chain has been retired and can't aggregate the values of child chains")]
+fn retired_chain_refuses_child_chains() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::Retire).blocking_wait();
+
+    receive_message(
+        &mut app,
+        ChainId::root(2),
+        Message::Register {
+            stake: Amount::ZERO,
+        },
+    );
+}
+
+/// Test if a retired chain ignores the epochs started by its former parent chain.
+#[test]
+fn retired_chain_ignores_new_epoch() {
+    let mut app = create_and_instantiate_app();
+    let parent = ChainId::root(1);
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.execute_operation(Operation::Retire).blocking_wait();
+    app.runtime.created_send_message_requests().clear();
+
+    receive_message(&mut app, parent, Message::NewEpoch { epoch: 1 });
+
+    assert_eq!(*app.state.epoch.get(), 0);
+    assert!(app.runtime.created_send_message_requests().is_empty());
+}
+
+/// Test if a parent chain records its retired child chains and rejects their flushes.
+#[test]
+#[should_panic(expected = "has retired")]
+fn retired_child_flush_is_rejected() {
+    let mut app = create_and_instantiate_app();
+    let child = ChainId::root(1);
+
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::ZERO,
+        },
+    );
+    app.runtime.set_system_time(Timestamp::from(5_000));
+    receive_message(&mut app, child, Message::Retire);
+
+    assert_eq!(
+        app.state.children.get(&child).blocking_wait().unwrap(),
+        None
+    );
+    assert_eq!(
+        app.state
+            .retired_children
+            .get(&child)
+            .blocking_wait()
+            .unwrap(),
+        Some(Timestamp::from(5_000))
+    );
+
+    receive_flush(&mut app, 5);
+}
+
+/// Test if joining a tree asks the root chain for a parent chain.
+#[proptest]
+fn join(root: ChainId) {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::Join {
+        root,
+        aggregator: false,
+    })
+    .blocking_wait();
+
+    assert_eq!(*app.state.pending_join.get(), Some(root));
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            root,
+            Message::Join {
+                aggregator: false,
+                stake: Amount::ZERO,
+            },
+        )]
+    );
+}
+
+/// Test if the root chain assigns joining chains level by level to the chains that joined as
+/// aggregator chains.
+#[test]
+fn root_assigns_parents_by_level() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        fan_out: 2,
+        ..DepinDemoParameters::default()
+    });
+    let root = ChainId::root(0);
+    let children = (1..=5).map(ChainId::root).collect::<Vec<_>>();
+
+    app.runtime.set_chain_id(root);
+
+    for (index, &child) in children.iter().enumerate() {
+        let message = Message::Join {
+            aggregator: index < 2,
+            stake: Amount::ZERO,
+        };
+        receive_message(&mut app, child, message);
+    }
+    receive_message(
+        &mut app,
+        children[2],
+        Message::Join {
+            aggregator: false,
+            stake: Amount::ZERO,
+        },
+    );
+
+    let expected_parents = [
+        root,
+        root,
+        children[0],
+        children[0],
+        children[1],
+        children[0],
+    ];
+    let joined_children = children.iter().chain([&children[2]]);
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        joined_children
+            .zip(expected_parents)
+            .map(|(&child, parent)| {
+                let message = Message::AssignParent {
+                    parent,
+                    stake: Amount::ZERO,
+                };
+                outgoing_message(child, message)
+            })
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        app.state.joined_chains.read(..).blocking_wait().unwrap(),
+        children
+    );
+}
+
+/// Test if only the root chain assigns parent chains.
+#[test]
+#[should_panic(expected = "Only the root chain assigns parent chains to joining chains")]
+fn join_non_root_chain() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+
+    receive_message(
+        &mut app,
+        ChainId::root(2),
+        Message::Join {
+            aggregator: false,
+            stake: Amount::ZERO,
+        },
+    );
+}
+
+/// Test if the root chain forwards the stake of a joining chain to its assigned parent chain.
+#[test]
+fn root_forwards_join_stake() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        fan_out: 1,
+        ..staking_parameters()
+    });
+    let root = ChainId::root(0);
+    let aggregator = ChainId::root(1);
+    let child = ChainId::root(2);
+    let stake = Amount::from_tokens(10);
+
+    app.runtime.set_chain_id(root);
+    let application_account = fund_application_account(&mut app, Amount::from_tokens(20));
+
+    receive_message(
+        &mut app,
+        aggregator,
+        Message::Join {
+            aggregator: true,
+            stake,
+        },
+    );
+    receive_message(
+        &mut app,
+        child,
+        Message::Join {
+            aggregator: false,
+            stake,
+        },
+    );
+
+    assert_eq!(
+        *app.runtime.outgoing_transfers(),
+        HashMap::from([(Account::new(aggregator, application_account), stake)])
+    );
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![
+            outgoing_message(
+                aggregator,
+                Message::AssignParent {
+                    parent: root,
+                    stake,
+                },
+            ),
+            outgoing_message(
+                child,
+                Message::AssignParent {
+                    parent: aggregator,
+                    stake,
+                },
+            ),
+        ]
+    );
+}
+
+/// Test if the root chain opens a new aggregator chain in the last free place of its tree, and
+/// assigns the joining chain to it.
+#[test]
+fn root_opens_aggregator_for_last_place() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        fan_out: 2,
+        aggregator_balance: Amount::from_tokens(5),
+        ..staking_parameters()
+    });
+    let root = ChainId::root(0);
+    let children = [ChainId::root(1), ChainId::root(2)];
+    let stake = Amount::from_tokens(10);
+    let ownership = ChainOwnership::single(AccountOwner::from(CryptoHash::test_hash("owner")));
+    let open_chain_message = MessageId {
+        chain_id: root,
+        height: BlockHeight(0),
+        index: 0,
+    };
+    let new_aggregator = ChainId::child(open_chain_message);
+
+    app.runtime
+        .set_chain_id(root)
+        .set_chain_balance(Amount::from_tokens(5))
+        .set_chain_ownership(ownership.clone())
+        .add_expected_open_chain_call(
+            ownership,
+            linera_sdk::linera_base_types::ApplicationPermissions::default(),
+            Amount::from_tokens(5),
+            open_chain_message,
+        );
+    let application_account = fund_application_account(&mut app, Amount::from_tokens(30));
+
+    for child in children {
+        let message = Message::Join {
+            aggregator: false,
+            stake,
+        };
+        receive_message(&mut app, child, message);
+    }
+
+    assert_eq!(
+        *app.runtime.outgoing_transfers(),
+        HashMap::from([(Account::new(new_aggregator, application_account), stake)])
+    );
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![
+            outgoing_message(
+                children[0],
+                Message::AssignParent {
+                    parent: root,
+                    stake,
+                },
+            ),
+            outgoing_message(
+                new_aggregator,
+                Message::ConnectOpenedChain {
+                    parent: root,
+                    role: NodeRole::Aggregator,
+                    open_chain_message,
+                    stake,
+                },
+            ),
+            outgoing_message(
+                children[1],
+                Message::AssignParent {
+                    parent: new_aggregator,
+                    stake,
+                },
+            ),
+        ]
+    );
+    assert_eq!(
+        app.state.joined_chains.read(..).blocking_wait().unwrap(),
+        vec![children[0], new_aggregator, children[1]]
+    );
+    assert_eq!(
+        app.state
+            .joined_aggregators
+            .read(..)
+            .blocking_wait()
+            .unwrap(),
+        vec![new_aggregator]
+    );
+}
+
+/// Test if a joining chain connects to the parent chain assigned by the root chain.
+#[test]
+fn assigned_parent_is_adopted() {
+    let mut app = create_and_instantiate_app();
+    let root = ChainId::root(0);
+    let parent = ChainId::root(1);
+
+    app.execute_operation(Operation::Join {
+        root,
+        aggregator: false,
+    })
+    .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
+
+    receive_message(
+        &mut app,
+        root,
+        Message::AssignParent {
+            parent,
+            stake: Amount::ZERO,
+        },
+    );
+
+    assert_eq!(*app.state.parent.get(), Some(parent));
+    assert_eq!(*app.state.pending_join.get(), None);
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            parent,
+            Message::Register {
+                stake: Amount::ZERO,
+            },
+        )]
+    );
+}
+
+/// Test if parent chains assigned by other chains than the root chain are rejected.
+#[test]
+#[should_panic(expected = "Parent chains can only be assigned by the root chain that was asked to")]
+fn unrequested_parent_assignment() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::Join {
+        root: ChainId::root(0),
+        aggregator: false,
+    })
+    .blocking_wait();
+
+    receive_message(
+        &mut app,
+        ChainId::root(2),
+        Message::AssignParent {
+            parent: ChainId::root(2),
+            stake: Amount::ZERO,
+        },
+    );
+}
+
+/// Test if rebalancing moves the child chains exceeding the fan-in budget to the target chain.
+#[test]
+fn rebalance_moves_children_over_budget() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        fan_in_budget: 2,
+        ..DepinDemoParameters::default()
+    });
+    let target = ChainId::root(9);
+    let children = register_children(&mut app, 4);
+
+    app.execute_operation(Operation::Rebalance {
+        target: Some(target),
+        balance: Amount::ZERO,
+    })
+    .blocking_wait();
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        children[..2]
+            .iter()
+            .map(|&child| {
+                let message = Message::MoveToParent {
+                    parent: target,
+                    stake: Amount::ZERO,
+                };
+                outgoing_message(child, message)
+            })
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        app.state.moving_children.indices().blocking_wait().unwrap(),
+        children[..2]
+    );
+
+    receive_message(&mut app, children[0], Message::Moved);
+
+    assert_eq!(
+        app.state.moving_children.indices().blocking_wait().unwrap(),
+        children[1..2]
+    );
+}
+
+/// Test if child chains that are already moving are not counted or moved again.
+#[test]
+#[should_panic(expected = "Aggregator chain is within its fan-in budget")]
+fn rebalance_ignores_moving_children() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        fan_in_budget: 2,
+        ..DepinDemoParameters::default()
+    });
+
+    register_children(&mut app, 3);
+
+    for _ in 0..2 {
+        app.execute_operation(Operation::Rebalance {
+            target: Some(ChainId::root(9)),
+            balance: Amount::ZERO,
+        })
+        .blocking_wait();
+    }
+}
+
+/// Test if an aggregator receiving too many flushes moves half of its child chains.
+#[test]
+fn rebalance_on_flush_load() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        flush_budget: 2,
+        load_window: TimeDelta::from_secs(60),
+        ..DepinDemoParameters::default()
+    });
+    let target = ChainId::root(9);
+    let children = register_children(&mut app, 3);
+
+    app.runtime.set_system_time(Timestamp::from(100_000_000));
+
+    for _ in 0..3 {
+        receive_flush(&mut app, 1);
+    }
+
+    assert_eq!(
+        *app.state.flush_load.get(),
+        FlushLoad {
+            window_start: Timestamp::from(100_000_000),
+            flushes: 3,
+        }
+    );
+
+    app.execute_operation(Operation::Rebalance {
+        target: Some(target),
+        balance: Amount::ZERO,
+    })
+    .blocking_wait();
+
+    assert_eq!(
+        app.state.moving_children.indices().blocking_wait().unwrap(),
+        children[..2]
+    );
+}
+
+/// Test if flushes received in a load window that ended don't trigger a rebalance.
+#[test]
+#[should_panic(expected = "Aggregator chain is within its fan-in budget")]
+fn flush_load_window_ends() {
+    let load_window = TimeDelta::from_secs(60);
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        flush_budget: 2,
+        load_window,
+        ..DepinDemoParameters::default()
+    });
+
+    register_children(&mut app, 3);
+
+    for _ in 0..3 {
+        receive_flush(&mut app, 1);
+    }
+
+    app.runtime
+        .set_system_time(Timestamp::from(0).saturating_add(load_window));
+
+    app.execute_operation(Operation::Rebalance {
+        target: Some(ChainId::root(9)),
+        balance: Amount::ZERO,
+    })
+    .blocking_wait();
+}
+
+/// Test if rebalancing without a target opens a new aggregator chain for the moved child chains.
+#[test]
+fn rebalance_to_new_chain() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        fan_in_budget: 1,
+        ..DepinDemoParameters::default()
+    });
+    let children = register_children(&mut app, 2);
+    let ownership = ChainOwnership::single(AccountOwner::from(CryptoHash::test_hash("owner")));
+    let open_chain_message = MessageId {
+        chain_id: ChainId::root(0),
+        height: BlockHeight(3),
+        index: 0,
+    };
+    let new_chain = ChainId::child(open_chain_message);
+    let balance = Amount::from_tokens(5);
+
+    app.runtime
+        .set_chain_ownership(ownership.clone())
+        .add_expected_open_chain_call(
+            ownership,
+            linera_sdk::linera_base_types::ApplicationPermissions::default(),
+            balance,
+            open_chain_message,
+        );
+
+    app.execute_operation(Operation::Rebalance {
+        target: None,
+        balance,
+    })
+    .blocking_wait();
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![
+            outgoing_message(
+                new_chain,
+                Message::ConnectOpenedChain {
+                    parent: ChainId::root(0),
+                    role: NodeRole::Aggregator,
+                    open_chain_message,
+                    stake: Amount::ZERO,
+                },
+            ),
+            outgoing_message(
+                children[0],
+                Message::MoveToParent {
+                    parent: new_chain,
+                    stake: Amount::ZERO,
+                },
+            ),
+        ]
+    );
+}
+
+/// Test if rebalancing to an existing target chain can't fund it with a balance.
+#[test]
+#[should_panic(expected = "Only a newly opened aggregator chain can be funded with a balance")]
+fn rebalance_to_target_with_balance() {
+    let mut app = create_and_instantiate_app();
+
+    app.runtime.set_chain_id(ChainId::root(0));
+    app.execute_operation(Operation::Rebalance {
+        target: Some(ChainId::root(9)),
+        balance: Amount::ONE,
+    })
+    .blocking_wait();
+}
+
+/// Test if a moved child chain's stake is forwarded to its new parent chain instead of being
+/// kept for it to withdraw.
+#[test]
+fn moved_child_stake_is_forwarded() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        fan_in_budget: 1,
+        ..staking_parameters()
+    });
+    let target = ChainId::root(9);
+    let stake = Amount::from_tokens(10);
+
+    app.runtime.set_chain_id(ChainId::root(0));
+    let application_account = fund_application_account(&mut app, Amount::from_tokens(20));
+
+    for child in [ChainId::root(1), ChainId::root(2)] {
+        receive_message(&mut app, child, Message::Register { stake });
+    }
+    app.runtime.created_send_message_requests().clear();
+
+    app.execute_operation(Operation::Rebalance {
+        target: Some(target),
+        balance: Amount::ZERO,
+    })
+    .blocking_wait();
+
+    let moved_child = app.state.moving_children.indices().blocking_wait().unwrap()[0];
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            moved_child,
+            Message::MoveToParent {
+                parent: target,
+                stake,
+            },
+        )]
+    );
+
+    receive_message(&mut app, moved_child, Message::Moved);
+
+    assert_eq!(
+        *app.runtime.outgoing_transfers(),
+        HashMap::from([(Account::new(target, application_account), stake)])
+    );
+    assert_eq!(
+        app.state
+            .former_children
+            .get(&moved_child)
+            .blocking_wait()
+            .unwrap(),
+        None
+    );
+}
+
+/// Test if a moving child chain that withdraws its stake is no longer tracked as moving, so that
+/// it isn't subtracted from the remaining child chains.
+#[test]
+fn withdrawn_child_stops_moving() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        fan_in_budget: 1,
+        ..staking_parameters()
+    });
+    let stake = Amount::from_tokens(10);
+
+    app.runtime.set_chain_id(ChainId::root(0));
+    fund_application_account(&mut app, Amount::from_tokens(20));
+
+    for child in [ChainId::root(1), ChainId::root(2)] {
+        receive_message(&mut app, child, Message::Register { stake });
+    }
+
+    app.execute_operation(Operation::Rebalance {
+        target: Some(ChainId::root(9)),
+        balance: Amount::ZERO,
+    })
+    .blocking_wait();
+
+    let moving_child = app.state.moving_children.indices().blocking_wait().unwrap()[0];
+
+    receive_message(&mut app, moving_child, Message::Unstake);
+    app.runtime.set_system_time(Timestamp::from(1_000));
+    receive_message(&mut app, moving_child, Message::WithdrawStake);
+
+    assert_eq!(
+        app.state.moving_children.indices().blocking_wait().unwrap(),
+        Vec::<ChainId>::new()
+    );
+
+    receive_message(&mut app, ChainId::root(3), Message::Register { stake });
+    app.execute_operation(Operation::Rebalance {
+        target: Some(ChainId::root(9)),
+        balance: Amount::ZERO,
+    })
+    .blocking_wait();
+
+    let moving_children = app.state.moving_children.indices().blocking_wait().unwrap();
+
+    assert_eq!(moving_children.len(), 1);
+    assert_ne!(moving_children[0], moving_child);
+}
+
+/// Test if a moved child chain flushes its pending values to its old parent chain before
+/// connecting to the new one.
+#[test]
+fn moved_child_drains_to_old_parent() {
+    let mut app = create_and_instantiate_app();
+    let old_parent = ChainId::root(1);
+    let new_parent = ChainId::root(2);
+
+    app.execute_operation(Operation::ConnectToParent { parent: old_parent })
+        .blocking_wait();
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 5,
+    })
+    .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
+
+    receive_message(
+        &mut app,
+        old_parent,
+        Message::MoveToParent {
+            parent: new_parent,
+            stake: Amount::ZERO,
+        },
+    );
+
+    assert_eq!(*app.state.parent.get(), Some(new_parent));
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![
+            outgoing_message(
+                old_parent,
+                Message::Flush {
+                    epoch: 0,
+                    value: 5,
+                    contributions: 1,
+                    commitment: empty_commitment(),
+                    replicas: Vec::new(),
+                },
+            ),
+            outgoing_message(old_parent, Message::Moved),
+            outgoing_message(
+                new_parent,
+                Message::Register {
+                    stake: Amount::ZERO,
+                },
+            ),
+        ]
+    );
+}
+
+/// Test if only the parent chain can move a child chain.
+#[test]
+#[should_panic(expected = "Only the parent chain can move its child chains")]
+fn move_from_other_chain() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+
+    receive_message(
+        &mut app,
+        ChainId::root(2),
+        Message::MoveToParent {
+            parent: ChainId::root(2),
+            stake: Amount::ZERO,
+        },
+    );
+}
+
+/// Test if a chain opened by an aggregator chain takes the role and connects to the parent chain
+/// it is given, with the stake already paid by the aggregator chain.
+#[test]
+fn opened_chain_connects_to_parent() {
+    let mut app = create_and_instantiate_app();
+    let opener = ChainId::root(1);
+    let parent = ChainId::root(2);
+    let open_chain_message = MessageId {
+        chain_id: opener,
+        height: BlockHeight(0),
+        index: 0,
+    };
+
+    app.runtime.set_chain_id(ChainId::child(open_chain_message));
+
+    receive_message(
+        &mut app,
+        opener,
+        Message::ConnectOpenedChain {
+            parent,
+            role: NodeRole::Edge,
+            open_chain_message,
+            stake: Amount::from_tokens(10),
+        },
+    );
+
+    assert_eq!(*app.state.parent.get(), Some(parent));
+    assert_eq!(*app.state.role.get(), Some(NodeRole::Edge));
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            parent,
+            Message::Register {
+                stake: Amount::from_tokens(10),
+            },
+        )]
+    );
+    assert!(app.runtime.outgoing_transfers().is_empty());
+}
+
+/// Test if only the chain that opened a chain can connect it to a parent chain.
+#[test]
+#[should_panic(expected = "Only the chain that opened this chain can connect it to a parent chain")]
+fn opened_chain_connected_by_other_chain() {
+    let mut app = create_and_instantiate_app();
+    let open_chain_message = MessageId {
+        chain_id: ChainId::root(1),
+        height: BlockHeight(0),
+        index: 0,
+    };
+
+    app.runtime.set_chain_id(ChainId::child(open_chain_message));
+
+    receive_message(
+        &mut app,
+        ChainId::root(2),
+        Message::ConnectOpenedChain {
+            parent: ChainId::root(2),
+            role: NodeRole::Edge,
+            open_chain_message,
+            stake: Amount::ZERO,
+        },
+    );
+}
+
+/// Test if an aggregator chain opens an edge chain for a device, connected to it as its parent,
+/// paying the edge chain's stake from the balance it is funded with.
+#[test]
+fn open_edge_chain() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+    let aggregator = ChainId::root(0);
+    let owner = AccountOwner::from(CryptoHash::test_hash("device owner"));
+    let balance = Amount::from_tokens(15);
+    let open_chain_message = MessageId {
+        chain_id: aggregator,
+        height: BlockHeight(0),
+        index: 0,
+    };
+    let edge_chain = ChainId::child(open_chain_message);
+
+    app.runtime
+        .set_chain_id(aggregator)
+        .set_chain_balance(balance)
+        .add_expected_open_chain_call(
+            ChainOwnership::single(owner),
+            linera_sdk::linera_base_types::ApplicationPermissions::default(),
+            Amount::from_tokens(5),
+            open_chain_message,
+        );
+    let application_account = fund_application_account(&mut app, Amount::ZERO);
+
+    app.execute_operation(Operation::OpenEdgeChain { owner, balance })
+        .blocking_wait();
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            edge_chain,
+            Message::ConnectOpenedChain {
+                parent: aggregator,
+                role: NodeRole::Edge,
+                open_chain_message,
+                stake: Amount::from_tokens(10),
+            },
+        )]
+    );
+    assert_eq!(
+        app.state
+            .opened_chains
+            .index_values()
+            .blocking_wait()
+            .unwrap(),
+        vec![(edge_chain, owner)]
+    );
+    assert_eq!(
+        app.runtime.owner_balance(application_account),
+        Amount::from_tokens(10)
+    );
+}
+
+/// Test if edge chains can't be opened without the funds to stake with their parent chain.
+#[test]
+#[should_panic(expected = "Edge chains must be funded with at least the registration stake")]
+fn open_underfunded_edge_chain() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+
+    app.execute_operation(Operation::OpenEdgeChain {
+        owner: AccountOwner::from(CryptoHash::test_hash("device owner")),
+        balance: Amount::from_tokens(5),
+    })
+    .blocking_wait();
+}
+
+/// Test if the root chain broadcasts a new version of the tree configuration to its child chains.
+#[test]
+fn update_config() {
+    let mut app = create_and_instantiate_app();
+    let children = register_children(&mut app, 2);
+    let config = temperature_config(OutOfRangePolicy::Quarantine);
+
+    app.execute_operation(Operation::UpdateConfig {
+        config: config.clone(),
+    })
+    .blocking_wait();
+
+    assert_eq!(*app.state.config_version.get(), 1);
+    assert_eq!(*app.state.tree_config.get(), Some(config.clone()));
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        children
+            .iter()
+            .map(|&child| outgoing_message(
+                child,
+                Message::UpdateConfig {
+                    version: 1,
+                    config: config.clone(),
+                },
+            ))
+            .collect::<Vec<_>>()
+    );
+}
+
+/// Test if only the root chain can update the tree configuration.
+#[test]
+#[should_panic(expected = "Only the root chain can update the configuration of the tree")]
+fn update_config_on_non_root_chain() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+
+    app.execute_operation(Operation::UpdateConfig {
+        config: TreeConfig::default(),
+    })
+    .blocking_wait();
+}
+
+/// Test if a chain applies and forwards newer configurations from its parent chain, and ignores
+/// older ones.
+#[test]
+fn config_from_parent_is_applied_if_newer() {
+    let mut app = create_and_instantiate_app();
+    let parent = ChainId::root(1);
+    let child = ChainId::root(2);
+    let new_config = temperature_config(OutOfRangePolicy::Reject);
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::ZERO,
+        },
+    );
+    app.runtime.created_send_message_requests().clear();
+
+    receive_message(
+        &mut app,
+        parent,
+        Message::UpdateConfig {
+            version: 2,
+            config: new_config.clone(),
+        },
+    );
+    receive_message(
+        &mut app,
+        parent,
+        Message::UpdateConfig {
+            version: 1,
+            config: TreeConfig::default(),
+        },
+    );
+
+    assert_eq!(*app.state.config_version.get(), 2);
+    assert_eq!(*app.state.tree_config.get(), Some(new_config.clone()));
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            child,
+            Message::UpdateConfig {
+                version: 2,
+                config: new_config,
+            },
+        )]
+    );
+}
+
+/// Test if the root chain can't broadcast a configuration with an empty value range.
+#[test]
+#[should_panic(
+    expected = "The value range of metric \"temperature\" has a minimum above its maximum"
+)]
+fn config_with_empty_value_range() {
+    let mut app = create_and_instantiate_app();
+    let mut config = temperature_config(OutOfRangePolicy::Reject);
+
+    config.value_ranges[0].min = 30;
+
+    app.execute_operation(Operation::UpdateConfig { config })
+        .blocking_wait();
+}
+
+/// Test if the root chain can't broadcast a configuration with more than one value range for a
+/// metric.
+#[test]
+#[should_panic(expected = "Metric \"temperature\" has more than one value range")]
+fn config_with_duplicate_metric() {
+    let mut app = create_and_instantiate_app();
+    let mut config = temperature_config(OutOfRangePolicy::Reject);
+
+    config.value_ranges.push(MetricRange {
+        metric: "temperature".to_owned(),
+        min: 0,
+        max: 5,
+    });
+
+    app.execute_operation(Operation::UpdateConfig { config })
+        .blocking_wait();
+}
+
+/// Test if configuration updates from other chains than the parent chain are rejected.
+#[test]
+#[should_panic(expected = "Configuration updates can only be received from the parent chain")]
+fn config_from_other_chain() {
+    let mut app = create_and_instantiate_app();
+
+    receive_message(
+        &mut app,
+        ChainId::root(1),
+        Message::UpdateConfig {
+            version: 1,
+            config: TreeConfig::default(),
+        },
+    );
+}
+
+/// Test if the messages a former parent chain sent before it learned that this chain moved to
+/// another parent chain are ignored.
+#[test]
+fn former_parent_messages_are_ignored() {
+    let mut app = create_and_instantiate_app();
+    let old_parent = ChainId::root(1);
+    let new_parent = ChainId::root(2);
+
+    app.execute_operation(Operation::ConnectToParent { parent: old_parent })
+        .blocking_wait();
+    app.execute_operation(Operation::Reparent { parent: new_parent })
+        .blocking_wait();
+
+    let sent_messages = app.runtime.created_send_message_requests().len();
+
+    receive_message(
+        &mut app,
+        old_parent,
+        Message::MoveToParent {
+            parent: ChainId::root(3),
+            stake: Amount::ZERO,
+        },
+    );
+    receive_message(
+        &mut app,
+        old_parent,
+        Message::UpdateConfig {
+            version: 1,
+            config: temperature_config(OutOfRangePolicy::Reject),
+        },
+    );
+    receive_message(&mut app, old_parent, Message::NewEpoch { epoch: 1 });
+
+    assert_eq!(
+        app.runtime.created_send_message_requests().len(),
+        sent_messages
+    );
+    assert_eq!(*app.state.parent.get(), Some(new_parent));
+    assert_eq!(*app.state.config_version.get(), 0);
+    assert_eq!(*app.state.epoch.get(), 0);
+}
+
+/// Test if child chains that register receive the current tree configuration.
+#[test]
+fn registering_child_receives_config() {
+    let mut app = create_and_instantiate_app();
+    let child = ChainId::root(1);
+    let config = temperature_config(OutOfRangePolicy::Reject);
+
+    app.execute_operation(Operation::UpdateConfig {
+        config: config.clone(),
+    })
+    .blocking_wait();
+
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::ZERO,
+        },
+    );
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            child,
+            Message::UpdateConfig { version: 1, config },
+        )]
+    );
+}
+
+/// Test if submitted values are validated with the tree configuration instead of the
+/// application parameters once it is updated.
+#[test]
+#[should_panic(expected = "Value 9 is outside of the allowed range for metric \"temperature\"")]
+fn submit_with_updated_config() {
+    let mut app = create_and_instantiate_app_with_parameters(temperature_range_parameters(
+        OutOfRangePolicy::Quarantine,
+    ));
+
+    app.execute_operation(Operation::UpdateConfig {
+        config: temperature_config(OutOfRangePolicy::Reject),
+    })
+    .blocking_wait();
+
+    app.execute_operation(Operation::Submit {
+        metric: Some("temperature".to_owned()),
+        value: 9,
+    })
+    .blocking_wait();
+}
+
+/// Test if an edge chain accepts submitted values and connects to a parent chain.
+#[test]
+fn edge_role() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::SetRole {
+        role: NodeRole::Edge,
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 5,
+    })
+    .blocking_wait();
+
+    assert_eq!(*app.state.role.get(), Some(NodeRole::Edge));
+    assert_eq!(*app.state.value.get(), 5);
+}
+
+/// Test if edge chains don't accept child chains.
+#[test]
+#[should_panic(expected = "Edge chains can't receive Register")]
+fn edge_role_rejects_child_chains() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::SetRole {
+        role: NodeRole::Edge,
+    })
+    .blocking_wait();
+
+    receive_message(
+        &mut app,
+        ChainId::root(1),
+        Message::Register {
+            stake: Amount::ZERO,
+        },
+    );
+}
+
+/// Test if edge chains can't join a tree as aggregator chains, so that the root chain never
+/// assigns them child chains.
+#[test]
+#[should_panic(expected = "Edge chains can't execute Join")]
+fn edge_role_can_not_join_as_aggregator() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::SetRole {
+        role: NodeRole::Edge,
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::Join {
+        root: ChainId::root(0),
+        aggregator: true,
+    })
+    .blocking_wait();
+}
+
+/// Test if chains with child chains can't become edge chains.
+#[test]
+#[should_panic(expected = "Chains with child chains can't be edge chains")]
+fn edge_role_with_child_chains() {
+    let mut app = create_and_instantiate_app();
+
+    register_children(&mut app, 1);
+
+    app.execute_operation(Operation::SetRole {
+        role: NodeRole::Edge,
+    })
+    .blocking_wait();
+}
+
+/// Test if aggregator chains don't accept submitted values.
+#[test]
+#[should_panic(expected = "Aggregator chains can't execute Submit")]
+fn aggregator_role_rejects_submissions() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::SetRole {
+        role: NodeRole::Aggregator,
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 5,
+    })
+    .blocking_wait();
+}
+
+/// Test if only root chains perform the operations that affect the whole tree.
+#[test]
+#[should_panic(expected = "Aggregator chains can't execute CloseEpoch")]
+fn aggregator_role_can_not_close_epochs() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::SetRole {
+        role: NodeRole::Aggregator,
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::CloseEpoch).blocking_wait();
+}
+
+/// Test if root chains never flush their values.
+#[test]
+#[should_panic(expected = "Root chains can't execute Flush")]
+fn root_role_never_flushes() {
+    let mut app = create_and_instantiate_app_with_parameters(streams_parameters());
+
+    app.execute_operation(Operation::SetRole {
+        role: NodeRole::Root,
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::Flush).blocking_wait();
+}
+
+/// Test if chains connected to a parent chain can't become root chains.
+#[test]
+#[should_panic(expected = "Chains connected to a parent chain can't be root chains")]
+fn root_role_with_parent() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::SetRole {
+        role: NodeRole::Root,
+    })
+    .blocking_wait();
+}
+
+/// Test if the chain the application is created on is set up with the instantiation argument.
+#[test]
+fn instantiate_with_setup() {
+    let mut app = create_app_on_new_chain(DepinDemoParameters::default());
+    let parent = ChainId::root(1);
+    let flush_policy = FlushPolicy {
+        max_pending_contributions: 2,
+    };
+
+    app.instantiate(ChainSetup {
+        parent: Some(parent),
+        role: Some(NodeRole::Edge),
+        flush_policy,
+    })
+    .blocking_wait();
+
+    assert!(*app.state.initialized.get());
+    assert_eq!(*app.state.parent.get(), Some(parent));
+    assert_eq!(*app.state.role.get(), Some(NodeRole::Edge));
+    assert_eq!(*app.state.flush_policy.get(), flush_policy);
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            parent,
+            Message::Register {
+                stake: Amount::ZERO,
+            },
+        )]
+    );
+}
+
+/// Test if the application can't be created with an empty value range.
+#[test]
+#[should_panic(
+    expected = "The value range of metric \"temperature\" has a minimum above its maximum"
+)]
+fn instantiate_with_empty_value_range() {
+    create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        value_ranges: BTreeMap::from([("temperature".to_owned(), ValueRange { min: 20, max: 10 })]),
+        ..DepinDemoParameters::default()
+    });
+}
+
+/// Test if the application can't be created to shard the aggregation without reducer chains.
+#[test]
+#[should_panic(expected = "Sharded aggregation requires at least one reducer chain")]
+fn instantiate_sharded_without_reducers() {
+    create_and_instantiate_app_with_parameters(sharded_parameters(vec![]));
+}
+
+/// Test if a chain set up by its first operation flushes automatically according to its flush
+/// policy.
+#[test]
+fn initialize_and_flush_automatically() {
+    let mut app = create_app_on_new_chain(DepinDemoParameters::default());
+    let parent = ChainId::root(1);
+
+    app.execute_operation(Operation::Initialize {
+        setup: ChainSetup {
+            parent: Some(parent),
+            role: Some(NodeRole::Edge),
+            flush_policy: FlushPolicy {
+                max_pending_contributions: 2,
+            },
+        },
+    })
+    .blocking_wait();
+
+    for value in [3, 4] {
+        app.execute_operation(Operation::Submit {
+            metric: None,
+            value,
+        })
+        .blocking_wait();
+    }
+
+    assert_eq!(*app.state.value.get(), 0);
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![
+            outgoing_message(
+                parent,
+                Message::Register {
+                    stake: Amount::ZERO,
+                },
+            ),
+            outgoing_message(
+                parent,
+                Message::Flush {
+                    epoch: 0,
+                    value: 7,
+                    contributions: 2,
+                    commitment: empty_commitment(),
+                    replicas: Vec::new(),
+                },
+            ),
+        ]
+    );
+}
+
+/// Test if a chain can only be set up once.
+#[test]
+#[should_panic(expected = "Application is already initialized on this chain")]
+fn initialize_twice() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::Initialize {
+        setup: ChainSetup::default(),
+    })
+    .blocking_wait();
+}
+
+/// Test if an aggregator chain flushes automatically once it merged enough contributions from its
+/// child chains.
+#[test]
+fn aggregator_flushes_automatically() {
+    let mut app = create_app_on_new_chain(DepinDemoParameters::default());
+    let parent = ChainId::root(2);
+
+    app.instantiate(ChainSetup {
+        parent: Some(parent),
+        role: Some(NodeRole::Aggregator),
+        flush_policy: FlushPolicy {
+            max_pending_contributions: 2,
+        },
+    })
+    .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
+
+    receive_flush(&mut app, 5);
+
+    assert!(app.runtime.created_send_message_requests().is_empty());
+
+    receive_flush(&mut app, 6);
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            parent,
+            Message::Flush {
+                epoch: 0,
+                value: 11,
+                contributions: 2,
+                commitment: app.contribution_root(0).blocking_wait(),
+                replicas: Vec::new(),
+            },
+        )]
+    );
+}
+
+/// Test if root chains never flush automatically.
+#[test]
+fn root_chain_never_flushes_automatically() {
+    let mut app = create_app_on_new_chain(streams_parameters());
+
+    app.instantiate(ChainSetup {
+        role: Some(NodeRole::Root),
+        flush_policy: FlushPolicy {
+            max_pending_contributions: 1,
+        },
+        ..ChainSetup::default()
+    })
+    .blocking_wait();
+
+    receive_flush(&mut app, 5);
+
+    assert_eq!(*app.state.value.get(), 5);
+    assert_eq!(*app.state.last_flush.get(), None);
+}
+
+/// Test if a chain's backup parent chains are recorded in order.
+#[test]
+fn set_backup_parents() {
+    let mut app = create_and_instantiate_app();
+    let backups = vec![ChainId::root(2), ChainId::root(3)];
+
+    app.runtime.set_chain_id(ChainId::root(0));
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::SetBackupParents {
+        parents: backups.clone(),
+    })
+    .blocking_wait();
+
+    assert_eq!(*app.state.backup_parents.get(), backups);
+}
+
+/// Test if the parent chain can't also be a backup parent chain.
+#[test]
+#[should_panic(expected = "can't be a backup parent chain of this chain")]
+fn parent_as_backup_parent() {
+    let mut app = create_and_instantiate_app();
+
+    app.runtime.set_chain_id(ChainId::root(0));
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::SetBackupParents {
+        parents: vec![ChainId::root(1)],
+    })
+    .blocking_wait();
+}
+
+/// Test if chains with backup parent chains replicate their flushes until the parent chain
+/// acknowledges them.
+#[test]
+fn flushes_are_replicated_until_acknowledged() {
+    let mut app = create_and_instantiate_app();
+    let parent = ChainId::root(1);
+
+    app.runtime.set_chain_id(ChainId::root(0));
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.execute_operation(Operation::SetBackupParents {
+        parents: vec![ChainId::root(2)],
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 5,
+    })
+    .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
+
+    app.execute_operation(Operation::Flush).blocking_wait();
+
+    let replicas = vec![ReplicatedFlush {
+        child: ChainId::root(0),
+        sequence: 0,
+        value: 5,
+        contributions: 1,
+    }];
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            parent,
+            Message::Flush {
+                epoch: 0,
+                value: 5,
+                contributions: 1,
+                commitment: empty_commitment(),
+                replicas: replicas.clone(),
+            },
+        )]
+    );
+    assert_eq!(
+        app.state
+            .unacknowledged_flushes
+            .get(&0)
+            .blocking_wait()
+            .unwrap(),
+        Some(UnacknowledgedFlush {
+            parent,
+            epoch: 0,
+            value: 5,
+            contributions: 1,
+            commitment: empty_commitment(),
+            replicas,
+        })
+    );
+
+    receive_message(
+        &mut app,
+        ChainId::root(2),
+        Message::AcknowledgeFlush { sequence: 0 },
+    );
+
+    assert_eq!(
+        app.state
+            .unacknowledged_flushes
+            .count()
+            .blocking_wait()
+            .expect("Failed to load unacknowledged flushes"),
+        1
+    );
+
+    receive_message(&mut app, parent, Message::AcknowledgeFlush { sequence: 0 });
+
+    assert_eq!(
+        app.state
+            .unacknowledged_flushes
+            .count()
+            .blocking_wait()
+            .expect("Failed to load unacknowledged flushes"),
+        0
+    );
+}
+
+/// Test if a chain fails over to its backup parent chain after too many unacknowledged flushes,
+/// sending them again to it.
+#[test]
+fn fail_over_after_unacknowledged_flushes() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        max_unacknowledged_flushes: 2,
+        ..DepinDemoParameters::default()
+    });
+    let parent = ChainId::root(1);
+    let backup = ChainId::root(2);
+
+    app.runtime.set_chain_id(ChainId::root(0));
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.execute_operation(Operation::SetBackupParents {
+        parents: vec![backup],
+    })
+    .blocking_wait();
+
+    for value in [5, 6, 7] {
+        app.execute_operation(Operation::Submit {
+            metric: None,
+            value,
+        })
+        .blocking_wait();
+
+        if value == 7 {
+            app.runtime.created_send_message_requests().clear();
+        }
+
+        app.execute_operation(Operation::Flush).blocking_wait();
+    }
+
+    let flush = |sequence: u64, value: u64| Message::Flush {
+        epoch: 0,
+        value,
+        contributions: 1,
+        commitment: empty_commitment(),
+        replicas: vec![ReplicatedFlush {
+            child: ChainId::root(0),
+            sequence,
+            value,
+            contributions: 1,
+        }],
+    };
+
+    assert_eq!(*app.state.parent.get(), Some(backup));
+    assert_eq!(*app.state.backup_parents.get(), vec![parent]);
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![
+            outgoing_message(parent, Message::Deregister),
+            outgoing_message(
+                backup,
+                Message::Register {
+                    stake: Amount::ZERO,
+                },
+            ),
+            outgoing_message(backup, flush(0, 5)),
+            outgoing_message(backup, flush(1, 6)),
+            outgoing_message(backup, flush(2, 7)),
+        ]
+    );
+    assert!(app
+        .state
+        .unacknowledged_flushes
+        .index_values()
+        .blocking_wait()
+        .unwrap()
+        .into_iter()
+        .all(|(_, flush)| flush.parent == backup));
+}
+
+/// Test if setting backup parent chains reserves the registration stake, which pays the stake of
+/// the backup parent chain when failing over, since that may happen while executing a message.
+#[test]
+fn fail_over_pays_reserved_stake() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        max_unacknowledged_flushes: 1,
+        ..staking_parameters()
+    });
+    let application_account = fund_application_account(&mut app, Amount::ZERO);
+    let parent = ChainId::root(1);
+    let backup = ChainId::root(2);
+    let stake = Amount::from_tokens(10);
+
+    app.runtime
+        .set_chain_id(ChainId::root(0))
+        .set_chain_balance(Amount::from_tokens(25));
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.execute_operation(Operation::SetBackupParents {
+        parents: vec![backup],
+    })
+    .blocking_wait();
+
+    assert_eq!(app.runtime.chain_balance(), Amount::from_tokens(5));
+    assert_eq!(app.runtime.owner_balance(application_account), stake);
+    assert_eq!(*app.state.reserved_stake.get(), stake);
+
+    for value in [5, 6] {
+        app.execute_operation(Operation::Submit {
+            metric: None,
+            value,
+        })
+        .blocking_wait();
+        app.execute_operation(Operation::Flush).blocking_wait();
+    }
+
+    assert_eq!(*app.state.parent.get(), Some(backup));
+    assert!(app
+        .runtime
+        .created_send_message_requests()
+        .contains(&outgoing_message(backup, Message::Register { stake })));
+    assert_eq!(
+        *app.runtime.outgoing_transfers(),
+        HashMap::from([
+            (Account::new(parent, application_account), stake),
+            (Account::new(backup, application_account), stake),
+        ])
+    );
+    assert_eq!(app.runtime.owner_balance(application_account), Amount::ZERO);
+    assert_eq!(app.runtime.chain_balance(), Amount::from_tokens(5));
+    assert_eq!(*app.state.reserved_stake.get(), Amount::ZERO);
+}
+
+/// Test if clearing the backup parent chains returns the reserved registration stake to the
+/// chain's balance.
+#[test]
+fn clearing_backup_parents_returns_reserved_stake() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+    let application_account = fund_application_account(&mut app, Amount::ZERO);
+
+    app.runtime
+        .set_chain_id(ChainId::root(0))
+        .set_chain_balance(Amount::from_tokens(15));
+    app.execute_operation(Operation::SetBackupParents {
+        parents: vec![ChainId::root(2)],
+    })
+    .blocking_wait();
+
+    assert_eq!(app.runtime.chain_balance(), Amount::from_tokens(5));
+
+    app.execute_operation(Operation::SetBackupParents { parents: vec![] })
+        .blocking_wait();
+
+    assert_eq!(app.runtime.chain_balance(), Amount::from_tokens(15));
+    assert_eq!(app.runtime.owner_balance(application_account), Amount::ZERO);
+    assert_eq!(*app.state.reserved_stake.get(), Amount::ZERO);
+    assert!(app.state.backup_parents.get().is_empty());
+}
+
+/// Test if a chain without a reserved registration stake keeps waiting for its parent chain
+/// instead of failing over.
+#[test]
+fn fail_over_waits_for_reserved_stake() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        max_unacknowledged_flushes: 1,
+        ..staking_parameters()
+    });
+    fund_application_account(&mut app, Amount::ZERO);
+    let parent = ChainId::root(1);
+    let backups = vec![ChainId::root(2), ChainId::root(3)];
+
+    app.runtime
+        .set_chain_id(ChainId::root(0))
+        .set_chain_balance(Amount::from_tokens(20));
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.execute_operation(Operation::SetBackupParents {
+        parents: backups.clone(),
+    })
+    .blocking_wait();
+
+    for value in [5, 6, 7] {
+        app.execute_operation(Operation::Submit {
+            metric: None,
+            value,
+        })
+        .blocking_wait();
+        app.execute_operation(Operation::Flush).blocking_wait();
+    }
+
+    assert_eq!(*app.state.parent.get(), Some(backups[0]));
+    assert_eq!(*app.state.backup_parents.get(), vec![backups[1], parent]);
+    assert!(!app
+        .runtime
+        .created_send_message_requests()
+        .iter()
+        .any(|request| request.destination == Destination::Recipient(backups[1])));
+}
+
+/// Test if parent chains acknowledge replicated flushes, and discount the replicated flushes they
+/// already merged before forwarding the others with their next flush.
+#[test]
+fn duplicate_replicas_are_discounted() {
+    let mut app = create_and_instantiate_app();
+    let parent = ChainId::root(10);
+    let grandchild = ChainId::root(9);
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    let children = register_children(&mut app, 2);
+    let grandchild_replica = ReplicatedFlush {
+        child: grandchild,
+        sequence: 0,
+        value: 5,
+        contributions: 1,
+    };
+    let child_replica = ReplicatedFlush {
+        child: children[1],
+        sequence: 3,
+        value: 3,
+        contributions: 2,
+    };
+
+    receive_message(
+        &mut app,
+        children[0],
+        Message::Flush {
+            epoch: 0,
+            value: 5,
+            contributions: 1,
+            commitment: empty_commitment(),
+            replicas: vec![grandchild_replica.clone()],
+        },
+    );
+    receive_message(
+        &mut app,
+        children[1],
+        Message::Flush {
+            epoch: 0,
+            value: 8,
+            contributions: 3,
+            commitment: empty_commitment(),
+            replicas: vec![grandchild_replica.clone(), child_replica.clone()],
+        },
+    );
+
+    assert_eq!(*app.state.value.get(), 8);
+    assert_eq!(*app.state.contributions.get(), 3);
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            children[1],
+            Message::AcknowledgeFlush { sequence: 3 },
+        )]
+    );
+
+    app.runtime.created_send_message_requests().clear();
+    app.execute_operation(Operation::Flush).blocking_wait();
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            parent,
+            Message::Flush {
+                epoch: 0,
+                value: 8,
+                contributions: 3,
+                commitment: app.contribution_root(0).blocking_wait(),
+                replicas: vec![grandchild_replica, child_replica],
+            },
+        )]
+    );
+}
+
+/// Test if chains only keep the sequence numbers of the latest replicated flushes merged from each
+/// chain, and discount older ones.
+#[test]
+fn merged_replicas_are_bounded() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        max_unacknowledged_flushes: 2,
+        ..DepinDemoParameters::default()
+    });
+    let grandchild = ChainId::root(9);
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(10),
+    })
+    .blocking_wait();
+    let children = register_children(&mut app, 1);
+
+    for sequence in [0, 1, 2, 3, 4, 5, 2] {
+        receive_message(
+            &mut app,
+            children[0],
+            Message::Flush {
+                epoch: 0,
+                value: 1,
+                contributions: 1,
+                commitment: empty_commitment(),
+                replicas: vec![ReplicatedFlush {
+                    child: grandchild,
+                    sequence,
+                    value: 1,
+                    contributions: 1,
+                }],
+            },
+        );
+    }
+
+    assert_eq!(*app.state.value.get(), 6);
+    assert_eq!(*app.state.contributions.get(), 6);
+    assert_eq!(
+        app.state
+            .merged_replicas
+            .get(&grandchild)
+            .blocking_wait()
+            .unwrap()
+            .map(|merged| (merged.highest, merged.sequences)),
+        Some((5, BTreeSet::from([3, 4, 5])))
+    );
+}
+
+/// Test if the unstake and withdraw stake operations send requests to the parent chain.
+#[proptest]
+fn unstake_operations_send_messages(parent: ChainId) {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
+
+    app.execute_operation(Operation::Unstake).blocking_wait();
+    app.execute_operation(Operation::WithdrawStake)
+        .blocking_wait();
+    app.execute_operation(Operation::WithdrawStakeFrom {
+        parent: ChainId::root(9),
+    })
+    .blocking_wait();
+
+    let messages = mem::take(&mut *app.runtime.created_send_message_requests())
+        .into_iter()
+        .map(|request| (request.destination, request.message))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        messages,
+        vec![
+            (Destination::Recipient(parent), Message::Unstake),
+            (Destination::Recipient(parent), Message::WithdrawStake),
+            (
+                Destination::Recipient(ChainId::root(9)),
+                Message::WithdrawStake
+            ),
+        ]
+    );
+    assert_eq!(*app.state.parent.get(), None);
+    assert!(!*app.state.unstaking.get());
+}
+
+/// Test if a chain flushes its pending values before unstaking, and stops flushing afterwards.
+#[test]
+fn unstaking_chain_stops_flushing() {
+    let mut app = create_app_on_new_chain(DepinDemoParameters::default());
+    let parent = ChainId::root(1);
+
+    app.execute_operation(Operation::Initialize {
+        setup: ChainSetup {
+            parent: Some(parent),
+            flush_policy: FlushPolicy {
+                max_pending_contributions: 1,
+            },
+            ..ChainSetup::default()
+        },
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 5,
+    })
+    .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
+
+    app.execute_operation(Operation::Unstake).blocking_wait();
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 6,
+    })
+    .blocking_wait();
+    receive_message(&mut app, parent, Message::NewEpoch { epoch: 1 });
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(parent, Message::Unstake)]
+    );
+    assert_eq!(*app.state.value.get(), 6);
+    assert_eq!(*app.state.epoch.get(), 1);
+}
+
+/// Test if a chain that is unstaking can't flush to its parent chain.
+#[test]
+#[should_panic(expected = "Chain is unstaking and can't flush to its parent chain")]
+fn unstaking_chain_can_not_flush() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::Unstake).blocking_wait();
+    app.execute_operation(Operation::Flush).blocking_wait();
+}
+
+/// Test if new epochs are not broadcast to the child chains that are unstaking.
+#[test]
+fn unstaking_children_are_not_notified() {
+    let mut app = create_and_instantiate_app();
+    let children = register_children(&mut app, 2);
+
+    receive_message(&mut app, children[0], Message::Unstake);
+    app.execute_operation(Operation::CloseEpoch).blocking_wait();
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            children[1],
+            Message::NewEpoch { epoch: 1 }
+        )]
+    );
+}
+
+/// Test if a child chain can withdraw its stake after the unstaking cooldown.
+#[test]
+fn withdraw_stake_after_cooldown() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+    let child = ChainId::root(1);
+
+    fund_application_account(&mut app, Amount::from_tokens(10));
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::from_tokens(10),
+        },
+    );
+
+    app.runtime.set_system_time(Timestamp::from(5_000));
+    receive_message(&mut app, child, Message::Unstake);
+
+    assert_eq!(
+        app.state
+            .children
+            .get(&child)
+            .blocking_wait()
+            .unwrap()
+            .and_then(|record| record.unstaking_since),
+        Some(Timestamp::from(5_000))
+    );
+
+    app.runtime.set_system_time(Timestamp::from(6_000));
+    receive_message(&mut app, child, Message::WithdrawStake);
+
+    assert_eq!(
+        *app.runtime.outgoing_transfers(),
+        HashMap::from([(Account::chain(child), Amount::from_tokens(10))])
+    );
+    assert_eq!(
+        app.state.children.get(&child).blocking_wait().unwrap(),
+        None
+    );
+}
+
+/// Test if a child chain can't withdraw its stake before the unstaking cooldown elapses.
+#[test]
+#[should_panic(expected = "can't withdraw its stake before the unstaking cooldown elapses")]
+fn withdraw_stake_before_cooldown() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+    let child = ChainId::root(1);
+
+    fund_application_account(&mut app, Amount::from_tokens(10));
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::from_tokens(10),
+        },
+    );
+
+    app.runtime.set_system_time(Timestamp::from(5_000));
+    receive_message(&mut app, child, Message::Unstake);
+
+    app.runtime.set_system_time(Timestamp::from(5_999));
+    receive_message(&mut app, child, Message::WithdrawStake);
+}
+
+/// Test if a former child chain can still be slashed before it withdraws its stake, and only
+/// withdraws what is left.
+#[test]
+fn slash_former_child() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+    let child = ChainId::root(1);
+
+    fund_application_account(&mut app, Amount::from_tokens(10));
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::from_tokens(10),
+        },
+    );
+    receive_message(&mut app, child, Message::Deregister);
+
+    app.execute_operation(Operation::Slash {
+        child,
+        amount: Amount::from_tokens(3),
+    })
+    .blocking_wait();
+
+    assert_eq!(
+        app.state
+            .former_children
+            .get(&child)
+            .blocking_wait()
+            .unwrap()
+            .map(|record| (record.stake, record.slashed)),
+        Some((Amount::from_tokens(7), Amount::from_tokens(3)))
+    );
+
+    app.runtime.set_system_time(Timestamp::from(1_000));
+    receive_message(&mut app, child, Message::WithdrawStake);
+
+    assert_eq!(
+        *app.runtime.outgoing_transfers(),
+        HashMap::from([(Account::chain(child), Amount::from_tokens(7))])
+    );
+}
+
+/// Test if slashing a child chain's stake prevents it from flushing more values.
+#[test]
+#[should_panic(expected = "has not staked the required amount")]
+fn slashed_child_can_not_flush() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+    let child = ChainId::root(1);
+
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::from_tokens(10),
+        },
+    );
+
+    app.execute_operation(Operation::Slash {
+        child,
+        amount: Amount::ONE,
+    })
+    .blocking_wait();
+
+    assert_eq!(
+        app.state.children.get(&child).blocking_wait().unwrap(),
+        Some(ChildRecord {
+            stake: Amount::from_tokens(9),
+            slashed: Amount::ONE,
+            unstaking_since: None,
+        })
+    );
+
+    receive_message(
+        &mut app,
+        child,
+        Message::Flush {
+            epoch: 0,
+            value: 5,
+            contributions: 1,
+            commitment: empty_commitment(),
+            replicas: Vec::new(),
+        },
+    );
+}
+
+/// Test if flushing without a configured parent causes the block to be rejected.
+#[test]
+#[should_panic(expected = "Can't flush if the chain is not connected to a parent chain")]
+fn flush_without_parent() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::Flush).blocking_wait();
+}
+
+/// Test if flushing values sends messages to the parent chain.
+#[proptest]
+fn flush_sends_messages(parent: ChainId, values_to_submit: Vec<Option<u32>>) {
+    let mut app = create_and_instantiate_app();
+    let mut accumulated = 0_u64;
+    let mut contributions = 0_u64;
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
+
+    for maybe_value in values_to_submit.into_iter().chain(None) {
+        match maybe_value {
+            Some(value) => {
+                app.execute_operation(Operation::Submit {
+                    metric: None,
+                    value: value.into(),
+                })
+                .blocking_wait();
+
+                accumulated += u64::from(value);
+                contributions += 1;
+            }
+            None => {
+                app.execute_operation(Operation::Flush).blocking_wait();
+
+                assert_eq!(
+                    mem::take(&mut *app.runtime.created_send_message_requests()),
+                    vec![SendMessageRequest {
+                        destination: Destination::Recipient(parent),
+                        authenticated: false,
+                        is_tracked: false,
+                        grant: Resources::default(),
+                        message: Message::Flush {
+                            epoch: 0,
+                            value: mem::take(&mut accumulated),
+                            contributions: mem::take(&mut contributions),
+                            commitment: empty_commitment(),
+                            replicas: Vec::new(),
+                        },
+                    }]
+                );
+            }
+        }
+    }
+}
+
+/// Test that value overflows are avoided by flushing.
+#[proptest]
+fn submit_operation_overflow_is_avoided_by_flushing(parent: ChainId) {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: u64::MAX,
+    })
+    .blocking_wait();
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.execute_operation(Operation::Flush).blocking_wait();
+
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 1,
+    })
+    .blocking_wait();
+
+    assert_eq!(*app.state.value.get(), 1);
+}
+
+/// Test if flushed values are accumulated.
+#[proptest]
+fn incoming_messages_are_accumulated(incoming_messages: Vec<u32>) {
+    let mut app = create_and_instantiate_app();
+
+    for &value in &incoming_messages {
+        receive_message(
+            &mut app,
+            ChainId::root(1),
+            Message::Flush {
+                epoch: 0,
+                value: value.into(),
+                contributions: 1,
+                commitment: empty_commitment(),
+                replicas: Vec::new(),
+            },
+        );
+    }
+
+    assert_eq!(
+        *app.state.value.get(),
+        incoming_messages.into_iter().map(u64::from).sum::<u64>()
+    );
+}
+
+/// Test if the block in which the value last changed is tracked as messages are processed.
+#[test]
+fn value_updates_are_tracked() {
+    let mut app = create_and_instantiate_app();
+
+    for (height, value) in [(3, 5), (4, 2)] {
+        app.runtime
+            .set_block_height(BlockHeight(height))
+            .set_system_time(Timestamp::from(1_000 * height));
+        receive_flush(&mut app, value);
+    }
+
+    assert_eq!(
+        *app.state.last_value_update.get(),
+        Some(ValueUpdate {
+            value: 7,
+            block_height: BlockHeight(4),
+            timestamp: Timestamp::from(4_000),
+        })
+    );
+}
+
+/// Test if flushed value overflows cause the block to be rejected.
+#[test]
+#[should_panic(expected = "attempt to add with overflow")]
+fn incoming_messages_overflow() {
+    let mut app = create_and_instantiate_app();
+
+    receive_flush(&mut app, u64::MAX);
+    receive_flush(&mut app, 1);
+}
+
+/// Test if flushed value does not overflow if it is flushed further upwards.
+#[proptest]
+fn incoming_messages_overflow_is_avoided_by_a_flush(parent: ChainId) {
+    let mut app = create_and_instantiate_app();
+
+    receive_flush(&mut app, u64::MAX);
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.execute_operation(Operation::Flush).blocking_wait();
+    receive_flush(&mut app, 1);
+
+    assert_eq!(*app.state.value.get(), 1);
+}
+
+/// Test if the contributions flushed by each child chain are tracked separately.
+#[test]
+fn child_contributions_are_tracked() {
+    let mut app = create_and_instantiate_app();
+    let first_child = ChainId::root(1);
+    let second_child = ChainId::root(2);
+
+    for (child, contributions) in [(first_child, 3), (second_child, 1), (first_child, 2)] {
+        receive_message(
+            &mut app,
+            child,
+            Message::Flush {
+                epoch: 0,
+                value: 1,
+                contributions,
+                commitment: empty_commitment(),
+                replicas: Vec::new(),
+            },
+        );
+    }
+
+    assert_eq!(*app.state.contributions.get(), 6);
+    assert_eq!(
+        app.state
+            .child_contributions
+            .get(&first_child)
+            .blocking_wait()
+            .unwrap(),
+        Some(5)
+    );
+    assert_eq!(
+        app.state
+            .child_contributions
+            .get(&second_child)
+            .blocking_wait()
+            .unwrap(),
+        Some(1)
+    );
+}
+
+/// Test if a snapshot of the value is recorded every time values from a child chain are merged.
+#[test]
+fn merged_values_are_recorded_in_history() {
+    let mut app = create_and_instantiate_app();
+    let first_child = ChainId::root(1);
+    let second_child = ChainId::root(2);
+
+    for (height, child, value) in [(0, first_child, 3), (1, second_child, 4)] {
+        app.runtime
+            .set_block_height(BlockHeight(height))
+            .set_system_time(Timestamp::from(1_000 * height));
+        receive_message(
+            &mut app,
+            child,
+            Message::Flush {
+                epoch: 0,
+                value,
+                contributions: 1,
+                commitment: empty_commitment(),
+                replicas: Vec::new(),
+            },
+        );
+    }
+
+    assert_eq!(
+        app.state.history.read(..).blocking_wait().unwrap(),
+        vec![
+            ValueSnapshot {
+                timestamp: Timestamp::from(0),
+                block_height: BlockHeight(0),
+                value: 3,
+                child: first_child,
+            },
+            ValueSnapshot {
+                timestamp: Timestamp::from(1_000),
+                block_height: BlockHeight(1),
+                value: 7,
+                child: second_child,
+            },
+        ]
+    );
+}
+
+/// Test if snapshots of the value are only recorded after the configured history interval.
+#[test]
+fn history_snapshots_are_throttled() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        history_interval: TimeDelta::from_millis(1),
+        ..DepinDemoParameters::default()
+    });
+
+    for timestamp in [0, 500, 1_000, 1_999, 2_000] {
+        app.runtime.set_system_time(Timestamp::from(timestamp));
+        receive_flush(&mut app, 1);
+    }
+
+    let snapshots = app.state.history.read(..).blocking_wait().unwrap();
+
+    assert_eq!(
+        snapshots
+            .iter()
+            .map(|snapshot| (snapshot.timestamp, snapshot.value))
+            .collect::<Vec<_>>(),
+        vec![
+            (Timestamp::from(0), 1),
+            (Timestamp::from(1_000), 3),
+            (Timestamp::from(2_000), 5),
+        ]
+    );
+}
+
+/// Test if merged values are rolled up in time buckets of every resolution.
+#[test]
+fn merged_values_are_rolled_up() {
+    let mut app = create_and_instantiate_app();
+
+    for (seconds, value) in [(0, 1), (30, 2), (61, 3), (3_601, 4)] {
+        app.runtime
+            .set_system_time(Timestamp::from(seconds * 1_000_000));
+        receive_flush(&mut app, value);
+    }
+
+    let minute = |minutes: u64| Timestamp::from(minutes * 60_000_000);
+
+    assert_eq!(
+        rollups(&app, Resolution::Minute),
+        vec![
+            Rollup {
+                start: minute(0),
+                merges: 2,
+                merged_value: 3,
+                min_value: 1,
+                max_value: 3,
+                last_value: 3,
+            },
+            Rollup::new(minute(1), 3, 6),
+            Rollup::new(minute(60), 4, 10),
+        ]
+    );
+    assert_eq!(
+        rollups(&app, Resolution::Hour),
+        vec![
+            Rollup {
+                start: minute(0),
+                merges: 3,
+                merged_value: 6,
+                min_value: 1,
+                max_value: 6,
+                last_value: 6,
+            },
+            Rollup::new(minute(60), 4, 10),
+        ]
+    );
+    assert_eq!(
+        rollups(&app, Resolution::Day),
+        vec![Rollup {
+            start: minute(0),
+            merges: 4,
+            merged_value: 10,
+            min_value: 1,
+            max_value: 10,
+            last_value: 10,
+        }]
+    );
+}
+
+/// Test if rollups are pruned once their time buckets end before the configured retention.
+#[test]
+fn rollups_are_pruned_after_retention() {
+    let mut app = create_and_instantiate_app_with_parameters(DepinDemoParameters {
+        rollup_retention: BTreeMap::from([(Resolution::Minute, TimeDelta::from_secs(120))]),
+        ..DepinDemoParameters::default()
+    });
+
+    for minutes in 0..5 {
+        app.runtime
+            .set_system_time(Timestamp::from(minutes * 60_000_000));
+        receive_flush(&mut app, 1);
+    }
+
+    let rollup_starts = |resolution| {
+        rollups(&app, resolution)
+            .into_iter()
+            .map(|rollup| rollup.start.micros() / 60_000_000)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(rollup_starts(Resolution::Minute), vec![2, 3, 4]);
+    assert_eq!(rollup_starts(Resolution::Hour), vec![0]);
+}
+
+/// Test if closing an epoch on the root chain archives its aggregate, resets it and notifies the
+/// child chains.
+#[test]
+fn close_epoch() {
+    let mut app = create_and_instantiate_app();
+    let child = ChainId::root(1);
+
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::ZERO,
+        },
+    );
+    receive_flush(&mut app, 5);
+    app.runtime.set_system_time(Timestamp::from(1_000));
+
+    app.execute_operation(Operation::CloseEpoch).blocking_wait();
+
+    assert_eq!(*app.state.value.get(), 0);
+    assert_eq!(*app.state.contributions.get(), 0);
+    assert_eq!(*app.state.epoch.get(), 1);
+    assert_eq!(
+        app.state.archived_epochs.get(&0).blocking_wait().unwrap(),
+        Some(ArchivedEpoch {
+            value: 5,
+            contributions: 1,
+            closed_at: Timestamp::from(1_000),
+            late_flushes: 0,
+        })
+    );
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(child, Message::NewEpoch { epoch: 1 })]
+    );
+}
+
+/// Test if the aggregate of a closed epoch is delivered to a consumer application on the root
+/// chain.
+#[test]
+fn close_epoch_calls_local_consumer() {
+    let mut app = create_and_instantiate_app();
+    let root_chain = ChainId::root(0);
+    let consumer = caller_application_id();
+    let calls = record_application_calls(&mut app);
+
+    app.runtime.set_chain_id(root_chain);
+    app.execute_operation(Operation::SetConsumer {
+        consumer: Some(AggregateConsumer {
+            application_id: consumer,
+            chain_id: root_chain,
+        }),
+    })
+    .blocking_wait();
+    receive_flush(&mut app, 5);
+    app.runtime.set_system_time(Timestamp::from(1_000));
+
+    app.execute_operation(Operation::CloseEpoch).blocking_wait();
+
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![(
+            consumer,
+            FinalizedAggregate {
+                root_chain,
+                epoch: 0,
+                value: 5,
+                contributions: 1,
+                closed_at: Timestamp::from(1_000),
+            },
+        )]
+    );
+}
+
+/// Test if the aggregate of a closed epoch is sent to a consumer application on another chain.
+#[test]
+fn close_epoch_sends_to_remote_consumer() {
+    let mut app = create_and_instantiate_app();
+    let root_chain = ChainId::root(0);
+    let consumer = AggregateConsumer {
+        application_id: caller_application_id(),
+        chain_id: ChainId::root(5),
+    };
+
+    app.runtime.set_chain_id(root_chain);
+    app.execute_operation(Operation::SetConsumer {
+        consumer: Some(consumer),
+    })
+    .blocking_wait();
+    receive_flush(&mut app, 5);
+
+    app.execute_operation(Operation::CloseEpoch).blocking_wait();
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            consumer.chain_id,
+            Message::DeliverAggregate {
+                consumer: consumer.application_id,
+                aggregate: FinalizedAggregate {
+                    root_chain,
+                    epoch: 0,
+                    value: 5,
+                    contributions: 1,
+                    closed_at: Timestamp::from(0),
+                },
+            },
+        )]
+    );
+}
+
+/// Test if aggregates received from another chain are delivered to the consumer application,
+/// labeled with the chain that sent them.
+#[test]
+fn delivered_aggregates_call_consumer() {
+    let mut app = create_and_instantiate_app();
+    let root_chain = ChainId::root(0);
+    let consumer = caller_application_id();
+    let calls = record_application_calls(&mut app);
+    let aggregate = FinalizedAggregate {
+        root_chain: ChainId::root(9),
+        epoch: 2,
+        value: 5,
+        contributions: 1,
+        closed_at: Timestamp::from(1_000),
+    };
+
+    receive_message(
+        &mut app,
+        root_chain,
+        Message::DeliverAggregate {
+            consumer,
+            aggregate: aggregate.clone(),
+        },
+    );
+
+    assert_eq!(
+        *calls.lock().unwrap(),
+        vec![(
+            consumer,
+            FinalizedAggregate {
+                root_chain,
+                ..aggregate
+            },
+        )]
+    );
+}
+
+/// Test if a chain that is not the root chain can't configure a consumer application.
+#[test]
+#[should_panic(expected = "Only the root chain finalizes aggregates for a consumer")]
+fn set_consumer_on_non_root_chain() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::SetConsumer {
+        consumer: Some(AggregateConsumer {
+            application_id: caller_application_id(),
+            chain_id: ChainId::root(1),
+        }),
+    })
+    .blocking_wait();
+}
+
+/// Test if closing an epoch on a chain that is not the root chain causes the block to be
+/// rejected.
+#[test]
+#[should_panic(expected = "Only the root chain can close epochs")]
+fn close_epoch_on_non_root_chain() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::CloseEpoch).blocking_wait();
+}
+
+/// Test if values flushed for a closed epoch are merged into its archived aggregate.
+#[test]
+fn late_flushes_are_archived() {
+    let mut app = create_and_instantiate_app();
+    let child = ChainId::root(1);
+
+    receive_flush(&mut app, 5);
+    app.execute_operation(Operation::CloseEpoch).blocking_wait();
+
+    for epoch in [0, 1] {
+        receive_message(
+            &mut app,
+            child,
+            Message::Flush {
+                epoch,
+                value: 2,
+                contributions: 1,
+                commitment: empty_commitment(),
+                replicas: Vec::new(),
+            },
+        );
+    }
+
+    assert_eq!(*app.state.value.get(), 2);
+    assert_eq!(
+        app.state.archived_epochs.get(&0).blocking_wait().unwrap(),
+        Some(ArchivedEpoch {
+            value: 7,
+            contributions: 2,
+            closed_at: Timestamp::from(0),
+            late_flushes: 1,
+        })
+    );
+}
+
+/// Test if values flushed for an epoch that has not started cause the block to be rejected.
+#[test]
+#[should_panic(expected = "which has not started")]
+fn flush_from_future_epoch() {
+    let mut app = create_and_instantiate_app();
+
+    receive_message(
+        &mut app,
+        ChainId::root(1),
+        Message::Flush {
+            epoch: 1,
+            value: 2,
+            contributions: 1,
+            commitment: empty_commitment(),
+            replicas: Vec::new(),
+        },
+    );
+}
+
+/// Test if a new epoch flushes the pending values of the previous epoch and is propagated to the
+/// child chains.
+#[test]
+fn new_epoch_is_propagated() {
+    let mut app = create_and_instantiate_app();
+    let parent = ChainId::root(1);
+    let child = ChainId::root(2);
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    receive_message(
+        &mut app,
+        child,
+        Message::Register {
+            stake: Amount::ZERO,
+        },
+    );
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 4,
+    })
+    .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
+
+    receive_message(&mut app, parent, Message::NewEpoch { epoch: 1 });
+
+    assert_eq!(*app.state.epoch.get(), 1);
+    assert_eq!(*app.state.value.get(), 0);
+    assert_eq!(
+        mem::take(&mut *app.runtime.created_send_message_requests()),
+        vec![
+            outgoing_message(
+                parent,
+                Message::Flush {
+                    epoch: 0,
+                    value: 4,
+                    contributions: 1,
+                    commitment: empty_commitment(),
+                    replicas: Vec::new(),
+                },
+            ),
+            outgoing_message(child, Message::NewEpoch { epoch: 1 }),
+        ]
+    );
+
+    receive_message(
+        &mut app,
+        child,
+        Message::Flush {
+            epoch: 0,
+            value: 3,
+            contributions: 1,
+            commitment: empty_commitment(),
+            replicas: Vec::new(),
+        },
+    );
+
+    let mut contribution_tree = ContributionTree::default();
+    contribution_tree.push(&ContributionLeaf {
+        child,
+        value: 3,
+        contributions: 1,
+        commitment: empty_commitment(),
+    });
+
+    assert_eq!(*app.state.value.get(), 0);
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            parent,
+            Message::Flush {
+                epoch: 0,
+                value: 3,
+                contributions: 1,
+                commitment: contribution_tree.root(),
+                replicas: Vec::new(),
+            },
+        )]
+    );
+}
+
+/// Test if flushes from child chains are committed to in the contribution tree of their epoch,
+/// whose root is sent with the next flush.
+#[test]
+fn flushes_are_committed_to() {
+    let mut app = create_and_instantiate_app();
+    let parent = ChainId::root(1);
+    let leaves = [
+        ContributionLeaf {
+            child: ChainId::root(2),
+            value: 3,
+            contributions: 2,
+            commitment: empty_commitment(),
+        },
+        ContributionLeaf {
+            child: ChainId::root(3),
+            value: 4,
+            contributions: 1,
+            commitment: CryptoHash::test_hash("grandchild contributions"),
+        },
+    ];
+    let mut contribution_tree = ContributionTree::default();
+
+    for leaf in &leaves {
+        receive_message(
+            &mut app,
+            leaf.child,
+            Message::Flush {
+                epoch: 0,
+                value: leaf.value,
+                contributions: leaf.contributions,
+                commitment: leaf.commitment,
+                replicas: Vec::new(),
+            },
+        );
+        contribution_tree.push(leaf);
+    }
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.runtime.created_send_message_requests().clear();
+    app.execute_operation(Operation::Flush).blocking_wait();
+
+    assert_eq!(
+        app.state
+            .contribution_trees
+            .get(&0)
+            .blocking_wait()
+            .unwrap(),
+        Some(contribution_tree.clone())
+    );
+    assert_eq!(
+        app.state
+            .contribution_leaves
+            .try_load_entry(&0)
+            .blocking_wait()
+            .unwrap()
+            .expect("Contribution leaves should be stored")
+            .read(..)
+            .blocking_wait()
+            .unwrap(),
+        leaves
+    );
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            parent,
+            Message::Flush {
+                epoch: 0,
+                value: 7,
+                contributions: 3,
+                commitment: contribution_tree.root(),
+                replicas: Vec::new(),
+            },
+        )]
+    );
+    assert_eq!(
+        *app.state.last_flush.get(),
+        Some(FlushRecord {
+            epoch: 0,
+            value: 7,
+            contributions: 3,
+            commitment: contribution_tree.root(),
+            block_height: BlockHeight(0),
+            timestamp: Timestamp::from(0),
+        })
+    );
+}
+
+/// Test if chains aggregating through streams flush without a parent chain.
+#[test]
+fn flush_to_stream() {
+    let mut app = create_and_instantiate_app_with_parameters(streams_parameters());
+
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 5,
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::Flush).blocking_wait();
+
+    assert_eq!(*app.state.value.get(), 0);
+    assert!(app.runtime.created_send_message_requests().is_empty());
+    assert_eq!(
+        *app.state.last_flush.get(),
+        Some(FlushRecord {
+            epoch: 0,
+            value: 5,
+            contributions: 1,
+            commitment: empty_commitment(),
+            block_height: BlockHeight(0),
+            timestamp: Timestamp::from(0),
+        })
+    );
+}
+
+/// Test if chains aggregating through streams can't connect to a parent chain.
+#[test]
+#[should_panic(expected = "Chains publish their flushes to event streams")]
+fn connect_to_parent_with_streams() {
+    let mut app = create_and_instantiate_app_with_parameters(streams_parameters());
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+}
+
+/// Test if the flushes published by subscribed child chains are merged once.
+#[test]
+fn child_streams_are_processed() {
+    let mut app = create_and_instantiate_app_with_parameters(streams_parameters());
+    let child = ChainId::root(2);
+    let flushes = [(3, 2), (4, 1), (10, 5)];
+
+    for (index, (value, contributions)) in flushes.into_iter().enumerate() {
+        publish_flush(&mut app, child, index as u32, value, contributions);
+    }
+
+    app.execute_operation(Operation::SubscribeToChild { child })
+        .blocking_wait();
+    app.execute_operation(Operation::ProcessStreams {
+        updates: vec![StreamUpdate {
+            chain_id: child,
+            next_index: 2,
+        }],
+    })
+    .blocking_wait();
+
+    assert_eq!(*app.state.value.get(), 7);
+    assert_eq!(*app.state.contributions.get(), 3);
+    assert_eq!(
+        app.state
+            .stream_subscriptions
+            .get(&child)
+            .blocking_wait()
+            .unwrap(),
+        Some(2)
+    );
+
+    app.execute_operation(Operation::ProcessStreams {
+        updates: vec![StreamUpdate {
+            chain_id: child,
+            next_index: 3,
+        }],
+    })
+    .blocking_wait();
+
+    assert_eq!(*app.state.value.get(), 17);
+    assert_eq!(*app.state.contributions.get(), 8);
+    assert_eq!(
+        app.state
+            .contribution_trees
+            .get(&0)
+            .blocking_wait()
+            .unwrap()
+            .expect("Contribution tree should be stored")
+            .leaf_count(),
+        3
+    );
+}
+
+/// Test if the streams of child chains that are not subscribed to are not processed.
+#[test]
+#[should_panic(expected = "Chain is not subscribed to child chain")]
+fn unsubscribed_child_streams_are_rejected() {
+    let mut app = create_and_instantiate_app_with_parameters(streams_parameters());
+    let child = ChainId::root(2);
+
+    publish_flush(&mut app, child, 0, 3, 1);
+
+    app.execute_operation(Operation::SubscribeToChild { child })
+        .blocking_wait();
+    app.execute_operation(Operation::UnsubscribeFromChild { child })
+        .blocking_wait();
+    app.execute_operation(Operation::ProcessStreams {
+        updates: vec![StreamUpdate {
+            chain_id: child,
+            next_index: 1,
+        }],
+    })
+    .blocking_wait();
+}
+
+/// Test if chains that shard the aggregation send the values of each metric to the reducer
+/// chain it is assigned to when they flush.
+#[test]
+fn sharded_flush_sends_metrics_to_reducers() {
+    let parameters = sharded_parameters(vec![ChainId::root(1), ChainId::root(2)]);
+    let mut app = create_and_instantiate_app_with_parameters(parameters.clone());
+
+    for (metric, value) in [
+        (Some("temperature"), 20),
+        (Some("humidity"), 40),
+        (Some("temperature"), 22),
+        (None, 5),
+    ] {
+        app.execute_operation(Operation::Submit {
+            metric: metric.map(str::to_owned),
+            value,
+        })
+        .blocking_wait();
+    }
+
+    app.execute_operation(Operation::Flush).blocking_wait();
+
+    let mut expected_values = BTreeMap::<ChainId, Vec<MetricValue>>::new();
+
+    for (metric, value, contributions) in [("", 5, 1), ("humidity", 40, 1), ("temperature", 42, 2)]
+    {
+        let reducer = parameters
+            .reducer_chain(metric)
+            .expect("Metrics should be assigned to a reducer chain");
+
+        expected_values
+            .entry(reducer)
+            .or_default()
+            .push(MetricValue {
+                metric: metric.to_owned(),
+                value,
+                contributions,
+            });
+    }
+
+    let sent_values = app
+        .runtime
+        .created_send_message_requests()
+        .iter()
+        .map(|request| {
+            let Destination::Recipient(reducer) = request.destination else {
+                panic!("Metric values should be sent to a single reducer chain");
+            };
+            let Message::ReduceMetrics { values } = &request.message else {
+                panic!("Unexpected message {:?}", request.message);
+            };
+            let mut values = values.clone();
+
+            values.sort_by(|left, right| left.metric.cmp(&right.metric));
+            (reducer, values)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    assert_eq!(sent_values, expected_values);
+    assert_eq!(*app.state.value.get(), 0);
+    assert_eq!(
+        app.state
+            .pending_metric_values
+            .count()
+            .blocking_wait()
+            .expect("Failed to load pending metric values"),
+        0
+    );
+}
+
+/// Test if reducer chains reduce the values of the metrics assigned to them.
+#[test]
+fn reducer_merges_metric_values() {
+    let mut app =
+        create_and_instantiate_app_with_parameters(sharded_parameters(vec![ChainId::root(0)]));
+    let temperature = |value, contributions| MetricValue {
+        metric: "temperature".to_owned(),
+        value,
+        contributions,
+    };
+    let humidity = MetricValue {
+        metric: "humidity".to_owned(),
+        value: 10,
+        contributions: 1,
+    };
+
+    app.runtime.set_chain_id(ChainId::root(0));
+    receive_message(
+        &mut app,
+        ChainId::root(5),
+        Message::ReduceMetrics {
+            values: vec![temperature(42, 2), humidity.clone()],
+        },
+    );
+    receive_message(
+        &mut app,
+        ChainId::root(6),
+        Message::ReduceMetrics {
+            values: vec![temperature(8, 1)],
+        },
+    );
+
+    assert_eq!(*app.state.value.get(), 60);
+    assert_eq!(*app.state.contributions.get(), 4);
+    assert_eq!(
+        app.state
+            .reduced_metric_values
+            .index_values()
+            .blocking_wait()
+            .expect("Failed to load reduced metric values"),
+        vec![
+            ("humidity".to_owned(), humidity),
+            ("temperature".to_owned(), temperature(50, 3)),
+        ]
+    );
+}
+
+/// Test if values of metrics assigned to another reducer chain cause the block to be rejected.
+#[test]
+#[should_panic(expected = "is not reduced by this chain")]
+fn metric_for_other_reducer() {
+    let mut app =
+        create_and_instantiate_app_with_parameters(sharded_parameters(vec![ChainId::root(1)]));
+
+    app.runtime.set_chain_id(ChainId::root(0));
+    receive_message(
+        &mut app,
+        ChainId::root(5),
+        Message::ReduceMetrics {
+            values: vec![MetricValue {
+                metric: "temperature".to_owned(),
+                value: 42,
+                contributions: 1,
+            }],
+        },
+    );
+}
+
+/// Test if reducer chains report the values of their shard to the chain that created the
+/// application, which keeps the last report of each reducer chain.
+#[test]
+fn shard_is_reported_to_creator_chain() {
+    let reducer = ChainId::root(1);
+    let creator = ChainId::root(0);
+    let mut app = create_and_instantiate_app_with_parameters(sharded_parameters(vec![reducer]));
+    let values = vec![MetricValue {
+        metric: "temperature".to_owned(),
+        value: 42,
+        contributions: 2,
+    }];
+
+    app.runtime
+        .set_chain_id(reducer)
+        .set_application_creator_chain_id(creator);
+    receive_message(
+        &mut app,
+        ChainId::root(5),
+        Message::ReduceMetrics {
+            values: values.clone(),
+        },
+    );
+    app.execute_operation(Operation::ReportShard)
+        .blocking_wait();
+
+    assert_eq!(
+        *app.runtime.created_send_message_requests(),
+        vec![outgoing_message(
+            creator,
+            Message::ReportShard {
+                values: values.clone(),
+            },
+        )]
+    );
+
+    app.runtime.set_chain_id(creator);
+    app.runtime.set_system_time(Timestamp::from(1_000));
+    receive_message(
+        &mut app,
+        reducer,
+        Message::ReportShard {
+            values: values.clone(),
+        },
+    );
+
+    assert_eq!(
+        app.state
+            .shard_reports
+            .get(&reducer)
+            .blocking_wait()
+            .unwrap(),
+        Some(ShardReport {
+            values,
+            reported_at: Timestamp::from(1_000),
+        })
+    );
+}
+
+/// Test if shard reports from chains that are not reducer chains cause the block to be rejected.
+#[test]
+#[should_panic(expected = "Only reducer chains can report the values of their shard")]
+fn shard_report_from_other_chain() {
+    let mut app =
+        create_and_instantiate_app_with_parameters(sharded_parameters(vec![ChainId::root(1)]));
+
+    app.runtime
+        .set_chain_id(ChainId::root(0))
+        .set_application_creator_chain_id(ChainId::root(0));
+    receive_message(
+        &mut app,
+        ChainId::root(2),
+        Message::ReportShard { values: Vec::new() },
+    );
+}
+
+/// Test if authorized applications can submit readings and read the local aggregate.
+#[test]
+fn authorized_application_calls() {
+    let mut app = create_and_instantiate_app();
+    let caller = caller_application_id();
+
+    app.execute_operation(Operation::AuthorizeApplication {
+        application_id: caller,
+        permissions: ApplicationPermissions {
+            submit: true,
+            read_aggregate: true,
+        },
+    })
+    .blocking_wait();
+
+    app.runtime.set_authenticated_caller_id(caller);
+
+    let submit_response = app
+        .execute_operation(Operation::Submit {
+            metric: None,
+            value: 7,
+        })
+        .blocking_wait();
+    let read_response = app
+        .execute_operation(Operation::ReadAggregate)
+        .blocking_wait();
+
+    assert_eq!(submit_response, OperationResponse::Ok);
+    assert_eq!(
+        read_response,
+        OperationResponse::Aggregate(LocalAggregate {
+            epoch: 0,
+            value: 7,
+            contributions: 1,
+        })
+    );
+}
+
+/// Test if applications that are not authorized can't submit readings.
+#[test]
+#[should_panic(expected = "is not authorized to execute Submit")]
+fn unauthorized_application_submit() {
+    let mut app = create_and_instantiate_app();
+    let caller = caller_application_id();
+
+    app.execute_operation(Operation::AuthorizeApplication {
+        application_id: caller,
+        permissions: ApplicationPermissions {
+            submit: false,
+            read_aggregate: true,
+        },
+    })
+    .blocking_wait();
+
+    app.runtime.set_authenticated_caller_id(caller);
+    app.execute_operation(Operation::Submit {
+        metric: None,
+        value: 7,
+    })
+    .blocking_wait();
+}
+
+/// Test if revoked applications can't read the local aggregate.
+#[test]
+#[should_panic(expected = "is not authorized to execute ReadAggregate")]
+fn revoked_application_read() {
+    let mut app = create_and_instantiate_app();
+    let caller = caller_application_id();
+
+    app.execute_operation(Operation::AuthorizeApplication {
+        application_id: caller,
+        permissions: ApplicationPermissions {
+            submit: true,
+            read_aggregate: true,
+        },
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::RevokeApplication {
+        application_id: caller,
+    })
+    .blocking_wait();
+
+    app.runtime.set_authenticated_caller_id(caller);
+    app.execute_operation(Operation::ReadAggregate)
+        .blocking_wait();
+}
+
+/// Test if applications can't execute operations other than submitting and reading, even if
+/// they are authorized.
+#[test]
+#[should_panic(expected = "is not authorized to execute Flush")]
+fn application_flush() {
+    let mut app = create_and_instantiate_app();
+    let caller = caller_application_id();
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::AuthorizeApplication {
+        application_id: caller,
+        permissions: ApplicationPermissions {
+            submit: true,
+            read_aggregate: true,
+        },
+    })
+    .blocking_wait();
+
+    app.runtime.set_authenticated_caller_id(caller);
+    app.execute_operation(Operation::Flush).blocking_wait();
+}
+
+/// Test if unauthenticated calls from other applications are rejected, since their caller can't be
+/// checked.
+#[test]
+#[should_panic(
+    expected = "Operations must be signed by a chain owner or called by an authenticated"
+)]
+fn unauthenticated_application_call() {
+    let mut app = create_and_instantiate_app();
+
+    app.runtime.set_authenticated_signer(None);
+    app.execute_operation(Operation::AuthorizeApplication {
+        application_id: caller_application_id(),
+        permissions: ApplicationPermissions {
+            submit: true,
+            read_aggregate: true,
+        },
+    })
+    .blocking_wait();
+}
+
+/// Test if only the values submitted by registered devices count towards their rewards.
+#[test]
+fn device_contributions_are_tracked() {
+    let mut app = create_and_instantiate_app();
+    let device_owner = AccountOwner::from(CryptoHash::test_hash("device"));
+    let unregistered_owner = AccountOwner::from(CryptoHash::test_hash("unregistered"));
+    let reward_account = Account::chain(ChainId::root(1));
+
+    app.execute_operation(Operation::RegisterDevice {
+        owner: device_owner,
+        reward_account,
+    })
+    .blocking_wait();
+
+    for signer in [device_owner, unregistered_owner, device_owner] {
+        app.runtime.set_authenticated_signer(signer);
+        app.execute_operation(Operation::Submit {
+            metric: None,
+            value: 1,
+        })
+        .blocking_wait();
+    }
+
+    assert_eq!(*app.state.contributions.get(), 3);
+    assert_eq!(
+        app.state
+            .devices
+            .get(&device_owner)
+            .blocking_wait()
+            .unwrap(),
+        Some(Device {
+            reward_account,
+            contributions: 2,
+        })
+    );
+    assert_eq!(
+        app.state
+            .devices
+            .get(&unregistered_owner)
+            .blocking_wait()
+            .unwrap(),
+        None
+    );
+}
+
+/// Test if the root chain distributes rewards proportionally to the contributions of its child
+/// chains and devices.
+#[test]
+fn distribute_rewards() {
+    let mut app = create_and_instantiate_app();
+    let application_account = fund_application_account(&mut app, Amount::from_tokens(10));
+    let first_child = ChainId::root(1);
+    let second_child = ChainId::root(2);
+    let device_owner = AccountOwner::from(CryptoHash::test_hash("device"));
+    let device_account = Account::chain(ChainId::root(3));
+
+    receive_message(
+        &mut app,
+        first_child,
+        Message::Flush {
+            epoch: 0,
+            value: 10,
+            contributions: 3,
+            commitment: empty_commitment(),
+            replicas: Vec::new(),
+        },
+    );
+    receive_message(
+        &mut app,
+        second_child,
+        Message::Flush {
+            epoch: 0,
+            value: 20,
+            contributions: 1,
+            commitment: empty_commitment(),
+            replicas: Vec::new(),
+        },
+    );
+
+    app.execute_operation(Operation::RegisterDevice {
+        owner: device_owner,
+        reward_account: device_account,
+    })
+    .blocking_wait();
+    app.runtime.set_authenticated_signer(device_owner);
+
+    for value in 1..=4 {
+        app.execute_operation(Operation::Submit {
+            metric: None,
+            value,
+        })
+        .blocking_wait();
+    }
+
+    app.execute_operation(Operation::DistributeRewards {
+        amount: Amount::from_tokens(8),
+    })
+    .blocking_wait();
+
+    let first_child_account = Account::new(first_child, application_account);
+    let second_child_account = Account::new(second_child, application_account);
+
+    assert_eq!(
+        *app.runtime.outgoing_transfers(),
+        HashMap::from([
+            (first_child_account, Amount::from_tokens(3)),
+            (second_child_account, Amount::from_tokens(1)),
+            (device_account, Amount::from_tokens(4)),
+        ])
+    );
+    assert_eq!(
+        app.runtime.owner_balance(application_account),
+        Amount::from_tokens(2)
+    );
+
+    let messages = mem::take(&mut *app.runtime.created_send_message_requests());
+    assert_eq!(messages.len(), 2);
+    for (child, tokens) in [(first_child, 3), (second_child, 1)] {
+        assert!(messages.contains(&SendMessageRequest {
+            destination: Destination::Recipient(child),
+            authenticated: false,
+            is_tracked: false,
+            grant: Resources::default(),
+            message: Message::Reward {
+                epoch: 1,
+                amount: Amount::from_tokens(tokens),
+            },
+        }));
+    }
+
+    let settlement = app
+        .state
+        .reward_settlements
+        .get(0)
+        .blocking_wait()
+        .unwrap()
+        .expect("Missing reward settlement");
+    assert_eq!(settlement.epoch, 1);
+    assert_eq!(settlement.amount, Amount::from_tokens(8));
+    assert_eq!(settlement.contributions, 8);
+    assert_eq!(settlement.payouts.len(), 3);
+    assert!(settlement.payouts.contains(&RewardPayout {
+        recipient: device_account,
+        contributions: 4,
+        amount: Amount::from_tokens(4),
+    }));
+
+    assert_eq!(*app.state.reward_epoch.get(), 1);
+    assert_eq!(
+        app.state
+            .child_contributions
+            .count()
+            .blocking_wait()
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        app.state
+            .devices
+            .get(&device_owner)
+            .blocking_wait()
+            .unwrap()
+            .map(|device| device.contributions),
+        Some(0)
+    );
+}
+
+/// Test if only the root chain can start distributing rewards.
+#[test]
+#[should_panic(expected = "Only the root chain can start distributing rewards")]
+fn distribute_rewards_on_non_root_chain() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+    app.execute_operation(Operation::DistributeRewards {
+        amount: Amount::ONE,
+    })
+    .blocking_wait();
+}
+
+/// Test if rewards received from the parent chain are distributed to the devices.
+#[test]
+fn rewards_from_parent_are_distributed() {
+    let mut app = create_and_instantiate_app();
+    let parent = ChainId::root(1);
+    let devices = [
+        (
+            AccountOwner::from(CryptoHash::test_hash("first device")),
+            Account::chain(ChainId::root(2)),
+        ),
+        (
+            AccountOwner::from(CryptoHash::test_hash("second device")),
+            Account::chain(ChainId::root(3)),
+        ),
+    ];
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+
+    for (owner, reward_account) in devices {
+        app.execute_operation(Operation::RegisterDevice {
+            owner,
+            reward_account,
+        })
+        .blocking_wait();
+        app.runtime.set_authenticated_signer(owner);
+        app.execute_operation(Operation::Submit {
+            metric: None,
+            value: 1,
+        })
+        .blocking_wait();
+    }
+
+    // The parent chain transfers the reward before sending the message
+    fund_application_account(&mut app, Amount::from_tokens(4));
+    receive_message(
+        &mut app,
+        parent,
+        Message::Reward {
+            epoch: 5,
+            amount: Amount::from_tokens(4),
+        },
+    );
+
+    assert_eq!(
+        *app.runtime.outgoing_transfers(),
+        HashMap::from([
+            (devices[0].1, Amount::from_tokens(2)),
+            (devices[1].1, Amount::from_tokens(2)),
+        ])
+    );
+    assert_eq!(*app.state.reward_epoch.get(), 5);
+    assert_eq!(app.state.reward_settlements.count(), 1);
+}
+
+/// Test if rewards can't be received from chains other than the parent chain.
+#[test]
+#[should_panic(expected = "Rewards can only be received from the parent chain")]
+fn rewards_from_other_chains_are_rejected() {
+    let mut app = create_and_instantiate_app();
+
+    app.execute_operation(Operation::ConnectToParent {
+        parent: ChainId::root(1),
+    })
+    .blocking_wait();
+
+    receive_message(
+        &mut app,
+        ChainId::root(2),
+        Message::Reward {
+            epoch: 1,
+            amount: Amount::ONE,
+        },
+    );
+}
+
+/// Test if rewards paid by a former parent chain before it learned that this chain left are
+/// returned to it.
+#[test]
+fn rewards_from_former_parent_are_returned() {
+    let mut app = create_and_instantiate_app();
+    let parent = ChainId::root(1);
+
+    app.execute_operation(Operation::ConnectToParent { parent })
+        .blocking_wait();
+    app.execute_operation(Operation::Disconnect).blocking_wait();
+
+    let application_account = fund_application_account(&mut app, Amount::ONE);
+
+    receive_message(
+        &mut app,
+        parent,
+        Message::Reward {
+            epoch: 1,
+            amount: Amount::ONE,
+        },
+    );
+
+    assert_eq!(
+        *app.runtime.outgoing_transfers(),
+        HashMap::from([(Account::new(parent, application_account), Amount::ONE)])
+    );
+    assert_eq!(*app.state.reward_epoch.get(), 0);
+    assert_eq!(app.state.reward_settlements.count(), 0);
+}
+
+/// Test if rewards can't be paid from the stake held for child chains.
+#[test]
+#[should_panic(expected = "Rewards can't be paid from the stake held in the application's account")]
+fn rewards_can_not_spend_held_stake() {
+    let mut app = create_and_instantiate_app_with_parameters(staking_parameters());
+
+    fund_application_account(&mut app, Amount::from_tokens(12));
+    receive_message(
+        &mut app,
+        ChainId::root(1),
+        Message::Register {
+            stake: Amount::from_tokens(10),
+        },
+    );
+
+    assert_eq!(*app.state.held_stake.get(), Amount::from_tokens(10));
+
+    app.execute_operation(Operation::DistributeRewards {
+        amount: Amount::from_tokens(5),
+    })
+    .blocking_wait();
+}
+
+/// Test if reward shares never add up to more than the distributed amount.
+#[proptest]
+fn reward_shares_do_not_exceed_amount(amount: u128, contributions: Vec<u64>) {
+    let amount = Amount::from_attos(amount);
+    let total_contributions = contributions
+        .iter()
+        .map(|&contributions| u128::from(contributions))
+        .sum::<u128>();
+
+    // Contributions are counted as `u64`, so larger totals can't happen
+    if let Ok(total_contributions) = u64::try_from(total_contributions) {
+        let distributed = contributions
+            .iter()
+            .map(|&contributions| {
+                u128::from(proportional_share(
+                    amount,
+                    contributions,
+                    total_contributions,
+                ))
+            })
+            .sum::<u128>();
+
+        assert!(distributed <= u128::from(amount));
+    }
+}
+
+/// Creates a [`DepinDemoContract`] instance ready to be tested.
+fn create_and_instantiate_app() -> DepinDemoContract {
+    create_and_instantiate_app_with_parameters(DepinDemoParameters::default())
+}
+
+/// Creates a [`DepinDemoContract`] instance configured with the `parameters`.
+fn create_and_instantiate_app_with_parameters(
+    parameters: DepinDemoParameters,
+) -> DepinDemoContract {
+    let mut contract = create_app_on_new_chain(parameters);
+
+    contract.instantiate(ChainSetup::default()).blocking_wait();
+
+    contract
+}
+
+/// Creates a [`DepinDemoContract`] instance configured with the `parameters`, as if the
+/// application was used for the first time on a chain other than the one it was created on.
+fn create_app_on_new_chain(parameters: DepinDemoParameters) -> DepinDemoContract {
+    let runtime = ContractRuntime::new()
+        .with_application_parameters(parameters)
+        .with_authenticated_signer(Some(chain_owner()))
+        .with_authenticated_caller_id(None)
+        .with_block_height(BlockHeight(0))
+        .with_system_time(Timestamp::from(0));
+    DepinDemoContract {
+        state: DepinDemoState::load(runtime.root_view_storage_context())
+            .blocking_wait()
+            .expect("Failed to read from mock key value store"),
+        runtime,
+    }
+}
+
+/// Returns the owner of the chain, who signs the blocks in the tests.
+fn chain_owner() -> AccountOwner {
+    AccountOwner::from(CryptoHash::test_hash("chain owner"))
+}
+
+/// Returns the ID of another application that calls this one.
+fn caller_application_id() -> ApplicationId {
+    ApplicationId::new(CryptoHash::test_hash("device manager"))
+}
+
+/// Records the authenticated calls made to other applications, answering them as an
+/// [`depin_demo::AggregateConsumerAbi`] application.
+fn record_application_calls(
+    app: &mut DepinDemoContract,
+) -> Arc<Mutex<Vec<(ApplicationId, FinalizedAggregate)>>> {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let recorded_calls = calls.clone();
+
+    app.runtime
+        .set_call_application_handler(move |authenticated, application_id, call| {
+            assert!(
+                authenticated,
+                "Consumers should be called with authentication"
+            );
+
+            let aggregate = bcs::from_bytes(&call).expect("Failed to deserialize call to consumer");
+            recorded_calls
+                .lock()
+                .unwrap()
+                .push((application_id, aggregate));
+
+            bcs::to_bytes(&()).expect("Failed to serialize consumer response")
+        });
+
+    calls
+}
+
+/// Creates [`DepinDemoParameters`] for chains that aggregate through event streams.
+fn streams_parameters() -> DepinDemoParameters {
+    DepinDemoParameters {
+        aggregation_mode: AggregationMode::Streams,
+        ..DepinDemoParameters::default()
+    }
+}
+
+/// Creates [`DepinDemoParameters`] for chains that shard the aggregation between the
+/// `reducer_chains`.
+fn sharded_parameters(reducer_chains: Vec<ChainId>) -> DepinDemoParameters {
+    DepinDemoParameters {
+        aggregation_mode: AggregationMode::Sharded,
+        reducer_chains,
+        ..DepinDemoParameters::default()
+    }
+}
+
+/// Adds a flush of `value` and `contributions` at the `index` of a `child` chain's flushes
+/// stream.
+fn publish_flush(
+    app: &mut DepinDemoContract,
+    child: ChainId,
+    index: u32,
+    value: u64,
+    contributions: u64,
+) {
+    let event = AggregationEvent::FlushSent {
+        parent: None,
+        epoch: 0,
+        value,
+        contributions,
+        commitment: empty_commitment(),
+    };
+
+    app.runtime.add_event(
+        child,
+        FLUSHES_STREAM.into(),
+        index,
+        &bcs::to_bytes(&event).expect("Failed to serialize event"),
+    );
+}
+
+/// Creates [`DepinDemoParameters`] limiting the `temperature` metric to values from 10 to 20.
+fn temperature_range_parameters(out_of_range_policy: OutOfRangePolicy) -> DepinDemoParameters {
+    DepinDemoParameters {
+        value_ranges: BTreeMap::from([("temperature".to_owned(), ValueRange { min: 10, max: 20 })]),
+        out_of_range_policy,
+        ..DepinDemoParameters::default()
+    }
+}
+
+/// Creates a [`TreeConfig`] that allows values from 10 to 20 for the `temperature` metric, using
+/// the `out_of_range_policy` for values outside of that range.
+fn temperature_config(out_of_range_policy: OutOfRangePolicy) -> TreeConfig {
+    TreeConfig {
+        value_ranges: vec![MetricRange {
+            metric: "temperature".to_owned(),
+            min: 10,
+            max: 20,
+        }],
+        out_of_range_policy,
+    }
+}
+
+/// Creates a [`Reading`] of the `temperature` metric with the `value` measured at the
+/// `timestamp`.
+fn temperature_reading(value: u64, timestamp: u64) -> Reading {
+    Reading {
+        metric: Some("temperature".to_owned()),
+        value,
+        timestamp: Timestamp::from(timestamp),
+    }
+}
+
+/// Creates [`DepinDemoParameters`] requiring child chains to stake 10 tokens, with a cooldown of
+/// one millisecond to withdraw the stake.
+fn staking_parameters() -> DepinDemoParameters {
+    DepinDemoParameters {
+        registration_stake: Amount::from_tokens(10),
+        unstake_cooldown: TimeDelta::from_millis(1),
+        ..DepinDemoParameters::default()
+    }
+}
+
+/// Registers `count` child chains with the `app` running on the first root chain.
+///
+/// Returns the child chains in the order they are kept in the registry, and clears the messages
+/// sent to them.
+fn register_children(app: &mut DepinDemoContract, count: u32) -> Vec<ChainId> {
+    app.runtime.set_chain_id(ChainId::root(0));
+
+    for index in 1..=count {
+        receive_message(
+            app,
+            ChainId::root(index),
+            Message::Register {
+                stake: Amount::ZERO,
+            },
+        );
+    }
+
+    app.runtime.created_send_message_requests().clear();
+
+    app.state
+        .children
+        .indices()
+        .blocking_wait()
+        .expect("Failed to load child chain registry")
+}
+
+/// Executes a `message` in the `app` as if it was sent by the `sender` chain.
+fn receive_message(app: &mut DepinDemoContract, sender: ChainId, message: Message) {
+    app.runtime.set_message_id(MessageId {
+        chain_id: sender,
+        height: BlockHeight(0),
+        index: 0,
+    });
+
+    app.execute_message(message).blocking_wait();
+}
+
+/// Executes a message flushing a single contribution with the `value` from a child chain.
+fn receive_flush(app: &mut DepinDemoContract, value: u64) {
+    receive_message(
+        app,
+        ChainId::root(1),
+        Message::Flush {
+            epoch: 0,
+            value,
+            contributions: 1,
+            commitment: empty_commitment(),
+            replicas: Vec::new(),
+        },
+    );
+}
+
+/// Returns the root of an empty contribution tree, committed to by chains without child chains.
+fn empty_commitment() -> CryptoHash {
+    ContributionTree::default().root()
+}
+
+/// Returns all the rollups with the `resolution` kept by the `app`, including the open one.
+fn rollups(app: &DepinDemoContract, resolution: Resolution) -> Vec<Rollup> {
+    let mut rollups = match app
+        .state
+        .closed_rollups
+        .try_load_entry(&resolution)
+        .blocking_wait()
+        .expect("Failed to load rollups")
+    {
+        Some(closed_rollups) => closed_rollups
+            .elements()
+            .blocking_wait()
+            .expect("Failed to load rollups"),
+        None => Vec::new(),
+    };
+
+    rollups.extend(
+        app.state
+            .open_rollups
+            .get(&resolution)
+            .blocking_wait()
+            .expect("Failed to load rollups"),
+    );
+
+    rollups
+}
+
+/// Creates the [`SendMessageRequest`] expected for sending the `message` to the `destination`
+/// chain.
+fn outgoing_message(destination: ChainId, message: Message) -> SendMessageRequest<Message> {
+    SendMessageRequest {
+        destination: Destination::Recipient(destination),
+        authenticated: false,
+        is_tracked: false,
+        grant: Resources::default(),
+        message,
+    }
+}
+
+/// Configures the `app` with an application ID whose account on the chain holds the `balance`.
+///
+/// Returns the [`AccountOwner`] of the application's account.
+fn fund_application_account(app: &mut DepinDemoContract, balance: Amount) -> AccountOwner {
+    let application_id =
+        ApplicationId::new(CryptoHash::test_hash("depin-demo")).with_abi::<DepinDemoAbi>();
+    let application_account = AccountOwner::from(application_id);
+
+    app.runtime
+        .set_application_id(application_id)
+        .set_owner_balance(application_account, balance);
+
+    application_account
 }
@@ -0,0 +1,84 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+use linera_sdk::linera_base_types::{ChainId, CryptoHash};
+use test_strategy::proptest;
+
+use super::{verify_contribution_proof, ContributionLeaf, ContributionProof, ContributionTree};
+
+/// Test if the proofs created for every leaf are verified against the root of the tree.
+#[proptest]
+fn proofs_are_verified(#[strategy(1..40_usize)] leaf_count: usize) {
+    let leaves = create_leaves(leaf_count);
+    let tree = create_tree(&leaves);
+
+    assert_eq!(tree.leaf_count(), leaf_count as u64);
+
+    for index in 0..leaf_count {
+        let proof = ContributionProof::new(&leaves, index).expect("Leaf should exist");
+
+        assert!(verify_contribution_proof(&proof, tree.root()));
+    }
+}
+
+/// Test if proofs for tampered leaves are rejected.
+#[proptest]
+fn tampered_proofs_are_rejected(
+    #[strategy(1..40_usize)] leaf_count: usize,
+    #[strategy(0..#leaf_count)] index: usize,
+) {
+    let leaves = create_leaves(leaf_count);
+    let root = create_tree(&leaves).root();
+    let proof = ContributionProof::new(&leaves, index).expect("Leaf should exist");
+
+    let mut tampered_value = proof.clone();
+    tampered_value.leaf.value += 1;
+
+    let mut tampered_index = proof.clone();
+    tampered_index.index ^= 1;
+
+    assert!(!verify_contribution_proof(&tampered_value, root));
+    assert!(!verify_contribution_proof(&tampered_index, root));
+}
+
+/// Test if proofs can't be created for leaves that are not in the tree.
+#[test]
+fn missing_leaf_has_no_proof() {
+    let leaves = create_leaves(3);
+
+    assert_eq!(ContributionProof::new(&leaves, 3), None);
+}
+
+/// Test if the root of an empty tree changes when a leaf is added.
+#[test]
+fn pushing_a_leaf_changes_the_root() {
+    let mut tree = ContributionTree::default();
+    let empty_root = tree.root();
+
+    tree.push(&create_leaves(1)[0]);
+
+    assert_ne!(tree.root(), empty_root);
+}
+
+/// Creates `count` distinct [`ContributionLeaf`]s.
+fn create_leaves(count: usize) -> Vec<ContributionLeaf> {
+    (0..count)
+        .map(|index| ContributionLeaf {
+            child: ChainId::root(index as u32 % 3),
+            value: index as u64,
+            contributions: 1,
+            commitment: CryptoHash::test_hash(format!("child commitment {index}")),
+        })
+        .collect()
+}
+
+/// Creates a [`ContributionTree`] with the `leaves`.
+fn create_tree(leaves: &[ContributionLeaf]) -> ContributionTree {
+    let mut tree = ContributionTree::default();
+
+    for leaf in leaves {
+        tree.push(leaf);
+    }
+
+    tree
+}
@@ -1,14 +1,37 @@
 // Copyright (c) Zefchain Labs, Inc.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
 
 use async_graphql::{Request, Response, Value};
-use linera_sdk::{linera_base_types::ChainId, util::BlockingWait, views::View, Service, ServiceRuntime};
+use depin_demo::{
+    merkle::{verify_contribution_proof, ContributionLeaf, ContributionProof, ContributionTree},
+    AggregateConsumer, AggregationMode, ApplicationPermissions, DepinDemoParameters, FlushPolicy,
+    MetricRange, MetricValue, NodeRole, OutOfRangePolicy, ReplicatedFlush, Resolution, TreeConfig,
+    ValueRange,
+};
+use linera_sdk::{
+    linera_base_types::{
+        Account, AccountOwner, Amount, ApplicationId, BlockHeight, ChainId, CryptoHash, TimeDelta,
+        Timestamp,
+    },
+    util::BlockingWait,
+    views::View,
+    Service, ServiceRuntime,
+};
 use serde_json::json;
 use test_strategy::proptest;
 
-use super::{DepinDemoService, DepinDemoState};
+use super::{
+    state::{
+        ChildRecord, Device, FlushLoad, FlushRecord, QuarantinedReading, Rollup, ShardReport,
+        UnacknowledgedFlush, ValueSnapshot, ValueUpdate,
+    },
+    DepinDemoService, DepinDemoState,
+};
 
 /// Test reading the value in the state.
 #[test]
@@ -26,6 +49,40 @@ fn value_query() {
     assert_eq!(response, expected)
 }
 
+/// Test following the updates of the value by the block heights in which they happened.
+#[test]
+fn value_update_query() {
+    let mut service = create_service();
+
+    service
+        .state
+        .edit()
+        .last_value_update
+        .set(Some(ValueUpdate {
+            value: 60,
+            block_height: BlockHeight(5),
+            timestamp: Timestamp::from(1_000),
+        }));
+
+    let request = Request::new(
+        "{ \
+            latest: valueUpdate { value blockHeight } \
+            new: valueUpdate(after: 4) { value blockHeight timestamp } \
+            seen: valueUpdate(after: 5) { value } \
+        }",
+    );
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "latest": { "value": 60, "blockHeight": 5 },
+        "new": { "value": 60, "blockHeight": 5, "timestamp": 1_000 },
+        "seen": null,
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
 /// Test if it's possible to read the value in the state.
 #[test]
 fn empty_parent_query() {
@@ -55,6 +112,815 @@ fn parent_query(parent: ChainId) {
     assert_eq!(response, expected)
 }
 
+/// Test reading the values that are pending since the last flush to the parent chain.
+#[proptest]
+fn flush_status_query(parent: ChainId, commitment: CryptoHash) {
+    let mut service = create_service();
+    let state = service.state.edit();
+
+    state.parent.set(Some(parent));
+    state.value.set(7);
+    state.contributions.set(2);
+    state.last_flush.set(Some(FlushRecord {
+        epoch: 1,
+        value: 5,
+        contributions: 3,
+        commitment,
+        block_height: BlockHeight(4),
+        timestamp: Timestamp::from(1_000),
+    }));
+
+    let request = Request::new(
+        "{ \
+            isRoot \
+            pendingSinceLastFlush { value contributions } \
+            lastFlush { epoch value contributions commitment blockHeight timestamp } \
+        }",
+    );
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "isRoot": false,
+        "pendingSinceLastFlush": { "value": 7, "contributions": 2 },
+        "lastFlush": {
+            "epoch": 1,
+            "value": 5,
+            "contributions": 3,
+            "commitment": commitment.to_string(),
+            "blockHeight": 4,
+            "timestamp": 1_000,
+        },
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading the registered child chains with their stakes and contributions.
+#[proptest]
+fn children_query(child: ChainId) {
+    let mut service = create_service();
+    let state = service.state.edit();
+
+    state
+        .children
+        .insert(
+            &child,
+            ChildRecord {
+                stake: Amount::from_tokens(10),
+                slashed: Amount::ONE,
+                unstaking_since: None,
+            },
+        )
+        .unwrap();
+    state.child_contributions.insert(&child, 4).unwrap();
+
+    let request =
+        Request::new("{ isRoot children { chainId stake slashed unstakingSince contributions } }");
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "isRoot": true,
+        "children": [{
+            "chainId": child.to_string(),
+            "stake": Amount::from_tokens(10).to_string(),
+            "slashed": Amount::ONE.to_string(),
+            "unstakingSince": null,
+            "contributions": 4,
+        }],
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading the child chains that moved to another parent chain.
+#[proptest]
+fn former_children_query(child: ChainId) {
+    let mut service = create_service();
+
+    service
+        .state
+        .edit()
+        .former_children
+        .insert(
+            &child,
+            ChildRecord {
+                stake: Amount::from_tokens(10),
+                slashed: Amount::ZERO,
+                unstaking_since: Some(Timestamp::from(5_000)),
+            },
+        )
+        .expect("Failed to update former child chains");
+
+    let request = Request::new("{ formerChildren { chainId stake slashed unstakingSince } }");
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "formerChildren": [{
+            "chainId": child,
+            "stake": Amount::from_tokens(10).to_string(),
+            "slashed": Amount::ZERO.to_string(),
+            "unstakingSince": 5_000,
+        }],
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading the load of an aggregator chain and the child chains moving away from it.
+#[proptest]
+fn load_queries(child: ChainId, parent: ChainId) {
+    let mut service = create_service();
+
+    {
+        let state = service.state.edit();
+
+        state.flush_load.set(FlushLoad {
+            window_start: Timestamp::from(1_000),
+            flushes: 7,
+        });
+        state
+            .moving_children
+            .insert(&child, parent)
+            .expect("Failed to update moving child chains");
+    }
+
+    let request = Request::new(
+        "{ \
+            flushLoad { windowStart flushes } \
+            movingChildren { chainId parent } \
+        }",
+    );
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "flushLoad": { "windowStart": 1_000, "flushes": 7 },
+        "movingChildren": [{ "chainId": child, "parent": parent }],
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading the edge chains opened for new devices.
+#[proptest]
+fn opened_chains_query(chain_id: ChainId, owner: AccountOwner) {
+    let mut service = create_service();
+
+    service
+        .state
+        .edit()
+        .opened_chains
+        .insert(&chain_id, owner)
+        .expect("Failed to update opened chains");
+
+    let request = Request::new("{ openedChains { chainId owner } }");
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "openedChains": [{ "chainId": chain_id, "owner": owner }],
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading the backup parent chains and the flushes the parent chain has not acknowledged.
+#[proptest]
+fn replication_query(chain_id: ChainId, parent: ChainId, backup: ChainId) {
+    let mut service = create_service();
+
+    {
+        let state = service.state.edit();
+
+        state.backup_parents.set(vec![backup]);
+        state
+            .unacknowledged_flushes
+            .insert(
+                &4,
+                UnacknowledgedFlush {
+                    parent,
+                    epoch: 1,
+                    value: 7,
+                    contributions: 2,
+                    commitment: ContributionTree::default().root(),
+                    replicas: vec![ReplicatedFlush {
+                        child: chain_id,
+                        sequence: 4,
+                        value: 7,
+                        contributions: 2,
+                    }],
+                },
+            )
+            .expect("Failed to update unacknowledged flushes");
+    }
+
+    let request = Request::new(
+        "{ backupParents \
+            unacknowledgedFlushes { sequence parent epoch value replicas { child sequence } } }",
+    );
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "backupParents": [backup],
+        "unacknowledgedFlushes": [{
+            "sequence": 4,
+            "parent": parent,
+            "epoch": 1,
+            "value": 7,
+            "replicas": [{ "child": chain_id, "sequence": 4 }],
+        }],
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading the values of each metric that are pending, reduced by the chain, and combined
+/// from the reports of the reducer chains.
+#[test]
+fn metric_values_query() {
+    let mut service = create_service();
+    let metric_value = |metric: &str, value, contributions| MetricValue {
+        metric: metric.to_owned(),
+        value,
+        contributions,
+    };
+
+    {
+        let state = service.state.edit();
+
+        state
+            .pending_metric_values
+            .insert("humidity", metric_value("humidity", 40, 1))
+            .expect("Failed to update pending metric values");
+        state
+            .reduced_metric_values
+            .insert("temperature", metric_value("temperature", 30, 2))
+            .expect("Failed to update reduced metric values");
+
+        for (reducer, values) in [
+            (ChainId::root(1), vec![metric_value("temperature", 30, 2)]),
+            (
+                ChainId::root(2),
+                vec![
+                    metric_value("humidity", 50, 1),
+                    metric_value("temperature", 5, 1),
+                ],
+            ),
+        ] {
+            state
+                .shard_reports
+                .insert(
+                    &reducer,
+                    ShardReport {
+                        values,
+                        reported_at: Timestamp::from(1_000),
+                    },
+                )
+                .expect("Failed to update shard reports");
+        }
+    }
+
+    let request = Request::new(
+        "{ \
+            pendingMetricValues { metric value } \
+            reducedMetricValues { metric value contributions } \
+            shardReports { reducer values { metric } } \
+            combinedMetricValues { metric value contributions } \
+            combinedMetricValue(metric: \"temperature\") { value } \
+        }",
+    );
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "pendingMetricValues": [{ "metric": "humidity", "value": 40 }],
+        "reducedMetricValues": [{ "metric": "temperature", "value": 30, "contributions": 2 }],
+        "shardReports": [
+            {
+                "reducer": ChainId::root(2),
+                "values": [{ "metric": "humidity" }, { "metric": "temperature" }],
+            },
+            { "reducer": ChainId::root(1), "values": [{ "metric": "temperature" }] },
+        ],
+        "combinedMetricValues": [
+            { "metric": "humidity", "value": 50, "contributions": 1 },
+            { "metric": "temperature", "value": 35, "contributions": 3 },
+        ],
+        "combinedMetricValue": { "value": 35 },
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test finding the reducer chain that a metric is assigned to.
+#[test]
+fn reducer_chain_query() {
+    let reducer_chains = vec![ChainId::root(1), ChainId::root(2), ChainId::root(3)];
+    let parameters = DepinDemoParameters {
+        reducer_chains: reducer_chains.clone(),
+        ..DepinDemoParameters::default()
+    };
+    let expected_reducer = parameters
+        .reducer_chain("temperature")
+        .expect("Metrics should be assigned to a reducer chain");
+    let service = create_service_with_parameters(parameters);
+
+    let request = Request::new("{ reducerChain(metric: \"temperature\") }");
+    let response = service.handle_query(request).blocking_wait();
+
+    assert!(reducer_chains.contains(&expected_reducer));
+    assert_eq!(
+        response.data,
+        Value::from_json(json!({ "reducerChain": expected_reducer })).unwrap()
+    );
+}
+
+/// Test reading the chains that joined the tree with their assigned parent chains.
+#[proptest]
+fn joined_chains_query(root: ChainId, first_child: ChainId, second_child: ChainId) {
+    let mut service = create_service();
+
+    {
+        let state = service.state.edit();
+
+        for (child, parent) in [(first_child, root), (second_child, first_child)] {
+            state.joined_chains.push(child);
+            state
+                .assigned_parents
+                .insert(&child, parent)
+                .expect("Failed to update assigned parent chains");
+        }
+    }
+
+    let request = Request::new("{ joinedChains { chainId parent } }");
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "joinedChains": [
+            { "chainId": first_child, "parent": root },
+            { "chainId": second_child, "parent": first_child },
+        ],
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading the child chains that retired.
+#[proptest]
+fn retired_children_query(child: ChainId) {
+    let mut service = create_service();
+
+    service
+        .state
+        .edit()
+        .retired_children
+        .insert(&child, Timestamp::from(5_000))
+        .expect("Failed to update retired child chains");
+    service.state.edit().retired.set(true);
+
+    let request = Request::new("{ isRetired retiredChildren { chainId retiredAt } }");
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "isRetired": true,
+        "retiredChildren": [{ "chainId": child, "retiredAt": 5_000 }],
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading the child chains whose flushes streams are merged.
+#[proptest]
+fn stream_subscriptions_query(child: ChainId) {
+    let mut service = create_service();
+
+    service
+        .state
+        .edit()
+        .stream_subscriptions
+        .insert(&child, 4)
+        .expect("Failed to subscribe to child chain");
+
+    let request = Request::new("{ streamSubscriptions { chainId nextIndex } }");
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "streamSubscriptions": [{ "chainId": child, "nextIndex": 4 }],
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading the applications that are allowed to call this application.
+#[test]
+fn authorized_applications_query() {
+    let mut service = create_service();
+    let application_id = ApplicationId::new(CryptoHash::test_hash("device manager"));
+
+    service
+        .state
+        .edit()
+        .authorized_applications
+        .insert(
+            &application_id,
+            ApplicationPermissions {
+                submit: true,
+                read_aggregate: false,
+            },
+        )
+        .expect("Failed to authorize application");
+
+    let request = Request::new(
+        "{ authorizedApplications { applicationId permissions { submit readAggregate } } }",
+    );
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "authorizedApplications": [{
+            "applicationId": application_id,
+            "permissions": { "submit": true, "readAggregate": false },
+        }],
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading the application that receives the finalized aggregates.
+#[proptest]
+fn consumer_query(chain_id: ChainId) {
+    let mut service = create_service();
+    let application_id = ApplicationId::new(CryptoHash::test_hash("settlement"));
+
+    service.state.edit().consumer.set(Some(AggregateConsumer {
+        application_id,
+        chain_id,
+    }));
+
+    let request = Request::new("{ consumer { applicationId chainId } }");
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "consumer": { "applicationId": application_id, "chainId": chain_id },
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading the tree configuration, which falls back to the application parameters until the
+/// root chain broadcasts one.
+#[test]
+fn tree_config_query() {
+    let mut service =
+        create_service_with_parameters(temperature_range_parameters(OutOfRangePolicy::Reject));
+    let request =
+        "{ configVersion treeConfig { valueRanges { metric min max } outOfRangePolicy } }";
+
+    let response = service.handle_query(Request::new(request)).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "configVersion": 0,
+        "treeConfig": {
+            "valueRanges": [{ "metric": "temperature", "min": 10, "max": 20 }],
+            "outOfRangePolicy": "REJECT",
+        },
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected);
+
+    {
+        let state = service.state.edit();
+
+        state.config_version.set(3);
+        state.tree_config.set(Some(TreeConfig {
+            value_ranges: vec![],
+            out_of_range_policy: OutOfRangePolicy::Quarantine,
+        }));
+    }
+
+    let response = service.handle_query(Request::new(request)).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "configVersion": 3,
+        "treeConfig": { "valueRanges": [], "outOfRangePolicy": "QUARANTINE" },
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading how the chain was set up.
+#[test]
+fn setup_queries() {
+    let mut service = create_service();
+
+    {
+        let state = service.state.edit();
+
+        state.initialized.set(true);
+        state.flush_policy.set(FlushPolicy {
+            max_pending_contributions: 10,
+        });
+    }
+
+    let request = Request::new("{ isInitialized flushPolicy { maxPendingContributions } }");
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "isInitialized": true,
+        "flushPolicy": { "maxPendingContributions": 10 },
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test reading the role of the chain.
+#[test]
+fn role_query() {
+    let mut service = create_service();
+    let request = "{ role }";
+
+    let response = service.handle_query(Request::new(request)).blocking_wait();
+    assert_eq!(
+        response.data,
+        Value::from_json(json!({"role": null})).unwrap()
+    );
+
+    service.state.edit().role.set(Some(NodeRole::Aggregator));
+
+    let response = service.handle_query(Request::new(request)).blocking_wait();
+    assert_eq!(
+        response.data,
+        Value::from_json(json!({"role": "AGGREGATOR"})).unwrap()
+    );
+}
+
+/// Test if whether the chain is the root follows its role, and only chains that send their
+/// flushes to parent chains are the root without one.
+#[test]
+fn is_root_query_follows_role() {
+    let mut service = create_service_with_parameters(DepinDemoParameters {
+        aggregation_mode: AggregationMode::Streams,
+        ..DepinDemoParameters::default()
+    });
+    let request = "{ isRoot }";
+
+    let response = service.handle_query(Request::new(request)).blocking_wait();
+    assert_eq!(
+        response.data,
+        Value::from_json(json!({"isRoot": false})).unwrap()
+    );
+
+    service.state.edit().role.set(Some(NodeRole::Root));
+
+    let response = service.handle_query(Request::new(request)).blocking_wait();
+    assert_eq!(
+        response.data,
+        Value::from_json(json!({"isRoot": true})).unwrap()
+    );
+
+    let mut service = create_service();
+    service.state.edit().role.set(Some(NodeRole::Aggregator));
+
+    let response = service.handle_query(Request::new(request)).blocking_wait();
+    assert_eq!(
+        response.data,
+        Value::from_json(json!({"isRoot": false})).unwrap()
+    );
+}
+
+/// Test reading the application parameters.
+#[test]
+fn config_query() {
+    let parameters = DepinDemoParameters {
+        value_ranges: BTreeMap::from([("temperature".to_owned(), ValueRange { min: 10, max: 20 })]),
+        out_of_range_policy: OutOfRangePolicy::Quarantine,
+        registration_stake: Amount::from_tokens(10),
+        unstake_cooldown: TimeDelta::from_secs(60),
+        history_interval: TimeDelta::from_secs(1),
+        rollup_retention: BTreeMap::from([(Resolution::Minute, TimeDelta::from_secs(3_600))]),
+        value_thresholds: BTreeSet::from([100, 1_000]),
+        aggregation_mode: AggregationMode::Streams,
+        fan_out: 8,
+        aggregator_balance: Amount::from_tokens(5),
+        fan_in_budget: 16,
+        flush_budget: 100,
+        load_window: TimeDelta::from_secs(10),
+        max_unacknowledged_flushes: 3,
+        reducer_chains: vec![ChainId::root(1)],
+    };
+    let service = create_service_with_parameters(parameters);
+
+    let request = Request::new(
+        "{ config { \
+            valueRanges { metric min max } \
+            outOfRangePolicy \
+            registrationStake \
+            unstakeCooldown \
+            historyInterval \
+            rollupRetention { resolution retention } \
+            valueThresholds \
+            aggregationMode \
+            fanOut \
+            aggregatorBalance \
+            fanInBudget \
+            flushBudget \
+            loadWindow \
+            maxUnacknowledgedFlushes \
+            reducerChains \
+        } }",
+    );
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "config": {
+            "valueRanges": [{ "metric": "temperature", "min": 10, "max": 20 }],
+            "outOfRangePolicy": "QUARANTINE",
+            "registrationStake": Amount::from_tokens(10).to_string(),
+            "unstakeCooldown": 60_000_000,
+            "historyInterval": 1_000_000,
+            "rollupRetention": [{ "resolution": "MINUTE", "retention": 3_600_000_000_u64 }],
+            "valueThresholds": [100, 1_000],
+            "aggregationMode": "STREAMS",
+            "fanOut": 8,
+            "aggregatorBalance": "5.",
+            "fanInBudget": 16,
+            "flushBudget": 100,
+            "loadWindow": 10_000_000,
+            "maxUnacknowledgedFlushes": 3,
+            "reducerChains": [ChainId::root(1)],
+        },
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test paging through the quarantined out-of-range values.
+#[test]
+fn quarantine_query() {
+    let mut service = create_service();
+
+    for value in [1, 2, 300] {
+        service.state.edit().quarantine.push(QuarantinedReading {
+            metric: "temperature".to_owned(),
+            value,
+            allowed_range: ValueRange { min: 10, max: 20 },
+            block_height: BlockHeight(value),
+            timestamp: Timestamp::from(value),
+        });
+    }
+
+    let request = Request::new("{ quarantine(offset: 1, limit: 5) { value blockHeight } }");
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Value::from_json(json!({
+        "quarantine": [
+            { "value": 2, "blockHeight": 2 },
+            { "value": 300, "blockHeight": 300 },
+        ],
+    }))
+    .unwrap();
+
+    assert_eq!(response.data, expected)
+}
+
+/// Test paging through the value snapshots taken in a time range.
+#[test]
+fn history_query() {
+    let mut service = create_service();
+
+    for (index, timestamp) in [10, 20, 30, 40, 50].into_iter().enumerate() {
+        service.state.edit().history.push(ValueSnapshot {
+            timestamp: Timestamp::from(timestamp),
+            block_height: BlockHeight(index as u64),
+            value: timestamp,
+            child: ChainId::root(1),
+        });
+    }
+
+    let request = Request::new(
+        "{ history(start: 15, end: 50, offset: 1, limit: 5) { timestamp value blockHeight } }",
+    );
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Response::new(
+        Value::from_json(json!({
+            "history": [
+                { "timestamp": 30, "value": 30, "blockHeight": 2 },
+                { "timestamp": 40, "value": 40, "blockHeight": 3 },
+            ],
+        }))
+        .unwrap(),
+    );
+
+    assert_eq!(response, expected)
+}
+
+/// Test reading the rollups of a resolution in a time range.
+#[test]
+fn rollups_query() {
+    let mut service = create_service();
+    let minute = |minutes: u64| Timestamp::from(minutes * 60_000_000);
+    let state = service.state.edit();
+
+    for (minutes, value) in [(0, 1), (1, 3)] {
+        state
+            .closed_rollups
+            .load_entry_mut(&Resolution::Minute)
+            .blocking_wait()
+            .unwrap()
+            .push_back(rollup(minute(minutes), value));
+    }
+
+    state
+        .open_rollups
+        .insert(&Resolution::Minute, rollup(minute(2), 6))
+        .unwrap();
+    state
+        .open_rollups
+        .insert(&Resolution::Hour, rollup(minute(0), 10))
+        .unwrap();
+
+    let request = Request::new(format!(
+        "{{ rollups(resolution: MINUTE, start: {}) {{ start merges lastValue }} }}",
+        minute(1).micros()
+    ));
+    let response = service.handle_query(request).blocking_wait();
+
+    let expected = Response::new(
+        Value::from_json(json!({
+            "rollups": [
+                { "start": minute(1).micros(), "merges": 1, "lastValue": 3 },
+                { "start": minute(2).micros(), "merges": 1, "lastValue": 6 },
+            ],
+        }))
+        .unwrap(),
+    );
+
+    assert_eq!(response, expected)
+}
+
+/// Test reading the contribution root of an epoch and a proof of inclusion of a child chain's
+/// flush.
+#[proptest]
+fn contribution_proof_query(child: ChainId, other_child: ChainId) {
+    let mut service = create_service();
+    let state = service.state.edit();
+    let mut tree = ContributionTree::default();
+    let mut leaves = Vec::new();
+
+    for (value, child) in [(1, child), (2, other_child), (3, child)] {
+        let leaf = ContributionLeaf {
+            child,
+            value,
+            contributions: 1,
+            commitment: CryptoHash::test_hash("child commitment"),
+        };
+        tree.push(&leaf);
+        state
+            .contribution_leaves
+            .load_entry_mut(&0)
+            .blocking_wait()
+            .unwrap()
+            .push(leaf.clone());
+        leaves.push(leaf);
+    }
+
+    state.contribution_trees.insert(&0, tree.clone()).unwrap();
+
+    let request = Request::new(format!(
+        "{{ \
+            contributionRoot(epoch: 0) \
+            contributionProof(epoch: 0, child: \"{child}\", sequence: 1) {{ \
+                index siblings leaf {{ child value contributions commitment }} \
+            }} \
+        }}"
+    ));
+    let response = service.handle_query(request).blocking_wait();
+    let data = response.data.into_json().unwrap();
+
+    let root = serde_json::from_value::<CryptoHash>(data["contributionRoot"].clone()).unwrap();
+    let proof =
+        serde_json::from_value::<ContributionProof>(data["contributionProof"].clone()).unwrap();
+    let expected_index = if child == other_child { 1 } else { 2 };
+
+    assert_eq!(root, tree.root());
+    assert_eq!(proof.index, expected_index as u64);
+    assert_eq!(proof.leaf, leaves[expected_index]);
+    assert!(verify_contribution_proof(&proof, root));
+}
+
 /// Test creating a connect to parent operation.
 #[proptest]
 fn connect_to_parent_mutation(parent: ChainId) {
@@ -71,10 +937,198 @@ fn connect_to_parent_mutation(parent: ChainId) {
 #[proptest]
 fn submit_mutation(value: u64) {
     let service = create_service();
-    let request = Request::new(format!("mutation {{ submit(value: \"{value}\") }}"));
+    let request = Request::new(format!(
+        "mutation {{ submit(reading: {{ value: \"{value}\" }}) }}"
+    ));
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"submit": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating a submit operation with the value written as a number.
+#[test]
+fn submit_number_mutation() {
+    let service = create_service();
+    let request = Request::new("mutation { submit(reading: { value: 42 }) }");
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"submit": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test if a value that isn't an unsigned integer is refused.
+#[test]
+fn submit_invalid_number_mutation() {
+    let service = create_service();
+    let request = Request::new("mutation { submit(reading: { value: \"-1\" }) }");
+    let response = service.handle_query(request).blocking_wait();
+
+    assert_eq!(response.errors.len(), 1);
+    assert!(response.errors[0]
+        .message
+        .contains("Expected an unsigned integer of up to 128 bits, found \"-1\""));
+}
+
+/// Test creating a single operation to submit a batch of readings.
+#[test]
+fn submit_batch_mutation() {
+    let service = create_service();
+    let request = Request::new(
+        "mutation { submitBatch(readings: [\
+            { metric: \"temperature\", value: 15, timestamp: 1000 }, \
+            { value: \"3\", timestamp: 2000 }\
+        ]) }",
+    );
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"submitBatch": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test if an invalid reading in a batch is reported with its index.
+#[test]
+fn submit_batch_out_of_range_mutation() {
+    let service =
+        create_service_with_parameters(temperature_range_parameters(OutOfRangePolicy::Reject));
+    let request = Request::new(
+        "mutation { submitBatch(readings: [\
+            { metric: \"temperature\", value: 15, timestamp: 1000 }, \
+            { metric: \"temperature\", value: 21, timestamp: 2000 }\
+        ]) }",
+    );
+    let response = service.handle_query(request).blocking_wait();
+
+    assert_eq!(error_code(&response), Some("OUT_OF_RANGE"));
+    assert_eq!(
+        response.errors[0]
+            .extensions
+            .as_ref()
+            .and_then(|extensions| extensions.get("index")),
+        Some(&Value::from(1))
+    );
+}
+
+/// Test creating a submit operation for a specific metric.
+#[proptest]
+fn submit_metric_mutation(value: u64) {
+    let service = create_service();
+    let request = Request::new(format!(
+        "mutation {{ submit(reading: {{ value: \"{value}\", metric: \"temperature\" }}) }}"
+    ));
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"submit": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test if values outside of their metric's range are refused with an error code.
+#[test]
+fn submit_out_of_range_mutation() {
+    let service =
+        create_service_with_parameters(temperature_range_parameters(OutOfRangePolicy::Reject));
+    let request =
+        Request::new("mutation { submit(reading: { value: 9, metric: \"temperature\" }) }");
+    let response = service.handle_query(request).blocking_wait();
+
+    assert_eq!(error_code(&response), Some("OUT_OF_RANGE"));
+    assert_eq!(
+        response.errors[0].message,
+        "Value 9 is outside of the allowed range for metric \"temperature\" (10..=20)"
+    );
+}
+
+/// Test if values are checked with the tree configuration once the root chain broadcasts one.
+#[test]
+fn submit_with_updated_config_mutation() {
+    let mut service =
+        create_service_with_parameters(temperature_range_parameters(OutOfRangePolicy::Reject));
+
+    service.state.edit().tree_config.set(Some(TreeConfig {
+        value_ranges: vec![MetricRange {
+            metric: "temperature".to_owned(),
+            min: 0,
+            max: 10,
+        }],
+        out_of_range_policy: OutOfRangePolicy::Reject,
+    }));
+
+    let request =
+        Request::new("mutation { submit(reading: { value: 9, metric: \"temperature\" }) }");
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"submit": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test if values that don't fit in 64 bits are refused with an error code.
+#[test]
+fn submit_overflowing_value_mutation() {
+    let service = create_service();
+    let value = u128::from(u64::MAX) + 1;
+    let request = Request::new(format!(
+        "mutation {{ submit(reading: {{ value: \"{value}\" }}) }}"
+    ));
+    let response = service.handle_query(request).blocking_wait();
+
+    assert_eq!(error_code(&response), Some("OUT_OF_RANGE"));
+}
+
+/// Test if out-of-range values are accepted if they are quarantined by the application.
+#[test]
+fn submit_quarantined_mutation() {
+    let service =
+        create_service_with_parameters(temperature_range_parameters(OutOfRangePolicy::Quarantine));
+    let request =
+        Request::new("mutation { submit(reading: { value: 9, metric: \"temperature\" }) }");
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"submit": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test if metrics without a configured range are refused with an error code.
+#[test]
+fn submit_unknown_metric_mutation() {
+    let service =
+        create_service_with_parameters(temperature_range_parameters(OutOfRangePolicy::Reject));
+    let request = Request::new("mutation { submit(reading: { value: 15, metric: \"humidity\" }) }");
+    let response = service.handle_query(request).blocking_wait();
+
+    assert_eq!(error_code(&response), Some("UNKNOWN_METRIC"));
+}
+
+/// Test if readings from devices that are not registered are refused with an error code.
+#[proptest]
+fn submit_device_mutation(
+    device: AccountOwner,
+    unregistered_device: AccountOwner,
+    reward_chain: ChainId,
+) {
+    let mut service = create_service();
+
+    service
+        .state
+        .edit()
+        .devices
+        .insert(
+            &device,
+            Device {
+                reward_account: Account::chain(reward_chain),
+                contributions: 0,
+            },
+        )
+        .expect("Failed to register device");
+
+    let request = Request::new(format!(
+        "mutation {{ submit(reading: {{ value: 15, deviceId: \"{device}\" }}) }}"
+    ));
     let response = service.handle_query(request).blocking_wait();
     let expected = Response::new(Value::from_json(json!({"submit": true})).unwrap());
     assert_eq!(response, expected);
+
+    if unregistered_device != device {
+        let request = Request::new(format!(
+            "mutation {{ submit(reading: {{ value: 15, deviceId: \"{unregistered_device}\" }}) }}"
+        ));
+        let response = service.handle_query(request).blocking_wait();
+
+        assert_eq!(error_code(&response), Some("UNAUTHORIZED_DEVICE"));
+    }
 }
 
 /// Test creating a flush operation.
@@ -87,9 +1141,311 @@ fn flush_mutation() {
     assert_eq!(response, expected);
 }
 
+/// Test creating a register device operation.
+#[proptest]
+fn register_device_mutation(owner: AccountOwner, reward_chain: ChainId) {
+    let service = create_service();
+    let request = Request::new(format!(
+        "mutation {{ \
+            registerDevice(\
+                owner: \"{owner}\", \
+                rewardAccount: {{ chain_id: \"{reward_chain}\", owner: \"{owner}\" }}\
+            ) \
+        }}"
+    ));
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"registerDevice": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating a distribute rewards operation.
+#[proptest]
+fn distribute_rewards_mutation(#[strategy(0..=u128::from(u64::MAX))] attos: u128) {
+    let service = create_service();
+    let amount = Amount::from_attos(attos);
+    let request = Request::new(format!(
+        "mutation {{ distributeRewards(amount: \"{amount}\") }}"
+    ));
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"distributeRewards": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating operations to unstake and withdraw the stake from the parent chain.
+#[test]
+fn unstake_mutations() {
+    let service = create_service();
+    let request = Request::new("mutation { unstake withdrawStake }");
+    let response = service.handle_query(request).blocking_wait();
+    let expected =
+        Response::new(Value::from_json(json!({"unstake": true, "withdrawStake": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating the operations to move to a new parent chain and withdraw the stake from the
+/// old one.
+#[proptest]
+fn reparent_mutations(old_parent: ChainId, new_parent: ChainId) {
+    let service = create_service();
+    let request = Request::new(format!(
+        "mutation {{ \
+            reparent(parent: \"{new_parent}\") \
+            withdrawStakeFrom(parent: \"{old_parent}\") \
+        }}"
+    ));
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(
+        Value::from_json(json!({"reparent": true, "withdrawStakeFrom": true})).unwrap(),
+    );
+    assert_eq!(response, expected);
+}
+
+/// Test creating an operation to join the tree of a root chain.
+#[proptest]
+fn join_mutation(root: ChainId) {
+    let service = create_service();
+    let request = Request::new(format!(
+        "mutation {{ join(root: \"{root}\", aggregator: true) }}"
+    ));
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"join": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating an operation to move child chains to another aggregator chain.
+#[proptest]
+fn rebalance_mutation(target: ChainId) {
+    let service = create_service();
+    let request = Request::new(format!(
+        "mutation {{ \
+            toSibling: rebalance(target: \"{target}\") \
+            toNewChain: rebalance(balance: \"5\") \
+        }}"
+    ));
+    let response = service.handle_query(request).blocking_wait();
+    let expected =
+        Response::new(Value::from_json(json!({"toSibling": true, "toNewChain": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating an operation to open an edge chain for a new device.
+#[proptest]
+fn open_edge_chain_mutation(owner: AccountOwner, balance: u128) {
+    let service = create_service();
+    let request = Request::new(format!(
+        "mutation {{ openEdgeChain(owner: \"{owner}\", balance: \"{}\") }}",
+        Amount::from_attos(balance)
+    ));
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"openEdgeChain": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating an operation to broadcast a new tree configuration.
+#[test]
+fn update_config_mutation() {
+    let service = create_service();
+    let request = Request::new(
+        "mutation { updateConfig(config: { \
+            valueRanges: [{ metric: \"temperature\", min: 10, max: 20 }], \
+            outOfRangePolicy: QUARANTINE \
+        }) }",
+    );
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"updateConfig": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating an operation to set up the chain.
+#[proptest]
+fn initialize_mutation(parent: ChainId) {
+    let service = create_service();
+    let request = Request::new(format!(
+        "mutation {{ initialize(setup: {{ \
+            parent: \"{parent}\", \
+            role: EDGE, \
+            flushPolicy: {{ maxPendingContributions: 1 }} \
+        }}) }}"
+    ));
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"initialize": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating an operation to set the role of the chain.
+#[test]
+fn set_role_mutation() {
+    let service = create_service();
+    let request = Request::new("mutation { setRole(role: EDGE) }");
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"setRole": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating an operation to set the backup parent chains of the chain.
+#[proptest]
+fn set_backup_parents_mutation(first: ChainId, second: ChainId) {
+    let service = create_service();
+    let request = Request::new(format!(
+        "mutation {{ setBackupParents(parents: [\"{first}\", \"{second}\"]) }}"
+    ));
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"setBackupParents": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating an operation to report the values reduced by a reducer chain.
+#[test]
+fn report_shard_mutation() {
+    let service = create_service();
+    let request = Request::new("mutation { reportShard }");
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"reportShard": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating the operations to disconnect and retire a chain.
+#[test]
+fn retire_mutations() {
+    let service = create_service();
+    let request = Request::new("mutation { disconnect retire }");
+    let response = service.handle_query(request).blocking_wait();
+    let expected =
+        Response::new(Value::from_json(json!({"disconnect": true, "retire": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating a slash operation.
+#[proptest]
+fn slash_mutation(child: ChainId) {
+    let service = create_service();
+    let request = Request::new(format!(
+        "mutation {{ slash(child: \"{child}\", amount: \"1.5\") }}"
+    ));
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"slash": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating a close epoch operation.
+#[test]
+fn close_epoch_mutation() {
+    let service = create_service();
+    let request = Request::new("mutation { closeEpoch }");
+    let response = service.handle_query(request).blocking_wait();
+    let expected = Response::new(Value::from_json(json!({"closeEpoch": true})).unwrap());
+    assert_eq!(response, expected);
+}
+
+/// Test creating the operations to merge the flushes streams of child chains.
+#[proptest]
+fn stream_mutations(child: ChainId) {
+    let service = create_service();
+
+    for (mutation, field) in [
+        (
+            format!("subscribeToChild(child: \"{child}\")"),
+            "subscribeToChild",
+        ),
+        (
+            format!("unsubscribeFromChild(child: \"{child}\")"),
+            "unsubscribeFromChild",
+        ),
+        (
+            format!("processStreams(updates: [{{ chainId: \"{child}\", nextIndex: 3 }}])"),
+            "processStreams",
+        ),
+    ] {
+        let request = Request::new(format!("mutation {{ {mutation} }}"));
+        let response = service.handle_query(request).blocking_wait();
+        let expected = Response::new(Value::from_json(json!({ field: true })).unwrap());
+        assert_eq!(response, expected);
+    }
+}
+
+/// Test creating the operations to authorize and revoke applications.
+#[test]
+fn application_authorization_mutations() {
+    let service = create_service();
+    let application_id = ApplicationId::new(CryptoHash::test_hash("device manager"));
+
+    for (mutation, field) in [
+        (
+            format!(
+                "authorizeApplication(applicationId: \"{application_id}\", \
+                permissions: {{ submit: true, readAggregate: true }})"
+            ),
+            "authorizeApplication",
+        ),
+        (
+            format!("revokeApplication(applicationId: \"{application_id}\")"),
+            "revokeApplication",
+        ),
+    ] {
+        let request = Request::new(format!("mutation {{ {mutation} }}"));
+        let response = service.handle_query(request).blocking_wait();
+        let expected = Response::new(Value::from_json(json!({ field: true })).unwrap());
+        assert_eq!(response, expected);
+    }
+}
+
+/// Test creating the operations to configure and remove the consumer application.
+#[proptest]
+fn set_consumer_mutation(chain_id: ChainId) {
+    let service = create_service();
+    let application_id = ApplicationId::new(CryptoHash::test_hash("settlement"));
+
+    for consumer in [
+        format!("{{ applicationId: \"{application_id}\", chainId: \"{chain_id}\" }}"),
+        "null".to_owned(),
+    ] {
+        let request = Request::new(format!("mutation {{ setConsumer(consumer: {consumer}) }}"));
+        let response = service.handle_query(request).blocking_wait();
+        let expected = Response::new(Value::from_json(json!({"setConsumer": true})).unwrap());
+        assert_eq!(response, expected);
+    }
+}
+
+/// Creates a [`Rollup`] for the time bucket starting at `start` with a single merge of `value`.
+fn rollup(start: Timestamp, value: u64) -> Rollup {
+    Rollup {
+        start,
+        merges: 1,
+        merged_value: value,
+        min_value: value,
+        max_value: value,
+        last_value: value,
+    }
+}
+
+/// Creates [`DepinDemoParameters`] with an allowed range of `10..=20` for the `temperature`
+/// metric.
+fn temperature_range_parameters(out_of_range_policy: OutOfRangePolicy) -> DepinDemoParameters {
+    DepinDemoParameters {
+        value_ranges: BTreeMap::from([("temperature".to_owned(), ValueRange { min: 10, max: 20 })]),
+        out_of_range_policy,
+        ..DepinDemoParameters::default()
+    }
+}
+
+/// Returns the code in the extensions of the first error in the `response`, if there is one.
+fn error_code(response: &Response) -> Option<&str> {
+    let code = response.errors.first()?.extensions.as_ref()?.get("code")?;
+
+    match code {
+        Value::String(code) => Some(code),
+        _ => None,
+    }
+}
+
 /// Creates a [`DepinDemoService`] instance ready to be tested.
 fn create_service() -> DepinDemoService {
-    let runtime = ServiceRuntime::new();
+    create_service_with_parameters(DepinDemoParameters::default())
+}
+
+/// Creates a [`DepinDemoService`] instance configured with the `parameters`.
+fn create_service_with_parameters(parameters: DepinDemoParameters) -> DepinDemoService {
+    let runtime = ServiceRuntime::new().with_application_parameters(parameters);
     let state = DepinDemoState::load(runtime.root_view_storage_context())
         .blocking_wait()
         .expect("Failed to read from mock key value store");
@@ -0,0 +1,198 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Integration testing for the events published while values are aggregated.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::collections::BTreeSet;
+
+use depin_demo::{
+    AggregationEvent, AggregationMode, ChainSetup, DepinDemoAbi, DepinDemoParameters, Operation,
+    StreamUpdate,
+};
+use linera_chain::types::ConfirmedBlockCertificate;
+use linera_sdk::{bcs, test::TestValidator};
+
+/// Tests publishing events when values are flushed, merged and archived at the end of an epoch.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn aggregation_events_test() {
+    let parameters = DepinDemoParameters {
+        value_thresholds: BTreeSet::from([5, 100]),
+        ..DepinDemoParameters::default()
+    };
+    let (validator, application_id, root_chain) = TestValidator::with_current_application::<
+        DepinDemoAbi,
+        _,
+        _,
+    >(parameters, ChainSetup::default())
+    .await;
+
+    let edge_chain = validator.new_chain().await;
+    let edge_certificate = edge_chain
+        .add_block(|block| {
+            block
+                .with_operation(
+                    application_id,
+                    Operation::ConnectToParent {
+                        parent: root_chain.id(),
+                    },
+                )
+                .with_operation(
+                    application_id,
+                    Operation::Submit {
+                        metric: None,
+                        value: 3,
+                    },
+                )
+                .with_operation(
+                    application_id,
+                    Operation::Submit {
+                        metric: None,
+                        value: 4,
+                    },
+                )
+                .with_operation(application_id, Operation::Flush);
+        })
+        .await;
+
+    let edge_events = published_events(&edge_certificate);
+    let commitment = match edge_events.last() {
+        Some(AggregationEvent::FlushSent { commitment, .. }) => *commitment,
+        _ => panic!("Edge chain should publish its flush last"),
+    };
+
+    assert_eq!(
+        edge_events,
+        vec![
+            AggregationEvent::ThresholdCrossed {
+                threshold: 5,
+                value: 7,
+            },
+            AggregationEvent::FlushSent {
+                parent: Some(root_chain.id()),
+                epoch: 0,
+                value: 7,
+                contributions: 2,
+                commitment,
+            },
+        ]
+    );
+
+    let root_certificate = root_chain
+        .add_block(|block| {
+            block
+                .with_messages_from(&edge_certificate)
+                .with_operation(application_id, Operation::CloseEpoch);
+        })
+        .await;
+
+    assert_eq!(
+        published_events(&root_certificate),
+        vec![
+            AggregationEvent::ChildMerged {
+                child: edge_chain.id(),
+                epoch: 0,
+                value: 7,
+                contributions: 2,
+            },
+            AggregationEvent::ThresholdCrossed {
+                threshold: 5,
+                value: 7,
+            },
+            AggregationEvent::EpochClosed {
+                epoch: 0,
+                value: 7,
+                contributions: 2,
+            },
+        ]
+    );
+}
+
+/// Tests merging the flushes a child chain publishes to its stream on multiple chains.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn stream_aggregation_test() {
+    let parameters = DepinDemoParameters {
+        aggregation_mode: AggregationMode::Streams,
+        ..DepinDemoParameters::default()
+    };
+    let (validator, application_id, consumer_chain) = TestValidator::with_current_application::<
+        DepinDemoAbi,
+        _,
+        _,
+    >(parameters, ChainSetup::default())
+    .await;
+    let other_consumer_chain = validator.new_chain().await;
+
+    let edge_chain = validator.new_chain().await;
+    edge_chain
+        .add_block(|block| {
+            block
+                .with_operation(
+                    application_id,
+                    Operation::Submit {
+                        metric: None,
+                        value: 3,
+                    },
+                )
+                .with_operation(application_id, Operation::Flush)
+                .with_operation(
+                    application_id,
+                    Operation::Submit {
+                        metric: None,
+                        value: 4,
+                    },
+                )
+                .with_operation(application_id, Operation::Flush);
+        })
+        .await;
+
+    for chain in [&consumer_chain, &other_consumer_chain] {
+        chain
+            .add_block(|block| {
+                block
+                    .with_operation(
+                        application_id,
+                        Operation::SubscribeToChild {
+                            child: edge_chain.id(),
+                        },
+                    )
+                    .with_operation(
+                        application_id,
+                        Operation::ProcessStreams {
+                            updates: vec![StreamUpdate {
+                                chain_id: edge_chain.id(),
+                                next_index: 2,
+                            }],
+                        },
+                    );
+            })
+            .await;
+
+        let response = chain
+            .graphql_query(application_id, "query { value }")
+            .await
+            .response;
+
+        assert_eq!(response["value"].as_u64(), Some(7));
+    }
+}
+
+/// Returns the [`AggregationEvent`]s published in the block of a `certificate`, checking that
+/// each one was published to its stream.
+fn published_events(certificate: &ConfirmedBlockCertificate) -> Vec<AggregationEvent> {
+    certificate
+        .block()
+        .body
+        .events
+        .iter()
+        .flatten()
+        .map(|event| {
+            let value = bcs::from_bytes::<AggregationEvent>(&event.value)
+                .expect("Failed to deserialize published event");
+
+            assert_eq!(event.stream_id.stream_name, value.stream_name());
+            value
+        })
+        .collect()
+}
@@ -0,0 +1,254 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Integration testing for failing over to a backup parent chain when the parent chain stops
+//! acknowledging flushes.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use depin_demo::{ChainSetup, DepinDemoAbi, DepinDemoParameters, FlushPolicy, Operation};
+use linera_sdk::{
+    linera_base_types::{Account, AccountOwner, Amount, ChainId},
+    test::{Recipient, TestValidator},
+};
+
+/// Tests failing over to a backup parent chain, with the flushes merged by both parent chains
+/// counted only once by the root chain.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn fail_over_to_backup_parent_test() {
+    let parameters = DepinDemoParameters {
+        max_unacknowledged_flushes: 1,
+        ..DepinDemoParameters::default()
+    };
+    let (validator, application_id, root_chain) = TestValidator::with_current_application::<
+        DepinDemoAbi,
+        _,
+        _,
+    >(parameters, ChainSetup::default())
+    .await;
+
+    let primary_chain = validator.new_chain().await;
+    let backup_chain = validator.new_chain().await;
+
+    for chain in [&primary_chain, &backup_chain] {
+        chain
+            .add_block(|block| {
+                block.with_operation(
+                    application_id,
+                    Operation::ConnectToParent {
+                        parent: root_chain.id(),
+                    },
+                );
+            })
+            .await;
+    }
+
+    let edge_chain = validator.new_chain().await;
+    edge_chain
+        .add_block(|block| {
+            block
+                .with_operation(
+                    application_id,
+                    Operation::ConnectToParent {
+                        parent: primary_chain.id(),
+                    },
+                )
+                .with_operation(
+                    application_id,
+                    Operation::SetBackupParents {
+                        parents: vec![backup_chain.id()],
+                    },
+                );
+        })
+        .await;
+
+    for value in [3, 4] {
+        edge_chain
+            .add_block(|block| {
+                block
+                    .with_operation(
+                        application_id,
+                        Operation::Submit {
+                            metric: None,
+                            value,
+                        },
+                    )
+                    .with_operation(application_id, Operation::Flush);
+            })
+            .await;
+    }
+
+    // The primary parent chain only comes back after the edge chain failed over, and still merges
+    // the first flush.
+    for chain in [&backup_chain, &primary_chain] {
+        chain.handle_received_messages().await;
+        chain
+            .add_block(|block| {
+                block.with_operation(application_id, Operation::Flush);
+            })
+            .await;
+    }
+
+    root_chain.handle_received_messages().await;
+    edge_chain.handle_received_messages().await;
+
+    let response = root_chain
+        .graphql_query(application_id, "query { value }")
+        .await
+        .response;
+
+    assert_eq!(response["value"].as_u64(), Some(7));
+
+    let response = edge_chain
+        .graphql_query(
+            application_id,
+            "query { parent backupParents unacknowledgedFlushes { sequence } }",
+        )
+        .await
+        .response;
+
+    assert_eq!(
+        response["parent"].as_str(),
+        Some(backup_chain.id().to_string().as_str())
+    );
+    assert_eq!(
+        response["backupParents"][0].as_str(),
+        Some(primary_chain.id().to_string().as_str())
+    );
+    assert_eq!(
+        response["unacknowledgedFlushes"].as_array().map(Vec::len),
+        Some(0)
+    );
+}
+
+/// Tests an aggregator chain failing over while merging a flush from its child chain, with the
+/// registration stake reserved when setting its backup parent chains reaching the backup parent
+/// chain.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn staked_fail_over_test() {
+    let parameters = DepinDemoParameters {
+        registration_stake: Amount::ONE,
+        max_unacknowledged_flushes: 1,
+        ..DepinDemoParameters::default()
+    };
+    let (validator, application_id, root_chain) = TestValidator::with_current_application::<
+        DepinDemoAbi,
+        _,
+        _,
+    >(parameters, ChainSetup::default())
+    .await;
+    let application_account = AccountOwner::from(application_id);
+
+    let primary_chain = validator.new_chain().await;
+    let backup_chain = validator.new_chain().await;
+    let aggregator_chain = validator.new_chain().await;
+    let edge_chain = validator.new_chain().await;
+
+    let admin_chain = validator.get_chain(&ChainId::root(0));
+    admin_chain
+        .add_block(|block| {
+            for chain in [
+                &primary_chain,
+                &backup_chain,
+                &aggregator_chain,
+                &edge_chain,
+            ] {
+                block.with_native_token_transfer(
+                    AccountOwner::CHAIN,
+                    Recipient::Account(Account::chain(chain.id())),
+                    Amount::from_tokens(5),
+                );
+            }
+        })
+        .await;
+
+    for chain in [&primary_chain, &backup_chain] {
+        chain.handle_received_messages().await;
+        chain
+            .add_block(|block| {
+                block.with_operation(
+                    application_id,
+                    Operation::ConnectToParent {
+                        parent: root_chain.id(),
+                    },
+                );
+            })
+            .await;
+    }
+
+    aggregator_chain.handle_received_messages().await;
+    aggregator_chain
+        .add_block(|block| {
+            block
+                .with_operation(
+                    application_id,
+                    Operation::Initialize {
+                        setup: ChainSetup {
+                            parent: Some(primary_chain.id()),
+                            flush_policy: FlushPolicy {
+                                max_pending_contributions: 1,
+                            },
+                            ..ChainSetup::default()
+                        },
+                    },
+                )
+                .with_operation(
+                    application_id,
+                    Operation::SetBackupParents {
+                        parents: vec![backup_chain.id()],
+                    },
+                );
+        })
+        .await;
+
+    edge_chain.handle_received_messages().await;
+    edge_chain
+        .add_block(|block| {
+            block.with_operation(
+                application_id,
+                Operation::ConnectToParent {
+                    parent: aggregator_chain.id(),
+                },
+            );
+        })
+        .await;
+
+    // The primary parent chain never acknowledges the first flush, so the aggregator chain fails
+    // over while merging the second flush of the edge chain.
+    for value in [3, 4] {
+        edge_chain
+            .add_block(|block| {
+                block
+                    .with_operation(
+                        application_id,
+                        Operation::Submit {
+                            metric: None,
+                            value,
+                        },
+                    )
+                    .with_operation(application_id, Operation::Flush);
+            })
+            .await;
+        aggregator_chain.handle_received_messages().await;
+    }
+
+    backup_chain.handle_received_messages().await;
+
+    let response = aggregator_chain
+        .graphql_query(application_id, "query { parent }")
+        .await
+        .response;
+
+    assert_eq!(
+        response["parent"].as_str(),
+        Some(backup_chain.id().to_string().as_str())
+    );
+    assert_eq!(
+        aggregator_chain.owner_balance(&application_account).await,
+        Some(Amount::ONE)
+    );
+    assert_eq!(
+        backup_chain.owner_balance(&application_account).await,
+        Some(Amount::ONE)
+    );
+}
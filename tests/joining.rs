@@ -0,0 +1,115 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Integration testing for joining the tree of a root chain that requires a registration stake.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use depin_demo::{ChainSetup, DepinDemoAbi, DepinDemoParameters, Operation};
+use linera_sdk::{
+    linera_base_types::{Account, AccountOwner, Amount, ChainId},
+    test::{Recipient, TestValidator},
+};
+
+/// Tests joining the tree first as an aggregator chain and then as an edge chain assigned to it,
+/// with the stake of each chain reaching its parent chain.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn staked_join_test() {
+    let parameters = DepinDemoParameters {
+        fan_out: 1,
+        registration_stake: Amount::ONE,
+        ..DepinDemoParameters::default()
+    };
+    let (validator, application_id, root_chain) = TestValidator::with_current_application::<
+        DepinDemoAbi,
+        _,
+        _,
+    >(parameters, ChainSetup::default())
+    .await;
+    let application_account = AccountOwner::from(application_id);
+
+    let aggregator_chain = validator.new_chain().await;
+    let edge_chain = validator.new_chain().await;
+
+    let admin_chain = validator.get_chain(&ChainId::root(0));
+    admin_chain
+        .add_block(|block| {
+            for chain in [&aggregator_chain, &edge_chain] {
+                block.with_native_token_transfer(
+                    AccountOwner::CHAIN,
+                    Recipient::Account(Account::chain(chain.id())),
+                    Amount::from_tokens(5),
+                );
+            }
+        })
+        .await;
+
+    for (chain, aggregator) in [(&aggregator_chain, true), (&edge_chain, false)] {
+        chain.handle_received_messages().await;
+        chain
+            .add_block(|block| {
+                block.with_operation(
+                    application_id,
+                    Operation::Join {
+                        root: root_chain.id(),
+                        aggregator,
+                    },
+                );
+            })
+            .await;
+
+        root_chain.handle_received_messages().await;
+        chain.handle_received_messages().await;
+    }
+
+    edge_chain
+        .add_block(|block| {
+            block
+                .with_operation(
+                    application_id,
+                    Operation::Submit {
+                        metric: None,
+                        value: 7,
+                    },
+                )
+                .with_operation(application_id, Operation::Flush);
+        })
+        .await;
+
+    aggregator_chain.handle_received_messages().await;
+    aggregator_chain
+        .add_block(|block| {
+            block.with_operation(application_id, Operation::Flush);
+        })
+        .await;
+    root_chain.handle_received_messages().await;
+
+    let response = root_chain
+        .graphql_query(application_id, "query { value children { chainId } }")
+        .await
+        .response;
+
+    assert_eq!(response["value"].as_u64(), Some(7));
+    assert_eq!(
+        response["children"][0]["chainId"].as_str(),
+        Some(aggregator_chain.id().to_string().as_str())
+    );
+
+    let response = aggregator_chain
+        .graphql_query(application_id, "query { children { chainId } }")
+        .await
+        .response;
+
+    assert_eq!(
+        response["children"][0]["chainId"].as_str(),
+        Some(edge_chain.id().to_string().as_str())
+    );
+    assert_eq!(
+        aggregator_chain.owner_balance(&application_account).await,
+        Some(Amount::ONE)
+    );
+    assert_eq!(
+        root_chain.owner_balance(&application_account).await,
+        Some(Amount::ONE)
+    );
+}
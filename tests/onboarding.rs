@@ -0,0 +1,173 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Integration testing for onboarding devices on edge chains opened by their aggregator chain.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use depin_demo::{ChainSetup, DepinDemoAbi, DepinDemoParameters, Operation};
+use linera_sdk::{
+    linera_base_types::{
+        Account, AccountOwner, AccountSecretKey, Amount, ChainDescription, ChainId, MessageId,
+    },
+    test::{ActiveChain, Recipient, TestValidator},
+};
+
+/// Tests opening an edge chain for a device, which is already connected to the aggregator chain
+/// when the device submits its first value.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn open_edge_chain_test() {
+    let parameters = DepinDemoParameters::default();
+    let (validator, application_id, aggregator_chain) = TestValidator::with_current_application::<
+        DepinDemoAbi,
+        _,
+        _,
+    >(parameters, ChainSetup::default())
+    .await;
+    let device_key_pair = AccountSecretKey::generate();
+
+    let certificate = aggregator_chain
+        .add_block(|block| {
+            block.with_operation(
+                application_id,
+                Operation::OpenEdgeChain {
+                    owner: device_key_pair.public().into(),
+                    balance: Amount::ZERO,
+                },
+            );
+        })
+        .await;
+    let block = certificate.inner().block();
+    let edge_chain = ActiveChain::new(
+        device_key_pair,
+        ChainDescription::Child(MessageId {
+            chain_id: block.header.chain_id,
+            height: block.header.height,
+            index: 0,
+        }),
+        validator.clone(),
+    );
+    validator.add_chain(edge_chain.clone());
+
+    edge_chain.handle_received_messages().await;
+    edge_chain
+        .add_block(|block| {
+            block
+                .with_operation(
+                    application_id,
+                    Operation::Submit {
+                        metric: None,
+                        value: 7,
+                    },
+                )
+                .with_operation(application_id, Operation::Flush);
+        })
+        .await;
+
+    aggregator_chain.handle_received_messages().await;
+
+    let response = aggregator_chain
+        .graphql_query(
+            application_id,
+            "query { value children { chainId } openedChains { chainId } }",
+        )
+        .await
+        .response;
+    let edge_chain_id = edge_chain.id().to_string();
+
+    assert_eq!(response["value"].as_u64(), Some(7));
+    assert_eq!(
+        response["children"][0]["chainId"].as_str(),
+        Some(edge_chain_id.as_str())
+    );
+    assert_eq!(
+        response["openedChains"][0]["chainId"].as_str(),
+        Some(edge_chain_id.as_str())
+    );
+}
+
+/// Tests opening an edge chain for a device when the aggregator chain requires a registration
+/// stake, which the aggregator chain pays from the balance the edge chain is funded with.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn staked_open_edge_chain_test() {
+    let parameters = DepinDemoParameters {
+        registration_stake: Amount::ONE,
+        ..DepinDemoParameters::default()
+    };
+    let (validator, application_id, aggregator_chain) = TestValidator::with_current_application::<
+        DepinDemoAbi,
+        _,
+        _,
+    >(parameters, ChainSetup::default())
+    .await;
+    let application_account = AccountOwner::from(application_id);
+    let device_key_pair = AccountSecretKey::generate();
+
+    let admin_chain = validator.get_chain(&ChainId::root(0));
+    admin_chain
+        .add_block(|block| {
+            block.with_native_token_transfer(
+                AccountOwner::CHAIN,
+                Recipient::Account(Account::chain(aggregator_chain.id())),
+                Amount::from_tokens(10),
+            );
+        })
+        .await;
+    aggregator_chain.handle_received_messages().await;
+
+    let certificate = aggregator_chain
+        .add_block(|block| {
+            block.with_operation(
+                application_id,
+                Operation::OpenEdgeChain {
+                    owner: device_key_pair.public().into(),
+                    balance: Amount::from_tokens(5),
+                },
+            );
+        })
+        .await;
+    let block = certificate.inner().block();
+    let edge_chain = ActiveChain::new(
+        device_key_pair,
+        ChainDescription::Child(MessageId {
+            chain_id: block.header.chain_id,
+            height: block.header.height,
+            index: 1,
+        }),
+        validator.clone(),
+    );
+    validator.add_chain(edge_chain.clone());
+
+    edge_chain.handle_received_messages().await;
+    edge_chain
+        .add_block(|block| {
+            block
+                .with_operation(
+                    application_id,
+                    Operation::Submit {
+                        metric: None,
+                        value: 7,
+                    },
+                )
+                .with_operation(application_id, Operation::Flush);
+        })
+        .await;
+
+    aggregator_chain.handle_received_messages().await;
+
+    let response = aggregator_chain
+        .graphql_query(application_id, "query { value children { chainId } }")
+        .await
+        .response;
+
+    assert_eq!(response["value"].as_u64(), Some(7));
+    assert_eq!(
+        response["children"][0]["chainId"].as_str(),
+        Some(edge_chain.id().to_string().as_str())
+    );
+    assert_eq!(edge_chain.chain_balance().await, Amount::from_tokens(4));
+    assert_eq!(
+        aggregator_chain.owner_balance(&application_account).await,
+        Some(Amount::ONE)
+    );
+}
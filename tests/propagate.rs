@@ -5,18 +5,26 @@
 
 #![cfg(not(target_arch = "wasm32"))]
 
-use depin_demo::Operation;
+use depin_demo::{ChainSetup, DepinDemoAbi, DepinDemoParameters, FlushPolicy, NodeRole, Operation};
 use futures::{stream, FutureExt, StreamExt, TryStreamExt};
 use linera_sdk::test::TestValidator;
 
 /// Tests producing values from multiple chains and propagating to the root.
+///
+/// The chains are set up in the same block as their first operation, and flush automatically once
+/// all their values are aggregated.
 #[test_log::test(tokio::test(flavor = "multi_thread"))]
 async fn propagation_test() -> anyhow::Result<()> {
     const BRANCH_CHAINS: u64 = 5;
     const EDGE_CHAINS_PER_BRANCH: u64 = 10;
 
+    let parameters = DepinDemoParameters::default();
+    let root_setup = ChainSetup {
+        role: Some(NodeRole::Root),
+        ..ChainSetup::default()
+    };
     let (validator, application_id, root_chain) =
-        TestValidator::with_current_application::<depin_demo::DepinDemoAbi, _, _>((), ()).await;
+        TestValidator::with_current_application::<DepinDemoAbi, _, _>(parameters, root_setup).await;
     let root_chain_id = root_chain.id();
 
     stream::iter(0..BRANCH_CHAINS)
@@ -30,8 +38,14 @@ async fn propagation_test() -> anyhow::Result<()> {
                     .add_block(|block| {
                         block.with_operation(
                             application_id,
-                            Operation::ConnectToParent {
-                                parent: root_chain_id,
+                            Operation::Initialize {
+                                setup: ChainSetup {
+                                    parent: Some(root_chain_id),
+                                    role: Some(NodeRole::Aggregator),
+                                    flush_policy: FlushPolicy {
+                                        max_pending_contributions: EDGE_CHAINS_PER_BRANCH,
+                                    },
+                                },
                             },
                         );
                     })
@@ -45,28 +59,27 @@ async fn propagation_test() -> anyhow::Result<()> {
 
                             edge_chain
                                 .add_block(|block| {
-                                    block.with_operation(
-                                        application_id,
-                                        Operation::ConnectToParent {
-                                            parent: branch_chain_id,
-                                        },
-                                    );
-                                })
-                                .await;
-                            edge_chain
-                                .add_block(|block| {
-                                    block.with_operation(
-                                        application_id,
-                                        Operation::Submit {
-                                            value: EDGE_CHAINS_PER_BRANCH * branch_index
-                                                + edge_index,
-                                        },
-                                    );
-                                })
-                                .await;
-                            edge_chain
-                                .add_block(|block| {
-                                    block.with_operation(application_id, Operation::Flush);
+                                    block
+                                        .with_operation(
+                                            application_id,
+                                            Operation::Initialize {
+                                                setup: ChainSetup {
+                                                    parent: Some(branch_chain_id),
+                                                    role: Some(NodeRole::Edge),
+                                                    flush_policy: FlushPolicy {
+                                                        max_pending_contributions: 1,
+                                                    },
+                                                },
+                                            },
+                                        )
+                                        .with_operation(
+                                            application_id,
+                                            Operation::Submit {
+                                                metric: None,
+                                                value: EDGE_CHAINS_PER_BRANCH * branch_index
+                                                    + edge_index,
+                                            },
+                                        );
                                 })
                                 .await;
                         })
@@ -75,11 +88,6 @@ async fn propagation_test() -> anyhow::Result<()> {
                     .await?;
 
                 branch_chain.handle_received_messages().await;
-                branch_chain
-                    .add_block(|block| {
-                        block.with_operation(application_id, Operation::Flush);
-                    })
-                    .await;
 
                 Ok::<_, anyhow::Error>(())
             })
@@ -0,0 +1,139 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Integration testing for moving child chains away from an aggregator chain over its fan-in
+//! budget.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use depin_demo::{ChainSetup, DepinDemoAbi, DepinDemoParameters, Operation};
+use linera_sdk::{
+    linera_base_types::{Amount, ChainDescription, ChainId, MessageId},
+    test::{ActiveChain, TestValidator},
+};
+
+/// Tests moving a child chain to a newly opened aggregator chain, without losing any values.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn rebalance_to_new_chain_test() {
+    let parameters = DepinDemoParameters {
+        fan_in_budget: 2,
+        ..DepinDemoParameters::default()
+    };
+    let (validator, application_id, root_chain) = TestValidator::with_current_application::<
+        DepinDemoAbi,
+        _,
+        _,
+    >(parameters, ChainSetup::default())
+    .await;
+
+    for value in 1..=3 {
+        let edge_chain = validator.new_chain().await;
+
+        edge_chain
+            .add_block(|block| {
+                block
+                    .with_operation(
+                        application_id,
+                        Operation::ConnectToParent {
+                            parent: root_chain.id(),
+                        },
+                    )
+                    .with_operation(
+                        application_id,
+                        Operation::Submit {
+                            metric: None,
+                            value,
+                        },
+                    )
+                    .with_operation(application_id, Operation::Flush);
+            })
+            .await;
+    }
+
+    root_chain.handle_received_messages().await;
+
+    let certificate = root_chain
+        .add_block(|block| {
+            block.with_operation(
+                application_id,
+                Operation::Rebalance {
+                    target: None,
+                    balance: Amount::ZERO,
+                },
+            );
+        })
+        .await;
+    let block = certificate.inner().block();
+    let new_chain = ActiveChain::new(
+        root_chain.key_pair().copy(),
+        ChainDescription::Child(MessageId {
+            chain_id: block.header.chain_id,
+            height: block.header.height,
+            index: 0,
+        }),
+        validator.clone(),
+    );
+    validator.add_chain(new_chain.clone());
+
+    new_chain.handle_received_messages().await;
+
+    let response = root_chain
+        .graphql_query(
+            application_id,
+            "query { movingChildren { chainId parent } }",
+        )
+        .await
+        .response;
+    let moving_children = response["movingChildren"]
+        .as_array()
+        .expect("Moving child chains should be a list");
+
+    assert_eq!(moving_children.len(), 1);
+    assert_eq!(
+        moving_children[0]["parent"].as_str(),
+        Some(new_chain.id().to_string().as_str())
+    );
+
+    let moved_chain_id = moving_children[0]["chainId"]
+        .as_str()
+        .expect("Moving child chain should have an ID")
+        .parse::<ChainId>()
+        .expect("Failed to parse moving child chain ID");
+    let moved_chain = validator.get_chain(&moved_chain_id);
+
+    moved_chain.handle_received_messages().await;
+    moved_chain
+        .add_block(|block| {
+            block
+                .with_operation(
+                    application_id,
+                    Operation::Submit {
+                        metric: None,
+                        value: 10,
+                    },
+                )
+                .with_operation(application_id, Operation::Flush);
+        })
+        .await;
+
+    new_chain.handle_received_messages().await;
+    new_chain
+        .add_block(|block| {
+            block.with_operation(application_id, Operation::Flush);
+        })
+        .await;
+
+    root_chain.handle_received_messages().await;
+
+    let response = root_chain
+        .graphql_query(
+            application_id,
+            "query { value children { chainId } movingChildren { chainId } }",
+        )
+        .await
+        .response;
+
+    assert_eq!(response["value"].as_u64(), Some(16));
+    assert_eq!(response["children"].as_array().map(Vec::len), Some(3));
+    assert_eq!(response["movingChildren"].as_array().map(Vec::len), Some(0));
+}
@@ -0,0 +1,125 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Integration testing for the distribution of rewards to devices.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use depin_demo::{ChainSetup, DepinDemoAbi, DepinDemoParameters, Operation};
+use linera_sdk::{
+    linera_base_types::{Account, AccountOwner, Amount, ChainId},
+    test::{Recipient, TestValidator},
+};
+
+/// Tests distributing rewards from the root chain to the devices on the edge chains.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn reward_distribution_test() {
+    let parameters = DepinDemoParameters::default();
+    let (validator, application_id, root_chain) = TestValidator::with_current_application::<
+        DepinDemoAbi,
+        _,
+        _,
+    >(parameters, ChainSetup::default())
+    .await;
+    let application_account = AccountOwner::from(application_id);
+
+    let branch_chain = validator.new_chain().await;
+    branch_chain
+        .add_block(|block| {
+            block.with_operation(
+                application_id,
+                Operation::ConnectToParent {
+                    parent: root_chain.id(),
+                },
+            );
+        })
+        .await;
+
+    let mut reward_chains = Vec::new();
+
+    for submissions in [3, 1] {
+        let edge_chain = validator.new_chain().await;
+        let reward_chain = validator.new_chain().await;
+        let device_owner = AccountOwner::from(edge_chain.public_key());
+
+        edge_chain
+            .add_block(|block| {
+                block
+                    .with_operation(
+                        application_id,
+                        Operation::ConnectToParent {
+                            parent: branch_chain.id(),
+                        },
+                    )
+                    .with_operation(
+                        application_id,
+                        Operation::RegisterDevice {
+                            owner: device_owner,
+                            reward_account: Account::chain(reward_chain.id()),
+                        },
+                    );
+
+                for value in 0..submissions {
+                    block.with_operation(
+                        application_id,
+                        Operation::Submit {
+                            metric: None,
+                            value,
+                        },
+                    );
+                }
+
+                block.with_operation(application_id, Operation::Flush);
+            })
+            .await;
+
+        reward_chains.push((edge_chain, reward_chain));
+    }
+
+    branch_chain.handle_received_messages().await;
+    branch_chain
+        .add_block(|block| {
+            block.with_operation(application_id, Operation::Flush);
+        })
+        .await;
+    root_chain.handle_received_messages().await;
+
+    let admin_chain = validator.get_chain(&ChainId::root(0));
+    admin_chain
+        .add_block(|block| {
+            block.with_native_token_transfer(
+                AccountOwner::CHAIN,
+                Recipient::Account(Account::new(root_chain.id(), application_account)),
+                Amount::from_tokens(8),
+            );
+        })
+        .await;
+    root_chain.handle_received_messages().await;
+
+    root_chain
+        .add_block(|block| {
+            block.with_operation(
+                application_id,
+                Operation::DistributeRewards {
+                    amount: Amount::from_tokens(8),
+                },
+            );
+        })
+        .await;
+
+    branch_chain.handle_received_messages().await;
+
+    for (edge_chain, reward_chain) in &reward_chains {
+        edge_chain.handle_received_messages().await;
+        reward_chain.handle_received_messages().await;
+    }
+
+    assert_eq!(
+        reward_chains[0].1.chain_balance().await,
+        Amount::from_tokens(6)
+    );
+    assert_eq!(
+        reward_chains[1].1.chain_balance().await,
+        Amount::from_tokens(2)
+    );
+}
@@ -0,0 +1,99 @@
+// Copyright (c) Zefchain Labs, Inc.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Integration testing for sharding the aggregation of metrics between reducer chains.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+use depin_demo::{AggregationMode, ChainSetup, DepinDemoAbi, DepinDemoParameters, Operation};
+use linera_sdk::test::TestValidator;
+
+/// Tests reducing each metric on the reducer chain it is assigned to, and combining the reports
+/// of all the reducer chains on the chain that created the application.
+#[test_log::test(tokio::test(flavor = "multi_thread"))]
+async fn sharded_reduction_test() {
+    let (validator, module_id) =
+        TestValidator::with_current_module::<DepinDemoAbi, DepinDemoParameters, ChainSetup>().await;
+    let reducer_chains = [validator.new_chain().await, validator.new_chain().await];
+    let parameters = DepinDemoParameters {
+        aggregation_mode: AggregationMode::Sharded,
+        reducer_chains: reducer_chains.iter().map(|chain| chain.id()).collect(),
+        ..DepinDemoParameters::default()
+    };
+    let mut creator_chain = validator.new_chain().await;
+    let application_id = creator_chain
+        .create_application(module_id, parameters, ChainSetup::default(), vec![])
+        .await;
+
+    for values in [
+        [("temperature", 20), ("humidity", 40)],
+        [("temperature", 22), ("co2", 400)],
+    ] {
+        let edge_chain = validator.new_chain().await;
+
+        edge_chain
+            .add_block(|block| {
+                for (metric, value) in values {
+                    block.with_operation(
+                        application_id,
+                        Operation::Submit {
+                            metric: Some(metric.to_owned()),
+                            value,
+                        },
+                    );
+                }
+
+                block.with_operation(application_id, Operation::Flush);
+            })
+            .await;
+    }
+
+    for chain in &reducer_chains {
+        chain.handle_received_messages().await;
+        chain
+            .add_block(|block| {
+                block.with_operation(application_id, Operation::ReportShard);
+            })
+            .await;
+    }
+
+    creator_chain.handle_received_messages().await;
+
+    let response = creator_chain
+        .graphql_query(
+            application_id,
+            "query { combinedMetricValues { metric value contributions } reducerChain(metric: \
+            \"temperature\") }",
+        )
+        .await
+        .response;
+
+    assert_eq!(
+        response["combinedMetricValues"],
+        serde_json::json!([
+            { "metric": "co2", "value": 400, "contributions": 1 },
+            { "metric": "humidity", "value": 40, "contributions": 1 },
+            { "metric": "temperature", "value": 42, "contributions": 2 },
+        ])
+    );
+
+    let temperature_reducer = validator.get_chain(
+        &response["reducerChain"]
+            .as_str()
+            .expect("Temperature should be assigned to a reducer chain")
+            .parse()
+            .expect("Failed to parse reducer chain ID"),
+    );
+    let response = temperature_reducer
+        .graphql_query(
+            application_id,
+            "query { reducedMetricValues { metric value } }",
+        )
+        .await
+        .response;
+    let reduced_values = response["reducedMetricValues"]
+        .as_array()
+        .expect("Reduced metric values should be a list");
+
+    assert!(reduced_values.contains(&serde_json::json!({ "metric": "temperature", "value": 42 })));
+}